@@ -1,5 +1,6 @@
 fn main() {
     cargo_emit::rerun_if_changed!(
-        "src/shaders/*"
+        "src/shaders/*",
+        "compute_testing/shaders/*"
     );
 }
\ No newline at end of file
@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::Path;
+
+use image::ImageBuffer;
+use image::Rgba;
+use image::RgbaImage;
+
+#[cfg(test)]
+mod tests;
+
+// How far a rendered image may drift from its golden before the check fails
+#[derive(Copy, Clone, Debug)]
+pub struct Tolerance {
+    // Largest per-channel difference that still counts as the same pixel
+    pub channel: u8,
+    // Fraction of pixels allowed to differ by more than `channel`
+    pub differing_fraction: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Tolerance {
+        return Tolerance {
+            channel: 2,
+            differing_fraction: 0.001
+        };
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DiffStats {
+    pub pixel_count: usize,
+    // Pixels with any channel differing by more than the tolerance
+    pub differing_pixels: usize,
+    pub max_difference: u8,
+    // Mean over all channels of all pixels
+    pub mean_difference: f64,
+}
+
+impl DiffStats {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        return self.differing_pixels as f64 <= self.pixel_count as f64 * tolerance.differing_fraction;
+    }
+}
+
+pub enum GoldenResult {
+    Passed(DiffStats),
+    Failed(DiffStats),
+    Blessed,
+    // There is no golden to compare against yet
+    Missing,
+}
+
+// Compare two images of the same size. The diff image shows pixels over tolerance in red on top of
+// a dimmed copy of the golden.
+pub fn compare(actual: &RgbaImage, golden: &RgbaImage, tolerance: &Tolerance) -> Result<(DiffStats, RgbaImage), &'static str> {
+    if actual.dimensions() != golden.dimensions() {
+        return Err("Rendered image and golden have different sizes.");
+    }
+
+    let mut stats = DiffStats {
+        pixel_count: (actual.width() * actual.height()) as usize,
+        ..DiffStats::default()
+    };
+    let mut total_difference = 0u64;
+    let mut diff = ImageBuffer::new(actual.width(), actual.height());
+    for ((actual_pixel, golden_pixel), diff_pixel) in actual.pixels().zip(golden.pixels()).zip(diff.pixels_mut()) {
+        let difference = actual_pixel.0.iter()
+            .zip(golden_pixel.0.iter())
+            .map(|(a, b)| (*a as i16 - *b as i16).abs() as u8)
+            .fold(0, |max, x| {
+                total_difference += x as u64;
+                return max.max(x);
+            });
+
+        stats.max_difference = stats.max_difference.max(difference);
+        *diff_pixel = if difference > tolerance.channel {
+            stats.differing_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (golden_pixel[0] as u32 + golden_pixel[1] as u32 + golden_pixel[2] as u32) / 12;
+            Rgba([luma as u8, luma as u8, luma as u8, 255])
+        };
+    }
+    stats.mean_difference = total_difference as f64 / (stats.pixel_count * 4).max(1) as f64;
+
+    return Ok((stats, diff));
+}
+
+// Check a rendered image against `<golden_dir>/<name>.png`. On failure the rendered image and diff
+// are written to `<output_dir>/<name>.actual.png` and `<name>.diff.png`. Blessing overwrites the
+// golden with the rendered image instead.
+pub fn check<P: AsRef<Path>, Q: AsRef<Path>>(
+    name: &str,
+    actual: &RgbaImage,
+    golden_dir: P,
+    output_dir: Q,
+    tolerance: &Tolerance,
+    bless: bool
+) -> Result<GoldenResult, &'static str> {
+    let golden_path = golden_dir.as_ref().join(format!("{}.png", name));
+    if bless {
+        actual.save(&golden_path).map_err(|_| "Failed to save golden")?;
+        return Ok(GoldenResult::Blessed);
+    }
+
+    if !golden_path.exists() {
+        return Ok(GoldenResult::Missing);
+    }
+    let golden = image::open(&golden_path)
+        .map_err(|_| "Failed to load golden")?
+        .to_rgba();
+    let (stats, diff) = compare(actual, &golden, tolerance)?;
+    if stats.passes(tolerance) {
+        return Ok(GoldenResult::Passed(stats));
+    }
+
+    let output_dir = output_dir.as_ref();
+    fs::create_dir_all(output_dir).map_err(|_| "Failed to create golden output directory")?;
+    actual.save(output_dir.join(format!("{}.actual.png", name))).map_err(|_| "Failed to save rendered image")?;
+    diff.save(output_dir.join(format!("{}.diff.png", name))).map_err(|_| "Failed to save diff image")?;
+    return Ok(GoldenResult::Failed(stats));
+}
@@ -0,0 +1,78 @@
+use image::Rgba;
+use image::RgbaImage;
+
+use super::*;
+
+fn solid(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+    return RgbaImage::from_pixel(width, height, Rgba(color));
+}
+
+#[test]
+fn compare_exact_match() {
+    let golden = solid(4, 4, [10, 20, 30, 255]);
+    let (stats, diff) = compare(&golden.clone(), &golden, &Tolerance::default()).unwrap();
+    assert_eq!(stats.pixel_count, 16);
+    assert_eq!(stats.differing_pixels, 0);
+    assert_eq!(stats.max_difference, 0);
+    assert_eq!(stats.mean_difference, 0.0);
+    assert!(stats.passes(&Tolerance::default()));
+    // Matching pixels are a dimmed gray copy of the golden
+    assert_eq!(*diff.get_pixel(0, 0), Rgba([5, 5, 5, 255]));
+}
+
+#[test]
+fn compare_within_tolerance() {
+    let golden = solid(10, 10, [100, 100, 100, 255]);
+    let mut actual = solid(10, 10, [102, 99, 100, 255]);
+    // One pixel in a hundred may be off by any amount
+    actual.put_pixel(3, 4, Rgba([0, 0, 0, 255]));
+    let tolerance = Tolerance {
+        channel: 2,
+        differing_fraction: 0.01
+    };
+
+    let (stats, diff) = compare(&actual, &golden, &tolerance).unwrap();
+    assert_eq!(stats.differing_pixels, 1);
+    assert_eq!(stats.max_difference, 100);
+    assert!(stats.passes(&tolerance));
+    assert_eq!(*diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+    assert_ne!(*diff.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+fn compare_over_tolerance() {
+    let golden = solid(10, 10, [100, 100, 100, 255]);
+    let mut actual = golden.clone();
+    actual.put_pixel(0, 0, Rgba([103, 100, 100, 255]));
+    actual.put_pixel(1, 0, Rgba([100, 100, 100, 0]));
+    let tolerance = Tolerance {
+        channel: 2,
+        differing_fraction: 0.01
+    };
+
+    let (stats, _) = compare(&actual, &golden, &tolerance).unwrap();
+    assert_eq!(stats.differing_pixels, 2);
+    assert_eq!(stats.max_difference, 255);
+    assert!(!stats.passes(&tolerance));
+}
+
+#[test]
+fn compare_rejects_size_mismatch() {
+    let result = compare(&solid(4, 4, [0; 4]), &solid(4, 5, [0; 4]), &Tolerance::default());
+    assert_eq!(result.err(), Some("Rendered image and golden have different sizes."));
+}
+
+#[test]
+fn check_reports_missing_golden() {
+    let dir = std::env::temp_dir().join(format!("sekirbo_golden_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let image = solid(2, 2, [1, 2, 3, 255]);
+
+    let result = check("missing", &image, &dir, &dir, &Tolerance::default(), false).unwrap();
+    assert!(matches!(result, GoldenResult::Missing));
+
+    // Once blessed, the same image passes
+    assert!(matches!(check("missing", &image, &dir, &dir, &Tolerance::default(), true).unwrap(), GoldenResult::Blessed));
+    assert!(matches!(check("missing", &image, &dir, &dir, &Tolerance::default(), false).unwrap(), GoldenResult::Passed(_)));
+    fs::remove_dir_all(&dir).unwrap();
+}
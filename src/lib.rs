@@ -0,0 +1,4 @@
+pub mod golden;
+pub mod logger;
+pub mod rendering;
+pub mod scenes;
@@ -0,0 +1,51 @@
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+
+use std::str::FromStr;
+
+// Environment variable holding the max log level, e.g. SEKIRBO_LOG=debug
+pub const LOG_LEVEL_VAR: &str = "SEKIRBO_LOG";
+
+// Minimal logger writing records to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        return metadata.level() <= log::max_level();
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let level = match record.level() {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN ",
+            Level::Info => "INFO ",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        eprintln!("[{}] {}: {}", level, record.target(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// Install the stderr logger. Debug builds default to info, release builds to warn.
+pub fn init() {
+    let default_level = if cfg!(debug_assertions) { LevelFilter::Info } else { LevelFilter::Warn };
+    let level = std::env::var(LOG_LEVEL_VAR)
+        .ok()
+        .and_then(|x| LevelFilter::from_str(&x).ok())
+        .unwrap_or(default_level);
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,238 +1,151 @@
 use vulkano::instance::Instance;
 use vulkano::instance::InstanceExtensions;
-use vulkano::instance::PhysicalDevice;
-use vulkano::instance::PhysicalDeviceType;
 
 use vulkano::device::Device;
-use vulkano::device::DeviceExtensions;
-use vulkano::device::Features;
+use vulkano::device::Queue;
 
-use vulkano::buffer::CpuAccessibleBuffer;
-use vulkano::buffer::BufferUsage;
+use vulkano::swapchain::PresentMode;
+use vulkano::swapchain::Surface;
 
 use std::sync::Arc;
-use vulkano::format::Format;
-
-use vulkano::framebuffer::Framebuffer;
-use vulkano::image::Dimensions;
-use vulkano::image::StorageImage;
-
-use vulkano::command_buffer::AutoCommandBufferBuilder;
-
-use vulkano::pipeline::GraphicsPipeline;
-use vulkano::framebuffer::Subpass;
-
-use vulkano::command_buffer::DynamicState;
-use vulkano::pipeline::viewport::Viewport;
 
 use vulkano::sync;
 use vulkano::sync::GpuFuture;
 
-use image::ImageBuffer;
-use image::Rgba;
-
-use vulkano::pipeline::vertex::OneVertexOneInstanceDefinition;
-
-mod rendering;
-
-render_config!(
-    name: test_renderer,
-    attachments: {
-        depth: {
-            format: Format::D24Unorm_S8Uint
-        },
-        albedo: {
-            format: Format::R8G8B8A8Unorm
-        },
-        normal: {
-            format: Format::R8G8Unorm
-        },
-        color: {
-            format: Format::R8G8B8A8Unorm
-        },
-        blur: {
-            format: Format::R8G8B8A8Unorm
-        },
-        blur2: {
-            format: Format::R8G8B8A8Unorm
-        },
-        velocity: {
-            format: Format::R8G8B8A8Unorm
-        },
-        motion_blur: {
-            format: Format::R8G8B8A8Unorm
-        }
-    },
-    default_vertex_bindings: [
-        {
-            vertex_type_name: Vertex,
-            input_rate: 0,
-            attributes: {
-                position: [f32; 2],
-                color: [f32; 2]
-            }
-        },
-        {
-            vertex_type_name: InstanceData,
-            input_rate: 1,
-            attributes: {
-                position_offset: [f32; 2],
-                scale: f32
-            }
-        }
-    ],
-    graphics_passes: {
-        gbuffer: {
-            color_outputs: [albedo, normal],
-            depth_stencil_output: {depth},
-            input_attachments: [],
-            depth_stencil_input: {},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        },
-        lighting: {
-            color_outputs: [color],
-            depth_stencil_output: {},
-            input_attachments: [albedo, normal],
-            depth_stencil_input: {depth},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        },
-        blur_pass: {
-            color_outputs: [blur],
-            depth_stencil_output: {},
-            input_attachments: [color],
-            depth_stencil_input: {},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        },
-        blur_pass2: {
-            color_outputs: [blur2],
-            depth_stencil_output: {},
-            input_attachments: [blur],
-            depth_stencil_input: {},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        },
-        composite_pass: {
-            color_outputs: [backbuffer],
-            depth_stencil_output: {},
-            input_attachments: [color, blur, blur2, motion_blur],
-            depth_stencil_input: {},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        },
-        velocity_pass: {
-            color_outputs: [velocity],
-            depth_stencil_output: {},
-            input_attachments: [],
-            depth_stencil_input: {},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        },
-        motion_blur_pass: {
-            color_outputs: [motion_blur],
-            depth_stencil_output: {},
-            input_attachments: [velocity, color],
-            depth_stencil_input: {},
-            pipeline: {
-                shader_paths: {
-                    vertex: "src/shaders/passthrough_2d.vert",
-                    fragment: "src/shaders/passthrough.frag"
-                }
-            }
-        }
-    }
-);
-
-#[derive(Default, Copy, Clone)]
-struct Vertex {
-    position: [f32; 2],
-    color: [f32; 3]
+use vulkano_win::VkSurfaceBuild;
+
+use winit::event::Event;
+use winit::event::WindowEvent;
+use winit::event_loop::ControlFlow;
+use winit::event_loop::EventLoop;
+use winit::window::Window;
+use winit::window::WindowBuilder;
+
+use sekirbo::logger;
+use sekirbo::rendering::device_selection;
+use sekirbo::rendering::headless;
+use sekirbo::rendering::swapchain;
+use sekirbo::rendering::swapchain::Presenter;
+use sekirbo::scenes;
+
+const FRAME_COUNT: usize = 3;
+
+// `--window [fifo|mailbox|immediate]` renders to a window until it's closed, presenting with the
+// given mode if the surface supports it
+fn window_config() -> Option<PresentMode> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|x| x == "--window")?;
+    let present_mode = match args.get(index + 1).map(|x| x.as_str()) {
+        Some("mailbox") => PresentMode::Mailbox,
+        Some("immediate") => PresentMode::Immediate,
+        _ => PresentMode::Fifo
+    };
+    return Some(present_mode);
 }
 
-vulkano::impl_vertex!(Vertex, position, color);
-
-#[derive(Default, Copy, Clone)]
-struct InstanceData {
-    position_offset: [f32; 2],
-    scale: f32
+fn window_dimensions(surface: &Surface<Window>) -> [u32; 2] {
+    let size = surface.window().inner_size();
+    return [size.width, size.height];
 }
 
-vulkano::impl_vertex!(InstanceData, position_offset, scale);
+// Draw the triangle scene to the window of `surface` until it's closed. The swapchain and the
+// renderer's attachments are recreated when the window is resized.
+fn run_windowed(
+    event_loop: EventLoop<()>,
+    surface: Arc<Surface<Window>>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    present_mode: PresentMode
+) -> ! {
+    let caps = surface.capabilities(device.physical_device()).expect("Failed to get surface capabilities");
+    let format = swapchain::select_surface_format(&caps.supported_formats).unwrap();
+    println!("Backbuffer format: {:?} {:?}", format.0, format.1);
+
+    let mut renderer = scenes::triangle_with_format(device.clone(), format.0);
+    let mut presenter = Presenter::new(
+        device,
+        &queue,
+        surface.clone(),
+        format,
+        present_mode,
+        window_dimensions(&surface)
+    ).unwrap();
 
-fn device_rank(physical: &PhysicalDevice) -> u64 {
-    // Device type ranks highest
-    let device_type_rank = match physical.ty() {
-        PhysicalDeviceType::DiscreteGpu => 5,
-        PhysicalDeviceType::IntegratedGpu => 4,
-        PhysicalDeviceType::VirtualGpu => 3,
-        PhysicalDeviceType::Cpu => 2,
-        PhysicalDeviceType::Other => 1,
-    };
+    event_loop.run(move |event, _, control_flow| {
+        match event {
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                *control_flow = ControlFlow::Exit;
+            },
+            Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
+                presenter.invalidate();
+            },
+            Event::RedrawEventsCleared => {
+                let dimensions = window_dimensions(presenter.surface());
+                presenter.present_frame(&mut renderer, queue.clone(), dimensions).unwrap();
+            },
+            _ => ()
+        }
+    });
+}
 
-    return device_type_rank;
+// `--headless [frames] [output dir]` renders the frames offscreen and saves each one to disk
+fn headless_config() -> Option<(usize, String)> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|x| x == "--headless")?;
+    let frame_count = args.get(index + 1)
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(FRAME_COUNT);
+    let output_dir = args.get(index + 2)
+        .cloned()
+        .unwrap_or_else(|| "frames".to_string());
+    return Some((frame_count, output_dir));
 }
 
 fn main() {
-    test_renderer::build().unwrap();
-
-    // Create a vulkan instance
-    let instance = Instance::new(None, &InstanceExtensions::none(), None).expect("Failed to create vulkan instance");
-
-    // Choose a physical device
-    let physical = PhysicalDevice::enumerate(&instance)
-        .max_by(
-            |x, y| {
-                return device_rank(&x).cmp(&device_rank(&y))
-            }
-        )
-        .expect("No physical device available");
+    logger::init();
+
+    // Create a vulkan instance, with the extensions for a window surface if rendering to one
+    let present_mode = window_config();
+    let instance_extensions = if present_mode.is_some() {
+        vulkano_win::required_extensions()
+    } else {
+        InstanceExtensions::none()
+    };
+    let instance = Instance::new(None, &instance_extensions, None).expect("Failed to create vulkan instance");
+
+    let window = present_mode.map(|present_mode| {
+        let event_loop = EventLoop::new();
+        let surface = WindowBuilder::new()
+            .with_title("sekirbo")
+            .build_vk_surface(&event_loop, instance.clone())
+            .expect("Failed to create window");
+        return (event_loop, surface, present_mode);
+    });
+
+    // Choose a physical device that can run the test renderer's graph, or the one picked with
+    // --device or SEKIRBO_DEVICE
+    let mut requirements = scenes::device_requirements();
+    requirements.extensions.khr_swapchain = window.is_some();
+    // The queue does graphics and compute, and presents too when rendering to a window
+    let (physical, queue_family) = device_selection::select_device(
+        &instance,
+        &requirements,
+        device_selection::device_choice().as_ref(),
+        |q| {
+            return q.supports_graphics()
+                && q.supports_compute()
+                && window.as_ref().map_or(true, |(_, surface, _)| surface.is_supported(q).unwrap_or(false));
+        }
+    ).unwrap();
 
     println!("Physical device chosen: {}", physical.name());
 
-    // List queue families and find one that supports both gfx and compute
-    for family in physical.queue_families() {
-        println!("Found a queue family with {:?} queue(s)", family.queues_count());
-    }
-
-    let queue_family = physical.queue_families()
-                               .find(|&q| q.supports_graphics() && q.supports_compute())
-                               .expect("Couldn't find a valid queue family");
-
     // Create the device and queues
     let (device, mut queues) = {
         Device::new(
             physical, 
-            &Features::none(), 
-            &DeviceExtensions{
-                khr_storage_buffer_storage_class: true, 
-                ..DeviceExtensions::none()
-            },
+            &requirements.features, 
+            &requirements.extensions,
             [(queue_family, 0.5)]
                                 .iter()
                                 .cloned()
@@ -242,199 +155,27 @@ fn main() {
 
     let queue = queues.next().unwrap();
 
-    let vertex_buffer = CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        BufferUsage::all(),
-        false,
-        vec![
-            Vertex {
-                position: [-0.5, -0.5],
-                color: [1.0, 1.0, 0.0]
-            },
-            Vertex {
-                position: [0.0, 0.5],
-                color: [1.0, 0.0, 1.0]
-            },
-            Vertex {
-                position: [0.5, -0.25],
-                color: [0.0, 1.0, 1.0]
-            }
-        ].into_iter()
-    ).unwrap();
-
-    let instance_buffer= CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        BufferUsage::all(),
-        false,
-        vec![
-            InstanceData {
-                position_offset: [-0.5, -0.5],
-                scale: 1.0 
-            },
-            InstanceData {
-                position_offset: [0.0, 0.5],
-                scale: 0.5
-            },
-            InstanceData {
-                position_offset: [0.5, -0.25],
-                scale: 0.75
-            }
-        ].into_iter()
-    ).unwrap();
-
-    // Render pass and framebuffers
+    if let Some((event_loop, surface, present_mode)) = window {
+        run_windowed(event_loop, surface, device, queue, present_mode);
+    }
 
-    let render_pass = Arc::new(
-        vulkano::single_pass_renderpass!(
-            device.clone(),
-            attachments: {
-                color: {
-                    load: Clear,
-                    store: Store,
-                    format: Format::R8G8B8A8Unorm,
-                    samples: 1,
-                }
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {}
-            }
-        ).unwrap()
-    );
+    let image = headless::offscreen_backbuffer(device.clone(), [1024, 1024]).unwrap();
 
-    let image = StorageImage::new(
-        device.clone(),
-        Dimensions::Dim2d {
-            width: 1024,
-            height: 1024
+    let mut renderer = scenes::triangle(device.clone());
+    match headless_config() {
+        Some((frame_count, output_dir)) => {
+            let paths = headless::render_to_disk(&mut renderer, queue.clone(), image.clone(), frame_count, &output_dir).unwrap();
+            println!("Saved {} frames to {}", paths.len(), output_dir);
         },
-        Format::R8G8B8A8Unorm,
-        Some(queue.family())
-    ).unwrap();
-
-    let image_buf = CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        BufferUsage::all(),
-        false,
-        (0 .. 1024 * 1024 * 4).map(|_| 0u8)
-    ).expect("Failed to create image buf");
-
-    let framebuffer = Arc::new(
-        Framebuffer::start(
-            render_pass.clone()
-        )
-        .add(image.clone()).unwrap()
-        .build().unwrap()
-    );
+        None => {
+            // Draw a few frames without waiting on the CPU, then save the backbuffer of the last one
+            for _ in 0..FRAME_COUNT {
+                renderer.execute_frame(queue.clone(), image.clone(), sync::now(device.clone()).boxed()).unwrap();
+            }
 
-    // Pipeline
-    mod vs {
-        vulkano_shaders::shader!{
-            ty: "vertex",
-            path: "src/shaders/passthrough_2d.vert"
-        }
-    }
+            renderer.screenshot("triangle.png").unwrap();
 
-    mod fs {
-        vulkano_shaders::shader!{
-            ty: "fragment",
-            path: "src/shaders/passthrough.frag"
+            println!("Draw done");
         }
     }
-
-    let vs = vs::Shader::load(device.clone()).expect("Failed to create VS");
-    let fs = fs::Shader::load(device.clone()).expect("Failed to create FS");
-
-    let pipeline = Arc::new(
-        GraphicsPipeline::start()
-            .vertex_input(
-                OneVertexOneInstanceDefinition::<Vertex, InstanceData>::new()
-            )//.vertex_input_single_buffer::<Vertex>()
-            .vertex_shader(
-                vs.main_entry_point(), 
-                ()
-            )
-            .viewports_dynamic_scissors_irrelevant(1)
-            .fragment_shader(
-                fs.main_entry_point(), 
-                ()
-            )
-            .render_pass(
-                Subpass::from(
-                    render_pass.clone(), 
-                    0
-                ).unwrap()
-            )
-            .build(device.clone())
-            .unwrap()
-    );
-
-    // Draw commands
-
-    let dynamic_state = DynamicState {
-        viewports: Some(
-            vec![
-                Viewport {
-                    origin: [0.0, 0.0],
-                    dimensions: [1024.0, 1024.0],
-                    depth_range: 0.0 .. 1.0,
-                }
-            ]
-        ),
-        .. DynamicState::none()
-    };
-
-    let mut cmd_buf_builder = AutoCommandBufferBuilder::primary_one_time_submit(
-        device.clone(), 
-        queue_family
-    ).unwrap();
-
-    cmd_buf_builder
-        .begin_render_pass(
-            framebuffer.clone(), 
-            false, 
-            vec![[0.0, 0.0, 1.0, 1.0].into()]
-        ).unwrap()
-
-        .draw(
-            pipeline.clone(),
-            &dynamic_state,
-            (vertex_buffer.clone(), instance_buffer.clone()),
-            (),
-            ()
-        ).unwrap()
-
-        .end_render_pass()
-        .unwrap()
-        
-        .copy_image_to_buffer(
-            image.clone(), 
-            image_buf.clone()
-        )
-        .unwrap();
-    
-    let cmd_buf = cmd_buf_builder.build().unwrap();
-
-    // Execute
-    let future = sync::now(device.clone())
-        .then_execute(
-            queue.clone(), 
-            cmd_buf
-        )
-        .unwrap()
-        .then_signal_fence_and_flush()
-        .unwrap();
-
-    future.wait(None).unwrap();
-
-    println!("Draw done");
-
-    let image_buf_content = image_buf.read().unwrap();
-    let image = ImageBuffer::<Rgba<u8>, _>::from_raw(
-        1024,
-        1024,
-        &image_buf_content[..]
-    ).unwrap();
-
-    image.save("triangle.png").unwrap();
-}
\ No newline at end of file
+}
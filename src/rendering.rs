@@ -3,117 +3,325 @@ use typed_arena::Arena;
 use std::cell::RefCell;
 use std::cell::Cell;
 
+use std::sync::Arc;
+
 use std::collections::HashSet;
 use std::collections::HashMap;
 use std::collections::BinaryHeap;
 use std::cmp::Ordering;
+use std::ops::Range;
 
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
 use std::ptr::eq;
+use std::thread;
+use std::time::Instant;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::AutoCommandBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CommandBufferExecFuture;
+use vulkano::command_buffer::DynamicState;
+use vulkano::device::Device;
+use vulkano::device::Queue;
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::format::FormatTy;
+use vulkano::framebuffer::AttachmentDescription;
+use vulkano::framebuffer::LoadOp;
+use vulkano::framebuffer::PassDependencyDescription;
+use vulkano::framebuffer::PassDescription;
+use vulkano::framebuffer::RenderPass;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::StoreOp;
+use vulkano::framebuffer::Subpass;
+use vulkano::image::AttachmentImage;
+use vulkano::image::ImageLayout;
+use vulkano::image::ImageUsage;
+use vulkano::image::ImageViewAccess;
+use vulkano::instance::QueueFamily;
+use vulkano::pipeline::viewport::Scissor;
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::pipeline::shader::ShaderInterfaceDef;
+use vulkano::swapchain::Swapchain;
+use vulkano::sync::AccessFlagBits;
+use vulkano::sync::FenceSignalFuture;
+use vulkano::sync::FlushError;
+use vulkano::sync::GpuFuture;
+use vulkano::sync::PipelineStages;
+
+mod descriptor_set;
+pub mod device_selection;
+mod graph_report;
+pub mod headless;
+pub mod material;
+pub mod memory_budget;
+pub mod pass_executor;
+pub mod pipeline_cache;
+pub mod pipeline_state;
+pub mod swapchain;
+pub mod vertex_input;
+mod readback;
+mod render_pass;
+#[cfg(test)]
+mod tests;
+
+use device_selection::DeviceRequirements;
+use device_selection::FormatRequirement;
+use device_selection::is_format_supported;
+use material::Material;
+use material::Mesh;
+use memory_budget::AttachmentMemory;
+use memory_budget::MemoryEstimate;
+use memory_budget::PassMemory;
+use memory_budget::image_bytes;
+use pass_executor::PassContext;
+use pass_executor::ParallelPassExecutor;
+use pass_executor::PassExecutor;
+use pass_executor::RecordingPool;
+use pass_executor::SubpassResources;
+use pipeline_cache::PipelineCache;
+use pipeline_state::DepthBounds;
+use pipeline_state::DynamicStates;
+use pipeline_state::PolygonMode;
+use pipeline_state::PipelineStateDesc;
+use vertex_input::VertexBinding;
+use vertex_input::VertexInputDesc;
+use render_pass::GraphFramebuffer;
+use render_pass::GraphRenderPassDesc;
+use render_pass::clear_value;
+
+pub use readback::Readback;
+
+// Creates the graphics pipeline of a pass once its subpass in a physical render pass is known
+pub type PipelineFactory = dyn Fn(
+    Arc<Device>,
+    Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    VertexInputDesc,
+    &PipelineStateDesc
+) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, &'static str> + Send + Sync;
 
 pub const BACKBUFFER_NAME: &str = "BACKBUFFER";
-pub struct AttachmentDesc<'rb> {
-    name: &'rb str,
+// Log target of RendererBuilder::build, for filtering graph compilation output
+pub const BUILD_LOG_TARGET: &str = "render_graph::build";
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+// A phase of RendererBuilder::build. Logs when the phase starts, and its timing when dropped,
+// under BUILD_LOG_TARGET.
+struct BuildSpan {
+    phase: &'static str,
+    start: Instant,
+}
+
+impl BuildSpan {
+    fn enter(phase: &'static str) -> BuildSpan {
+        debug!(target: BUILD_LOG_TARGET, "begin phase={}", phase);
+        return BuildSpan {
+            phase,
+            start: Instant::now()
+        };
+    }
+}
+
+impl Drop for BuildSpan {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        info!(target: BUILD_LOG_TARGET, "end phase={} elapsed_us={}", self.phase, elapsed.as_micros());
+    }
+}
+
+// Handle to an attachment of a RendererBuilder
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AttachmentId(usize);
+
+// Handle to a pass of a RendererBuilder
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+struct AttachmentDesc {
+    id: AttachmentId,
+    name: &'static str,
+    // Chosen from `candidate_formats` when the renderer is built
     format: vulkano::format::Format,
+    // Formats to try in order of preference, starting with the declared one
+    candidate_formats: Vec<vulkano::format::Format>,
     samples: usize,
-    usage: Cell<vulkano::image::ImageUsage>,
-    readers: RefCell<Vec<&'rb PassDesc<'rb>>>,
-    writers: RefCell<Vec<&'rb PassDesc<'rb>>>
+    usage: vulkano::image::ImageUsage,
+    readers: Vec<PassId>,
+    writers: Vec<PassId>,
+    // Set on the previous frame's version of an attachment, e.g. `color@prev`
+    history_of: Option<AttachmentId>,
+    // Whether a history attachment reads this attachment's contents next frame
+    has_history: bool,
+    // Initial and final layouts of an image owned outside the graph
+    import: Option<(ImageLayout, ImageLayout)>,
+    // Set on the backbuffer and other output targets, whose images are given to the renderer
+    is_target: bool,
+    // The output target whose size the attachment's images take
+    sized_like: AttachmentId,
+    // Size estimate_memory takes an output target other than the backbuffer to be
+    estimate_dimensions: Option<[u32; 2]>
 }
 
-pub struct PassDesc<'rb> {
-    name: &'rb str,
-    input_attachments: RefCell<Vec<&'rb AttachmentDesc<'rb>>>,
-    color_outputs: RefCell<Vec<&'rb AttachmentDesc<'rb>>>,
-    depth_input: RefCell<Option<&'rb AttachmentDesc<'rb>>>,
-    depth_output: RefCell<Option<&'rb AttachmentDesc<'rb>>>,
+struct PassDesc {
+    id: PassId,
+    name: &'static str,
+    input_attachments: Vec<AttachmentId>,
+    // Read through a sampler rather than as attachments of the pass's framebuffer
+    sampled_inputs: Vec<AttachmentId>,
+    color_outputs: Vec<AttachmentId>,
+    depth_input: Option<AttachmentId>,
+    depth_output: Option<AttachmentId>,
+    pipeline_state: PipelineStateDesc,
+    pipeline_factory: Option<Box<PipelineFactory>>,
+    // Identifies the shaders, defines and specialization constants the factory compiles
+    shader_permutation: &'static str,
+    // Named (set, binding) pairs that materials of this pass bind resources to
+    material_bindings: Vec<(&'static str, usize, usize)>,
+    // Locations and formats of the fragment shader's outputs, when known
+    fragment_outputs: Option<Vec<(Range<u32>, Format)>>,
+    // Executed once per view of its output target rather than once per frame
+    per_view: bool,
 }
 
-impl<'rb> PassDesc<'rb> {
-    #[inline]
-    fn add_writer(&'rb self, attachment: &'rb AttachmentDesc<'rb>) {
-        attachment.writers.borrow_mut().push(self);
+impl PassDesc {
+    // The attachment used for depth testing in this pass, if any
+    fn depth_attachment(&self) -> Option<AttachmentId> {
+        return self.depth_output.or(self.depth_input);
     }
 
-    #[inline]
-    fn add_reader(&'rb self, attachment: &'rb AttachmentDesc<'rb>) {
-        attachment.readers.borrow_mut().push(self);
+    // Whether the depth attachment is both tested against and written
+    fn is_depth_read_write(&self) -> bool {
+        return self.depth_input.is_some() && self.depth_input == self.depth_output;
     }
 
-    #[inline]
-    // Write only color output
-    pub fn add_color_output(&'rb self, attachment: &'rb AttachmentDesc<'rb>) {
-        self.color_outputs.borrow_mut().push(attachment);
-        self.add_writer(attachment);
-        attachment.usage.set(vulkano::image::ImageUsage {
-            color_attachment: true,
-            ..attachment.usage.get()
-        });
+    fn depth_layout(&self) -> ImageLayout {
+        if self.depth_output.is_some() {
+            return ImageLayout::DepthStencilAttachmentOptimal;
+        }
+        return ImageLayout::DepthStencilReadOnlyOptimal;
     }
 
-    #[inline]
-    pub fn set_depth_output(&'rb self, attachment: &'rb AttachmentDesc<'rb>) {
-        self.depth_output.borrow_mut().replace(attachment);
-        self.add_writer(attachment);
-        attachment.usage.set(vulkano::image::ImageUsage {
-            depth_stencil_attachment: true,
-            ..attachment.usage.get()
-        });
+    // Whether the attachment is in the pass's framebuffer
+    fn uses_attachment(&self, attachment: AttachmentId) -> bool {
+        return self.color_outputs.contains(&attachment)
+            || self.input_attachments.contains(&attachment)
+            || self.depth_attachment() == Some(attachment);
     }
 
-    #[inline]
-    // Read only input attachment
-    pub fn add_input_attachment(&'rb self, attachment: &'rb AttachmentDesc<'rb>) {
-        self.input_attachments.borrow_mut().push(attachment);
-        self.add_reader(attachment);
-        attachment.usage.set(vulkano::image::ImageUsage {
-            input_attachment: true,
-            ..attachment.usage.get()
-        });
+    // Whether the pass touches the attachment's image at all, sampled or in the framebuffer
+    fn uses_image(&self, attachment: AttachmentId) -> bool {
+        return self.uses_attachment(attachment) || self.sampled_inputs.contains(&attachment);
     }
+}
 
-    #[inline]
-    pub fn set_depth_input(&'rb self, attachment: &'rb AttachmentDesc<'rb>) {
-        self.depth_input.borrow_mut().replace(attachment);
-        self.add_reader(attachment);
-        attachment.usage.set(vulkano::image::ImageUsage {
-            depth_stencil_attachment: true,
-            ..attachment.usage.get()
-        });
+fn is_depth_format(format: vulkano::format::Format) -> bool {
+    match format.ty() {
+        FormatTy::Depth | FormatTy::DepthStencil => true,
+        _ => false
+    }
+}
+
+// Numeric type written to an attachment of the format: floating point (including normalized
+// and sRGB formats), unsigned or signed integer. Shader outputs must match their attachment's.
+fn output_class(format: Format) -> FormatTy {
+    return match format.ty() {
+        FormatTy::Uint => FormatTy::Uint,
+        FormatTy::Sint => FormatTy::Sint,
+        _ => FormatTy::Float
+    };
+}
+
+fn has_stencil_aspect(format: vulkano::format::Format) -> bool {
+    match format.ty() {
+        FormatTy::Stencil | FormatTy::DepthStencil => true,
+        _ => false
     }
 }
 
 #[derive(Clone)]
 struct PassNodeDependency<'a, 'rb> {
     pass_node: &'a PassNode<'a, 'rb>,
-    attachment: &'rb AttachmentDesc<'rb>,
+    attachment: &'rb AttachmentDesc,
     usage: vk_sys::ImageUsageFlagBits,
     is_edge: Cell<bool> // JUST used for toposort
 }
 
 impl<'a, 'rb> PassNodeDependency<'a, 'rb> {
+    // Attachment reads (input/depth) can be satisfied by a subpass dependency. Anything read
+    // through a descriptor needs the writer to be in an earlier physical pass.
     pub fn requires_external_dep(&self) -> bool {
-        return self.usage | vk_sys::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT != 0
-                || self.usage | vk_sys::IMAGE_USAGE_INPUT_ATTACHMENT_BIT != 0;
+        return self.usage & (vk_sys::IMAGE_USAGE_SAMPLED_BIT | vk_sys::IMAGE_USAGE_STORAGE_BIT) != 0;
+    }
+
+    // Stages and accesses of the reading pass
+    fn destination_scope(&self) -> (PipelineStages, AccessFlagBits) {
+        if self.usage & vk_sys::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT != 0 {
+            return (
+                PipelineStages { early_fragment_tests: true, late_fragment_tests: true, ..PipelineStages::none() },
+                // Read-write depth passes also write after the source pass
+                AccessFlagBits {
+                    depth_stencil_attachment_read: true,
+                    depth_stencil_attachment_write: true,
+                    ..AccessFlagBits::none()
+                }
+            );
+        }
+        if self.usage & vk_sys::IMAGE_USAGE_INPUT_ATTACHMENT_BIT != 0 {
+            return (
+                PipelineStages { fragment_shader: true, ..PipelineStages::none() },
+                AccessFlagBits { input_attachment_read: true, ..AccessFlagBits::none() }
+            );
+        }
+        return (
+            PipelineStages { fragment_shader: true, ..PipelineStages::none() },
+            AccessFlagBits { shader_read: true, ..AccessFlagBits::none() }
+        );
+    }
+
+    // Stages and accesses of the writing pass
+    fn source_scope(&self) -> (PipelineStages, AccessFlagBits) {
+        if is_depth_format(self.attachment.format) {
+            return (
+                PipelineStages { early_fragment_tests: true, late_fragment_tests: true, ..PipelineStages::none() },
+                AccessFlagBits { depth_stencil_attachment_write: true, ..AccessFlagBits::none() }
+            );
+        }
+        return (
+            PipelineStages { color_attachment_output: true, ..PipelineStages::none() },
+            AccessFlagBits { color_attachment_write: true, ..AccessFlagBits::none() }
+        );
     }
 }
 
 #[derive(Clone)]
 struct PassNode<'a, 'rb> {
-    pass: &'rb PassDesc<'rb>,
-    dependents: Vec<PassNodeDependency<'a, 'rb>>,
-    dependencies: Vec<PassNodeDependency<'a, 'rb>>
+    pass: &'rb PassDesc,
+    dependents: RefCell<Vec<PassNodeDependency<'a, 'rb>>>,
+    dependencies: RefCell<Vec<PassNodeDependency<'a, 'rb>>>,
+    // Score the pass was queued with by the scheduler, None for passes without dependencies
+    overlap_score: Cell<Option<usize>>,
+    // Output target the pass's attachments are sized like. Only passes of the same size can share
+    // a framebuffer.
+    sized_like: AttachmentId
 }
 
 impl<'a, 'rb> PassNode<'a, 'rb> {
     pub fn is_independent(&self) -> bool {
-        return self.dependencies.iter().all(|x| !x.is_edge.get());
+        return self.dependencies.borrow().iter().all(|x| !x.is_edge.get());
     }
 
     pub fn depends_on(&self, other: &'a PassNode<'a, 'rb>) -> bool {
         if eq(self, other) {
             return true;
         }
-        for dependency in self.dependencies.iter() {
+        for dependency in self.dependencies.borrow().iter() {
             if dependency.pass_node.depends_on(other) {
                 return true;
             }
@@ -121,15 +329,16 @@ impl<'a, 'rb> PassNode<'a, 'rb> {
         return false;
     }
 
-    #[inline]
-    pub fn display(&'a self) {
-        println!("Pass: {}", self.pass.name);
-
-        if !self.dependencies.is_empty() {
-            println!("Dependent on:");
-            for dependency in self.dependencies.iter() {
-                println!("- Pass {}, via attachment {}", dependency.pass_node.pass.name, dependency.attachment.name);
-            }
+    // Log where the pass was scheduled and what it waits on
+    pub fn display(&'a self, index: usize) {
+        debug!(target: BUILD_LOG_TARGET, "scheduled index={} pass={}", index, self.pass.name);
+        for dependency in self.dependencies.borrow().iter() {
+            debug!(
+                target: BUILD_LOG_TARGET,
+                "  depends on pass={} attachment={}",
+                dependency.pass_node.pass.name,
+                dependency.attachment.name
+            );
         }
     }
 }
@@ -172,7 +381,7 @@ impl<'a, 'rb> PhysicalPass<'a, 'rb> {
 
     pub fn add_subpass(&mut self, pass_node: &'a PassNode<'a, 'rb>) {
         self.subpasses.push(pass_node);
-        for dep in pass_node.dependencies.iter() {
+        for dep in pass_node.dependencies.borrow().iter() {
             if dep.requires_external_dep() && !self.is_external_dep(dep.pass_node) {
                 self.external_dependencies.push(PassNodeDependency {
                     is_edge: Cell::new(dep.is_edge.get()),
@@ -181,134 +390,704 @@ impl<'a, 'rb> PhysicalPass<'a, 'rb> {
             }
         }
     }
-}
 
-pub struct RendererBuilder<'rb> {
-    attachment_arena: Arena<AttachmentDesc<'rb>>,
-    attachments: RefCell<Vec<&'rb AttachmentDesc<'rb>>>,
-    backbuffer_attachment: AttachmentDesc<'rb>,
+    fn subpass_index(&self, pass: PassId) -> Option<usize> {
+        return self.subpasses.iter().position(|x| x.pass.id == pass);
+    }
+
+    // Attachments used by the subpasses, in order of first use
+    fn attachments(&self) -> Vec<AttachmentId> {
+        let mut attachments: Vec<AttachmentId> = Vec::new();
+        for subpass in self.subpasses.iter() {
+            let pass = subpass.pass;
+            let used = pass.color_outputs.iter()
+                .chain(pass.input_attachments.iter())
+                .cloned()
+                .chain(pass.depth_attachment());
+            for attachment in used {
+                if !attachments.contains(&attachment) {
+                    attachments.push(attachment);
+                }
+            }
+        }
+        return attachments;
+    }
+
+    // Generate the vulkan render pass description. Attachments in `written_attachments` have
+    // been written by earlier physical passes and are loaded rather than cleared. Imported
+    // attachments not in `later_attachments` are left in their declared final layout.
+    pub fn render_pass_desc(
+        &self,
+        builder: &RendererBuilder,
+        written_attachments: &[AttachmentId],
+        later_attachments: &[AttachmentId]
+    ) -> GraphRenderPassDesc {
+        let mut desc = GraphRenderPassDesc::new();
+
+        let attachments = self.attachments();
+        for attachment in attachments.iter().map(|x| builder.attachment(*x)) {
+            let layout = if is_depth_format(attachment.format) {
+                ImageLayout::DepthStencilAttachmentOptimal
+            } else {
+                ImageLayout::ColorAttachmentOptimal
+            };
+
+            let written = written_attachments.contains(&attachment.id);
+            let load = if attachment.history_of.is_some() || written {
+                LoadOp::Load
+            } else {
+                match attachment.import {
+                    Some((ImageLayout::Undefined, _)) | None => LoadOp::Clear,
+                    Some(_) => LoadOp::Load
+                }
+            };
+
+            let initial_layout = match attachment.import {
+                Some((initial_layout, _)) if !written => initial_layout,
+                _ if load == LoadOp::Load => layout,
+                _ => ImageLayout::Undefined
+            };
+            let final_layout = match attachment.import {
+                Some((_, final_layout)) if !later_attachments.contains(&attachment.id) => final_layout,
+                _ => layout
+            };
+
+            // Keep the contents if anything outside of this physical pass (or next frame) touches the attachment
+            let store = if attachment.is_target
+                || attachment.import.is_some()
+                || attachment.has_history
+                || attachment.readers.iter()
+                    .chain(attachment.writers.iter())
+                    .any(|pass| self.subpass_index(*pass).is_none())
+            {
+                StoreOp::Store
+            } else {
+                StoreOp::DontCare
+            };
+
+            // Aspects no pass uses are neither loaded nor stored, unless the image is used outside the graph
+            let external = attachment.import.is_some() || attachment.usage.transfer_source;
+            let depth_used = external || builder.uses_depth_aspect(attachment);
+            let stencil_used = has_stencil_aspect(attachment.format)
+                && (external || builder.uses_stencil_aspect(attachment));
+            desc.add_attachment(AttachmentDescription {
+                format: attachment.format,
+                samples: attachment.samples as u32,
+                load: if depth_used { load } else { LoadOp::DontCare },
+                store: if depth_used { store } else { StoreOp::DontCare },
+                stencil_load: if stencil_used { load } else { LoadOp::DontCare },
+                stencil_store: if stencil_used { store } else { StoreOp::DontCare },
+                initial_layout,
+                final_layout
+            });
+        }
 
-    pass_arena: Arena<PassDesc<'rb>>,
-    passes: RefCell<Vec<&'rb PassDesc<'rb>>>,
+        let index_of = |attachment: &AttachmentId| {
+            return attachments.iter().position(|x| x == attachment).unwrap();
+        };
+
+        for (subpass_index, subpass) in self.subpasses.iter().enumerate() {
+            let pass = subpass.pass;
+
+            // Attachments used before and after this subpass but not by it must be preserved
+            let preserve_attachments = attachments.iter()
+                .filter(|attachment| {
+                    !pass.uses_attachment(**attachment)
+                        && self.subpasses[..subpass_index].iter().any(|x| x.pass.uses_attachment(**attachment))
+                        && self.subpasses[subpass_index + 1..].iter().any(|x| x.pass.uses_attachment(**attachment))
+                })
+                .map(|attachment| index_of(attachment))
+                .collect();
+
+            desc.add_subpass(PassDescription {
+                color_attachments: pass.color_outputs.iter()
+                    .map(|attachment| (index_of(attachment), ImageLayout::ColorAttachmentOptimal))
+                    .collect(),
+                depth_stencil: pass.depth_attachment()
+                    .map(|attachment| (index_of(&attachment), pass.depth_layout())),
+                input_attachments: pass.input_attachments.iter()
+                    .map(|attachment| (index_of(attachment), ImageLayout::ShaderReadOnlyOptimal))
+                    .collect(),
+                resolve_attachments: Vec::new(),
+                preserve_attachments
+            });
+
+            for dep in subpass.dependencies.borrow().iter() {
+                if let Some(source_subpass) = self.subpass_index(dep.pass_node.pass.id) {
+                    let (source_stages, source_access) = dep.source_scope();
+                    let (destination_stages, destination_access) = dep.destination_scope();
+                    desc.add_dependency(PassDependencyDescription {
+                        source_subpass,
+                        destination_subpass: subpass_index,
+                        source_stages,
+                        destination_stages,
+                        source_access,
+                        destination_access,
+                        by_region: true
+                    });
+                }
+            }
+        }
+
+        return desc;
+    }
 }
 
-impl<'rb> RendererBuilder<'rb> {
-    pub fn new() -> RendererBuilder<'rb> {
-        return RendererBuilder {
-            attachment_arena: Arena::new(),
-            attachments: RefCell::new(Vec::new()),
-            backbuffer_attachment: AttachmentDesc {
-                name: BACKBUFFER_NAME,
-                format: vulkano::format::Format::R8G8B8A8Unorm,
-                samples: 1,
-                usage: Cell::new(vulkano::image::ImageUsage::none()),
-                readers: RefCell::new(Vec::new()),
-                writers: RefCell::new(Vec::new())
-            },
-            pass_arena: Arena::new(),
-            passes: RefCell::new(Vec::new())
+// Describes a render graph. Attachments and passes are referred to by the handles returned when
+// they are added, so graph-building code can be split up and the builder kept around and edited
+// after a renderer has been built from it.
+pub struct RendererBuilder {
+    // The backbuffer is always the first attachment
+    attachments: Vec<AttachmentDesc>,
+    passes: Vec<PassDesc>,
+
+    default_vertex_input: VertexInputDesc,
+    frames_in_flight: usize,
+    imported_buffers: Vec<&'static str>,
+    // Where build writes the JSON report of the compiled graph, if anywhere
+    graph_report_path: Option<PathBuf>,
+    // Bytes of attachment memory estimate_memory warns above, and the backbuffer dimensions build
+    // checks them at
+    memory_budget: Option<(u64, [u32; 2])>,
+}
+
+impl RendererBuilder {
+    pub fn new() -> RendererBuilder {
+        let mut builder = RendererBuilder {
+            attachments: Vec::new(),
+            passes: Vec::new(),
+            default_vertex_input: VertexInputDesc::new(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            imported_buffers: Vec::new(),
+            graph_report_path: None,
+            memory_budget: None
         };
+        let backbuffer = builder.push_attachment(BACKBUFFER_NAME, headless::OFFSCREEN_FORMAT, 1, None, None);
+        builder.attachment_mut(backbuffer).is_target = true;
+        return builder;
+    }
+
+    fn attachment(&self, id: AttachmentId) -> &AttachmentDesc {
+        return &self.attachments[id.0];
+    }
+
+    fn attachment_mut(&mut self, id: AttachmentId) -> &mut AttachmentDesc {
+        return &mut self.attachments[id.0];
     }
 
-    pub fn add_attachment(&'rb self, name: &'static str, format: vulkano::format::Format, samples: usize) -> &'rb AttachmentDesc {
-        let attachment = self.attachment_arena.alloc(AttachmentDesc {
+    fn pass(&self, id: PassId) -> &PassDesc {
+        return &self.passes[id.0];
+    }
+
+    fn pass_mut(&mut self, id: PassId) -> &mut PassDesc {
+        return &mut self.passes[id.0];
+    }
+
+    fn push_attachment(
+        &mut self,
+        name: &'static str,
+        format: vulkano::format::Format,
+        samples: usize,
+        history_of: Option<AttachmentId>,
+        import: Option<(ImageLayout, ImageLayout)>
+    ) -> AttachmentId {
+        let id = AttachmentId(self.attachments.len());
+        self.attachments.push(AttachmentDesc {
+            id,
             name,
             format,
+            // History attachments take the format chosen for the attachment they follow
+            candidate_formats: if history_of.is_some() { Vec::new() } else { vec![format] },
             samples,
-            usage: Cell::new(vulkano::image::ImageUsage::none()),
-            readers: RefCell::new(Vec::new()),
-            writers: RefCell::new(Vec::new())
+            usage: vulkano::image::ImageUsage::none(),
+            readers: Vec::new(),
+            writers: Vec::new(),
+            history_of,
+            has_history: false,
+            import,
+            is_target: false,
+            sized_like: AttachmentId(0),
+            estimate_dimensions: None
         });
+        return id;
+    }
 
-        self.attachments.borrow_mut().push(attachment);
+    pub fn add_attachment(&mut self, name: &'static str, format: vulkano::format::Format, samples: usize) -> AttachmentId {
+        return self.push_attachment(name, format, samples, None, None);
+    }
 
-        return attachment;
+    // The previous frame's contents of an attachment. Passes read it like any other attachment but
+    // don't depend on its writers, since it was written last frame.
+    pub fn add_history_attachment(&mut self, name: &'static str, attachment: AttachmentId) -> AttachmentId {
+        // Adding the same history twice returns it again, any other attachment of that name is a
+        // collision reported by validation
+        if let Some(history) = self.attachments.iter().find(|x| x.name == name && x.history_of == Some(attachment)) {
+            return history.id;
+        }
+
+        let (format, samples) = (self.attachment(attachment).format, self.attachment(attachment).samples);
+        let history = self.push_attachment(name, format, samples, Some(attachment), None);
+        self.attachment_mut(attachment).has_history = true;
+        return history;
+    }
+
+    // An attachment backed by an image the graph doesn't own, set on the renderer with
+    // set_imported_image. The image is expected in `initial_layout` when the frame starts and is
+    // left in `final_layout` after its last use. An undefined initial layout discards its contents.
+    pub fn import_attachment(
+        &mut self,
+        name: &'static str,
+        format: vulkano::format::Format,
+        samples: usize,
+        initial_layout: ImageLayout,
+        final_layout: ImageLayout
+    ) -> AttachmentId {
+        return self.push_attachment(name, format, samples, None, Some((initial_layout, final_layout)));
+    }
+
+    // A buffer owned outside the graph that pass executors look up by name. Vulkano's command
+    // buffer builder synchronizes the passes that use it.
+    pub fn import_buffer(&mut self, name: &'static str) {
+        if !self.imported_buffers.contains(&name) {
+            self.imported_buffers.push(name);
+        }
+    }
+
+    // Allow the attachment to be copied back to the CPU with Renderer::read_attachment
+    pub fn enable_readback(&mut self, attachment: AttachmentId) {
+        self.attachment_mut(attachment).usage.transfer_source = true;
     }
 
-    pub fn add_depth_attachment(&'rb self, name: &'static str, samples: usize) -> &'rb AttachmentDesc {
-        return self.add_attachment(name, vulkano::format::Format::D24Unorm_S8Uint, samples);
+    // Format to use if the device supports none of the ones before it for this attachment's usage
+    pub fn add_fallback_format(&mut self, attachment: AttachmentId, format: vulkano::format::Format) {
+        self.attachment_mut(attachment).candidate_formats.push(format);
     }
 
-    pub fn add_pass(&'rb self, name: &'static str) -> &'rb PassDesc<'rb> {
-        let pass = self.pass_arena.alloc(PassDesc {
+    pub fn add_depth_attachment(&mut self, name: &'static str, samples: usize) -> AttachmentId {
+        let attachment = self.add_attachment(name, vulkano::format::Format::D24Unorm_S8Uint, samples);
+        self.add_fallback_format(attachment, vulkano::format::Format::D32Sfloat_S8Uint);
+        self.add_fallback_format(attachment, vulkano::format::Format::D32Sfloat);
+        return attachment;
+    }
+
+    pub fn add_pass(&mut self, name: &'static str) -> PassId {
+        let id = PassId(self.passes.len());
+        self.passes.push(PassDesc {
+            id,
             name,
-            input_attachments: RefCell::new(Vec::new()),
-            color_outputs: RefCell::new(Vec::new()),
-            depth_input: RefCell::new(None),
-            depth_output: RefCell::new(None)
+            input_attachments: Vec::new(),
+            sampled_inputs: Vec::new(),
+            color_outputs: Vec::new(),
+            depth_input: None,
+            depth_output: None,
+            pipeline_state: PipelineStateDesc::default(),
+            pipeline_factory: None,
+            shader_permutation: name,
+            material_bindings: Vec::new(),
+            fragment_outputs: None,
+            per_view: false
         });
+        return id;
+    }
+
+    pub fn get_backbuffer_attachment(&self) -> AttachmentId {
+        return AttachmentId(0);
+    }
+
+    // Format of the images the renderer will be given as BACKBUFFER, e.g. the swapchain's format
+    // chosen with swapchain::select_surface_format. Offscreen backbuffers use OFFSCREEN_FORMAT.
+    pub fn set_backbuffer_format(&mut self, format: Format) {
+        let backbuffer = self.get_backbuffer_attachment();
+        self.set_target_format(backbuffer, format);
+    }
+
+    // An image the graph presents to besides BACKBUFFER, e.g. a second window. Its image is set on
+    // the renderer with set_target_image each frame and can have any size; attachments take its
+    // size with set_sized_like.
+    pub fn add_output_target(&mut self, name: &'static str, format: Format) -> AttachmentId {
+        let target = self.push_attachment(name, format, 1, None, None);
+        let attachment = self.attachment_mut(target);
+        attachment.is_target = true;
+        attachment.sized_like = target;
+        return target;
+    }
+
+    pub fn set_target_format(&mut self, target: AttachmentId, format: Format) {
+        let attachment = self.attachment_mut(target);
+        attachment.format = format;
+        attachment.candidate_formats = vec![format];
+    }
+
+    // Size estimate_memory and the memory budget check take an output target added with
+    // add_output_target to be. Targets without one are taken to be the backbuffer's size.
+    pub fn set_target_dimensions(&mut self, target: AttachmentId, dimensions: [u32; 2]) {
+        self.attachment_mut(target).estimate_dimensions = Some(dimensions);
+    }
+
+    // Size the attachment's images like `target` instead of BACKBUFFER. Attachments read from the
+    // passes of several targets, such as shadow maps, are rendered once and shared between them.
+    pub fn set_sized_like(&mut self, attachment: AttachmentId, target: AttachmentId) {
+        self.attachment_mut(attachment).sized_like = target;
+    }
+
+    // Run the pass's executor once per view of its output target, see Renderer::set_views.
+    // Passes that aren't run per view, e.g. shadow map passes, run once for all views.
+    pub fn set_per_view(&mut self, pass: PassId) {
+        self.pass_mut(pass).per_view = true;
+    }
+
+    // Write only color output
+    pub fn add_color_output(&mut self, pass: PassId, attachment: AttachmentId) {
+        self.pass_mut(pass).color_outputs.push(attachment);
+        let attachment = self.attachment_mut(attachment);
+        attachment.writers.push(pass);
+        attachment.usage.color_attachment = true;
+    }
+
+    // Depth and stencil written by the pass. Setting the same attachment as the depth input makes
+    // the pass test against and update earlier contents instead of overwriting them.
+    pub fn set_depth_output(&mut self, pass: PassId, attachment: AttachmentId) {
+        self.pass_mut(pass).depth_output = Some(attachment);
+        let attachment = self.attachment_mut(attachment);
+        attachment.writers.push(pass);
+        attachment.usage.depth_stencil_attachment = true;
+    }
 
-        self.passes.borrow_mut().push(pass);
+    // Read only input attachment
+    pub fn add_input_attachment(&mut self, pass: PassId, attachment: AttachmentId) {
+        self.pass_mut(pass).input_attachments.push(attachment);
+        let attachment = self.attachment_mut(attachment);
+        attachment.readers.push(pass);
+        attachment.usage.input_attachment = true;
+    }
+
+    // Read through a sampler bound by the pass's executor or materials, e.g. to filter the image or
+    // read other pixels than the fragment's own. Unlike input attachments, the writers of a sampled
+    // input always execute in an earlier physical render pass.
+    pub fn add_sampled_input(&mut self, pass: PassId, attachment: AttachmentId) {
+        self.pass_mut(pass).sampled_inputs.push(attachment);
+        let attachment = self.attachment_mut(attachment);
+        attachment.readers.push(pass);
+        attachment.usage.sampled = true;
+    }
 
-        return pass;
+    // Depth and stencil tested against but not written, bound in a read-only layout
+    pub fn set_depth_input(&mut self, pass: PassId, attachment: AttachmentId) {
+        self.pass_mut(pass).depth_input = Some(attachment);
+        let attachment = self.attachment_mut(attachment);
+        attachment.readers.push(pass);
+        attachment.usage.depth_stencil_attachment = true;
     }
 
-    pub fn get_backbuffer_attachment(&'rb self) -> &'rb AttachmentDesc<'rb> {
-        return &self.backbuffer_attachment;
+    pub fn set_pipeline_state(&mut self, pass: PassId, state: PipelineStateDesc) {
+        self.pass_mut(pass).pipeline_state = state;
     }
 
-    fn validate_passes<'a, I>(passes: I) -> Result<(), &'static str> 
-        where 
-            I: Iterator<Item = &'a&'rb PassDesc<'rb>>,
-            'rb: 'a 
+    pub fn set_pipeline_factory<F>(&mut self, pass: PassId, factory: F)
+        where
+            F: Fn(
+                Arc<Device>,
+                Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+                VertexInputDesc,
+                &PipelineStateDesc
+            ) -> Result<Arc<dyn GraphicsPipelineAbstract + Send + Sync>, &'static str> + Send + Sync + 'static
     {
-        fn is_valid_depth_attachment<'rb>(attachment: Option<&'rb AttachmentDesc<'rb>>) -> bool {
-            match attachment {
-                Some(AttachmentDesc {format: vulkano::format::Format::D16Unorm, ..}) |
-                Some(AttachmentDesc {format: vulkano::format::Format::D16Unorm_S8Uint, ..}) |
-                Some(AttachmentDesc {format: vulkano::format::Format::D24Unorm_S8Uint, ..}) |
-                Some(AttachmentDesc {format: vulkano::format::Format::D32Sfloat, ..}) |
-                Some(AttachmentDesc {format: vulkano::format::Format::D32Sfloat_S8Uint, ..}) |
-                None
-                    => true,
-                _ => false 
+        self.pass_mut(pass).pipeline_factory = Some(Box::new(factory));
+    }
+
+    pub fn set_shader_permutation(&mut self, pass: PassId, permutation: &'static str) {
+        self.pass_mut(pass).shader_permutation = permutation;
+    }
+
+    pub fn add_material_binding(&mut self, pass: PassId, name: &'static str, set: usize, binding: usize) {
+        self.pass_mut(pass).material_bindings.push((name, set, binding));
+    }
+
+    // Outputs of the pass's fragment shader, e.g. `fs::MainOutput` generated by vulkano_shaders,
+    // checked against the attachments the pass writes
+    pub fn set_fragment_outputs<I: ShaderInterfaceDef>(&mut self, pass: PassId, outputs: &I) {
+        self.pass_mut(pass).fragment_outputs = Some(
+            outputs.elements()
+                .map(|x| (x.location, x.format))
+                .collect()
+        );
+    }
+
+    // How many frames the CPU may record ahead of the GPU
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: usize) {
+        self.frames_in_flight = frames_in_flight;
+    }
+
+    // Have build write a JSON description of the compiled graph to `path`, see graph_report
+    pub fn set_graph_report_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.graph_report_path = Some(path.as_ref().to_path_buf());
+    }
+
+    // Warn when the graph needs more than `bytes` of attachment memory. Build checks this with a
+    // backbuffer of `dimensions`, e.g. the smallest window size a quality preset targets.
+    pub fn set_memory_budget(&mut self, bytes: u64, dimensions: [u32; 2]) {
+        self.memory_budget = Some((bytes, dimensions));
+    }
+
+    pub fn add_default_vertex_binding(&mut self, binding: VertexBinding) {
+        self.default_vertex_input.add_binding(binding);
+    }
+
+    // The output target an attachment is sized like. History attachments follow their attachment.
+    fn attachment_size(&self, attachment: AttachmentId) -> AttachmentId {
+        let attachment = self.attachment(attachment);
+        return attachment.history_of.map_or(attachment.sized_like, |x| self.attachment(x).sized_like);
+    }
+
+    // The output target a pass renders at the size of, from its first attachment
+    fn pass_size(&self, pass: &PassDesc) -> AttachmentId {
+        return pass.color_outputs.iter()
+            .chain(pass.input_attachments.iter())
+            .cloned()
+            .chain(pass.depth_attachment())
+            .next()
+            .map_or(self.get_backbuffer_attachment(), |x| self.attachment_size(x));
+    }
+
+    // Whether any pass uses the depth (or color) aspect. Depth is used by passes that test
+    // against it and by passes that read it as an input attachment.
+    fn uses_depth_aspect(&self, attachment: &AttachmentDesc) -> bool {
+        if !is_depth_format(attachment.format) {
+            return true;
+        }
+        return attachment.readers.iter()
+            .chain(attachment.writers.iter())
+            .map(|x| self.pass(*x))
+            .any(|pass| {
+                if pass.depth_attachment() != Some(attachment.id) {
+                    return true;
+                }
+                return pass.pipeline_state.has_depth_test()
+                    || pass.pipeline_state.depth_stencil.depth_bounds_test != DepthBounds::Disabled;
+            });
+    }
+
+    // Whether any pass tests against the stencil aspect. Input attachments only read depth.
+    fn uses_stencil_aspect(&self, attachment: &AttachmentDesc) -> bool {
+        return attachment.readers.iter()
+            .chain(attachment.writers.iter())
+            .map(|x| self.pass(*x))
+            .any(|pass| pass.depth_attachment() == Some(attachment.id) && pass.pipeline_state.has_stencil_test());
+    }
+
+    fn validate_passes(&self) -> Result<(), &'static str> {
+        // Whether the device supports the format is checked when formats are selected
+        let is_valid_depth_attachment = |attachment: Option<AttachmentId>| {
+            return attachment.map_or(true, |x| is_depth_format(self.attachment(x).format));
+        };
+
+        for (i, attachment) in self.attachments.iter().enumerate() {
+            if self.attachments[..i].iter().any(|x| x.name == attachment.name) {
+                return Err("Attachment name collision");
             }
         }
 
-        for pass in passes {
-            if !is_valid_depth_attachment(*pass.depth_input.borrow()) {
+        for pass in self.passes.iter() {
+            if !is_valid_depth_attachment(pass.depth_input) {
                 return Err("Cannot set non-depth attachment to depth input.");
             }
 
-            if !is_valid_depth_attachment(*pass.depth_output.borrow()) {
+            if !is_valid_depth_attachment(pass.depth_output) {
                 return Err("Cannot set non-depth attachment to depth output.");
-            }           
+            }
+
+            // A pass has a single depth attachment, read only or read-write
+            if pass.depth_input.is_some() && pass.depth_output.is_some() && !pass.is_depth_read_write() {
+                return Err("Depth input and depth output of a pass must be the same attachment.");
+            }
+
+            let depth_attachment = pass.depth_attachment();
+            pass.pipeline_state.validate(
+                pass.color_outputs.len(),
+                depth_attachment.is_some(),
+                pass.depth_output.is_some(),
+                depth_attachment.map_or(false, |x| has_stencil_aspect(self.attachment(x).format))
+            )?;
+
+            // Sampling an attachment of the same framebuffer would be a feedback loop
+            if pass.sampled_inputs.iter().any(|x| pass.uses_attachment(*x)) {
+                return Err("A pass cannot sample an attachment it also uses as an attachment.");
+            }
+
+            for attachment in pass.input_attachments.iter().chain(pass.sampled_inputs.iter()).map(|x| self.attachment(*x)) {
+                if let Some(current) = attachment.history_of.map(|x| self.attachment(x)) {
+                    if current.writers.is_empty() {
+                        return Err("History attachment of an attachment that no pass writes.");
+                    }
+                    if is_depth_format(current.format) {
+                        return Err("History attachments of depth formats are not supported.");
+                    }
+                    if current.import.is_some() {
+                        return Err("History attachments of imported attachments are not supported.");
+                    }
+                }
+            }
+
+            let material_bindings = &pass.material_bindings;
+            for (i, (name, set, binding)) in material_bindings.iter().enumerate() {
+                if material_bindings[..i].iter().any(|x| x.0 == *name) {
+                    return Err("Material binding name is declared twice in a pass.");
+                }
+                if material_bindings[..i].iter().any(|x| x.1 == *set && x.2 == *binding) {
+                    return Err("Two material bindings share a set and binding.");
+                }
+            }
+
+            // Output target formats depend on the surface, so their writers are checked against them
+            let targets = pass.color_outputs.iter()
+                .map(|x| self.attachment(*x))
+                .enumerate()
+                .filter(|(_, x)| x.is_target);
+            for (location, target) in targets {
+                if let Some(outputs) = pass.fragment_outputs.as_ref() {
+                    let output = outputs.iter()
+                        .find(|x| x.0.contains(&(location as u32)))
+                        .ok_or("Pass writing an output target has no fragment shader output for it.")?;
+                    if output_class(output.1) != output_class(target.format) {
+                        return Err("Fragment shader output is incompatible with the output target's format.");
+                    }
+                }
+            }
+
+            // All attachments of a framebuffer have the same size
+            let pass_size = self.pass_size(pass);
+            let mut attachments = pass.color_outputs.iter()
+                .chain(pass.input_attachments.iter())
+                .cloned()
+                .chain(pass.depth_attachment());
+            if attachments.any(|x| self.attachment_size(x) != pass_size) {
+                return Err("Attachments of a pass must be sized like the same output target.");
+            }
+        }
+
+        if self.attachments.iter().any(|x| !self.attachment(x.sized_like).is_target) {
+            return Err("Attachments can only be sized like an output target.");
         }
 
         Ok(())
     }
 
-    fn create_pass_nodes<'a, I>(passes: I, arena: &'a Arena<PassNode<'a, 'rb>>) -> Result<Vec<&'a mut PassNode<'a, 'rb>>, &'static str>
-        where
-            I: Iterator<Item = &'a&'rb PassDesc<'rb>>
+    // Pick the first candidate format of each attachment that the device supports for everything
+    // the graph uses the attachment for. History attachments follow the attachment they copy, and
+    // imported attachments and output targets keep the format of the image they are given.
+    // `is_supported` tells whether the device supports a format for a usage and sample count.
+    fn select_formats<F>(&mut self, is_supported: F) -> Result<(), &'static str>
+        where F: Fn(Format, ImageUsage, usize) -> bool
     {
-        let mut pass_nodes: HashMap<&str, RefCell<&'a mut PassNode<'a, 'rb>>> = HashMap::new();
+        let mut selected = Vec::new();
+        for (attachment, usage) in self.format_usages() {
+            let format = attachment.candidate_formats.iter()
+                .cloned()
+                .find(|format| is_supported(*format, usage, attachment.samples))
+                .ok_or("The device supports none of an attachment's formats for its usage.")?;
+            if format != attachment.candidate_formats[0] {
+                warn!(target: BUILD_LOG_TARGET, "attachment={} falls back to format={:?}", attachment.name, format);
+            }
+            selected.push((attachment.id, format));
+        }
+
+        for (attachment, format) in selected {
+            self.attachment_mut(attachment).format = format;
+        }
+        for i in 0..self.attachments.len() {
+            if let Some(current) = self.attachments[i].history_of {
+                self.attachments[i].format = self.attachment(current).format;
+            }
+        }
+        return Ok(());
+    }
+
+    // Attachments whose format is selected for the device, with everything the graph uses their
+    // images for. History attachments share the images of the attachment they copy.
+    fn format_usages(&self) -> Vec<(&AttachmentDesc, ImageUsage)> {
+        let mut usages = Vec::new();
+        for attachment in self.attachments.iter().filter(|x| !x.is_target && x.history_of.is_none() && x.import.is_none()) {
+            let mut usage = attachment.usage;
+            for history in self.attachments.iter().filter(|x| x.history_of == Some(attachment.id)) {
+                usage = usage | history.usage | ImageUsage {
+                    transfer_destination: true,
+                    ..ImageUsage::none()
+                };
+            }
+            // Attachments no pass uses get no image
+            if usage != ImageUsage::none() {
+                usages.push((attachment, usage));
+            }
+        }
+        return usages;
+    }
+
+    // What a physical device needs to build and run the graph, see device_selection::select_device.
+    // Output target formats come from the surface, so only the formats build selects are checked.
+    pub fn device_requirements(&self) -> DeviceRequirements {
+        let mut requirements = DeviceRequirements::none();
+        for (attachment, usage) in self.format_usages() {
+            requirements.formats.push(FormatRequirement {
+                name: attachment.name,
+                candidates: attachment.candidate_formats.clone(),
+                usage,
+                samples: attachment.samples
+            });
+        }
+        for pass in self.passes.iter() {
+            let state = &pass.pipeline_state;
+            if state.polygon_mode != PolygonMode::Fill {
+                requirements.features.fill_mode_non_solid = true;
+            }
+            if state.depth_stencil.depth_bounds_test != DepthBounds::Disabled {
+                requirements.features.depth_bounds = true;
+            }
+            if state.blend.windows(2).any(|x| x[0] != x[1]) {
+                requirements.features.independent_blend = true;
+            }
+        }
+        return requirements;
+    }
+
+    fn create_pass_nodes<'a, 'rb>(&'rb self, arena: &'a Arena<PassNode<'a, 'rb>>) -> Result<Vec<&'a PassNode<'a, 'rb>>, &'static str> {
+        let mut pass_nodes: HashMap<&str, &'a PassNode<'a, 'rb>> = HashMap::new();
+        // Indexed by PassId
+        let mut ordered_pass_nodes: Vec<&'a PassNode<'a, 'rb>> = Vec::new();
 
         // Create new node objects
-        for pass in passes {
+        for pass in self.passes.iter() {
             if pass_nodes.contains_key(pass.name) {
                 return Err("Pass name collision");
             }
-            pass_nodes.insert(pass.name, RefCell::new(
-                arena.alloc(
-                    PassNode {
-                        pass,
-                        dependents: Vec::new(),
-                        dependencies: Vec::new(),
-                    }
-                )
-            ));
+            let pass_node = arena.alloc(
+                PassNode {
+                    pass,
+                    dependents: RefCell::new(Vec::new()),
+                    dependencies: RefCell::new(Vec::new()),
+                    overlap_score: Cell::new(None),
+                    sized_like: self.pass_size(pass)
+                }
+            );
+            pass_nodes.insert(pass.name, pass_node);
+            ordered_pass_nodes.push(pass_node);
         }
 
+        // Whether `reader` depends on `writer` through their depth attachment. Passes that both read
+        // and write depth are ordered by declaration instead of depending on each other.
+        let is_earlier_writer = |reader: PassId, writer: PassId| {
+            return !self.pass(reader).is_depth_read_write() || writer.0 < reader.0;
+        };
+
         // Fill in inter-node dependencies
-        for (_, pass_node_cell) in pass_nodes.iter() {
-            let pass_node = pass_node_cell.borrow_mut();
+        for pass_node in ordered_pass_nodes.iter() {
             let pass = pass_node.pass;
-            for input_attachment in pass.input_attachments.borrow().iter() {
-                for writer in input_attachment.writers.borrow().iter() {
-                    pass_node.dependencies.push(
+            let mut dependencies = pass_node.dependencies.borrow_mut();
+            let mut dependents = pass_node.dependents.borrow_mut();
+            for input_attachment in pass.input_attachments.iter().map(|x| self.attachment(*x)) {
+                for writer in input_attachment.writers.iter() {
+                    dependencies.push(
                         PassNodeDependency {
-                            pass_node: pass_nodes.get(writer.name).unwrap().get_mut(),
+                            pass_node: ordered_pass_nodes[writer.0],
                             attachment: input_attachment,
                             is_edge: Cell::new(true),
                             usage: vk_sys::IMAGE_USAGE_INPUT_ATTACHMENT_BIT
@@ -317,12 +1096,25 @@ impl<'rb> RendererBuilder<'rb> {
                 }
             }
 
-            if pass.depth_input.borrow().is_some() {
-                let depth_input = pass.depth_input.borrow().unwrap();
-                for writer in depth_input.writers.borrow().iter() {
-                    pass_node.dependencies.push(
+            for sampled_input in pass.sampled_inputs.iter().map(|x| self.attachment(*x)) {
+                for writer in sampled_input.writers.iter() {
+                    dependencies.push(
                         PassNodeDependency {
-                            pass_node: pass_nodes.get(writer.name).unwrap().get_mut(),
+                            pass_node: ordered_pass_nodes[writer.0],
+                            attachment: sampled_input,
+                            is_edge: Cell::new(true),
+                            usage: vk_sys::IMAGE_USAGE_SAMPLED_BIT
+                        }
+                    );
+                }
+            }
+
+            if let Some(depth_input) = pass.depth_input.map(|x| self.attachment(x)) {
+                // Read-write passes update what the writers declared before them left behind
+                for writer in depth_input.writers.iter().filter(|x| is_earlier_writer(pass.id, **x)) {
+                    dependencies.push(
+                        PassNodeDependency {
+                            pass_node: ordered_pass_nodes[writer.0],
                             attachment: depth_input,
                             is_edge: Cell::new(true),
                             usage: vk_sys::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT
@@ -331,11 +1123,11 @@ impl<'rb> RendererBuilder<'rb> {
                 }
             }
 
-            for color_output in pass.color_outputs.borrow().iter() {
-                for reader in color_output.readers.borrow().iter() {
-                    pass_node.dependents.push(
+            for color_output in pass.color_outputs.iter().map(|x| self.attachment(*x)) {
+                for reader in color_output.readers.iter() {
+                    dependents.push(
                         PassNodeDependency {
-                            pass_node: pass_nodes.get(reader.name).unwrap().get_mut(),
+                            pass_node: ordered_pass_nodes[reader.0],
                             attachment: color_output,
                             is_edge: Cell::new(true),
                             usage: vk_sys::IMAGE_USAGE_COLOR_ATTACHMENT_BIT 
@@ -344,12 +1136,11 @@ impl<'rb> RendererBuilder<'rb> {
                 }
             }
 
-            if pass.depth_output.borrow().is_some() {
-                let depth_output = pass.depth_output.borrow().unwrap();
-                for reader in depth_output.readers.borrow().iter() {
-                    pass_node.dependents.push(
+            if let Some(depth_output) = pass.depth_output.map(|x| self.attachment(x)) {
+                for reader in depth_output.readers.iter().filter(|x| is_earlier_writer(**x, pass.id)) {
+                    dependents.push(
                         PassNodeDependency {
-                            pass_node: pass_nodes.get(reader.name).unwrap().get_mut(),
+                            pass_node: ordered_pass_nodes[reader.0],
                             attachment: depth_output,
                             is_edge: Cell::new(true),
                             usage: vk_sys::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT 
@@ -358,19 +1149,22 @@ impl<'rb> RendererBuilder<'rb> {
                 }
             }
         }
-        return Ok(pass_nodes.into_iter().map(|(_, v)| v.get_mut()).collect();
+        return Ok(ordered_pass_nodes);
     }
 
-    fn schedule_passes<'a, I>(pass_nodes: I) -> Result<Vec<&'a PassNode<'a, 'rb>>, &'static str>
+    fn schedule_passes<'a, 'n, 'rb, I>(pass_nodes: I) -> Result<Vec<&'a PassNode<'a, 'rb>>, &'static str>
         where
-            I: Iterator<Item = &'a&'a mut PassNode<'a, 'rb>>,
+            I: Iterator<Item = &'n&'a PassNode<'a, 'rb>>,
+            'a: 'n
     {
         let mut root_nodes: BinaryHeap<RootNode<'a, 'rb>> = BinaryHeap::new();
 
+        let mut pass_count = 0;
         for pass_node in pass_nodes {
+            pass_count += 1;
             if pass_node.is_independent() {
                 root_nodes.push(RootNode {
-                    node: pass_node,
+                    node: *pass_node,
                     overlap_score: usize::MAX
                 });
             }
@@ -386,7 +1180,7 @@ impl<'rb> RendererBuilder<'rb> {
             sorted_passes.push(current_pass);
 
             // Remove edge for color attachments if it exists
-            for dependent in current_pass.dependents.iter() {
+            for dependent in current_pass.dependents.borrow().iter() {
                 if !dependent.is_edge.get() {
                     continue;
                 }
@@ -394,7 +1188,7 @@ impl<'rb> RendererBuilder<'rb> {
                 dependent.is_edge.replace(false);
 
                 // Remove current pass from dependent's dependencies
-                for dependency in dependent.pass_node.dependencies.iter() {
+                for dependency in dependent.pass_node.dependencies.borrow().iter() {
                     if eq(dependency.pass_node, current_pass) {
                         dependency.is_edge.replace(false);
                     }
@@ -414,6 +1208,7 @@ impl<'rb> RendererBuilder<'rb> {
                     // NOTE: We DON'T have to include the items already in the priority queue, because it's
                     // guaranteed that all items in this queue are independent.
                     // Insert into queue
+                    dependent.pass_node.overlap_score.set(Some(overlap_score));
                     root_nodes.push(RootNode {
                             node: dependent.pass_node,
                             overlap_score
@@ -424,51 +1219,25 @@ impl<'rb> RendererBuilder<'rb> {
             }
         }
 
-        // Check for cycles
-        for pass_node in sorted_passes.iter() {
-            if !pass_node.is_independent() {
-                return Err("Cyclical render graph provided");
-            }
+        // Passes on a cycle never become independent, so they are never scheduled
+        if sorted_passes.len() != pass_count {
+            return Err("Cyclical render graph provided");
         }
 
         Ok(sorted_passes)
     }
 
-    pub fn build(&'rb self) -> Result<Renderer, &'static str> {
-        // Validate
-        // TODO: handle this error properly
-        let passes = self.passes.borrow();
-
-        println!("Validating passes...");
-        RendererBuilder::validate_passes(passes.iter())?;
-
-        // Schedule passes
-        println!("Scheduling passes...");
-        let pass_node_arena: Arena<PassNode<'_, 'rb>> = Arena::new();
-        let pass_nodes = RendererBuilder::create_pass_nodes(passes.iter(), &pass_node_arena).unwrap();
-        let scheduled_passes = RendererBuilder::schedule_passes(pass_nodes.iter())?;
-
-        println!("Pass scheduling complete. Result:\n");
-        for (i, pass_node ) in scheduled_passes.iter().enumerate() {
-            print!("index {}: ", i);
-            pass_node.display();
-        }
-
-        // Create vulkan resources
-
-        println!("\nCreating vulkan resources\n");
-
-        // Merge passes based on a set of criteria
-
+    // Group scheduled passes into physical render passes, each pass becoming a subpass
+    fn merge_passes<'a, 'rb>(scheduled_passes: &[&'a PassNode<'a, 'rb>]) -> Vec<PhysicalPass<'a, 'rb>> {
         // If depth attachments are different, needs to be a different pass
         // If any input, depth, color, or resolve attachment is the same as the previous pass, merge
         // Else, don't merge.
 
-        let mut physical_passes: Vec<PhysicalPass> = Vec::new();
+        let mut physical_passes: Vec<PhysicalPass<'a, 'rb>> = Vec::new();
         for pass in scheduled_passes.iter() {
             fn merge_score<'a, 'rb>(pass: &'a PassNode<'a, 'rb>, physical_pass: &PhysicalPass<'a, 'rb>) -> usize {
                 // Calculate the merge score of a given pass and a physical pass
-                return pass.dependencies.iter()
+                return pass.dependencies.borrow().iter()
                     .fold(0, |score, dep| {
                         if !dep.requires_external_dep() && physical_pass.is_internal_dep(dep.pass_node) {
                             // If this is an internal dependency, increase score if it exists as a subpass in the physical pass
@@ -483,71 +1252,1124 @@ impl<'rb> RendererBuilder<'rb> {
             }
             // Try to find a physical pass which we can merge into 
             // This can only be done if all of the dependencies are met by the physical pass or its external dependencies
-            let mut merge_physical_pass = physical_passes.iter_mut()
-                .filter(
-                    |physical_pass| {
-                        // Find suitable physical passes for merging
-                        for dep in pass.dependencies.iter() {
-                            let in_subpasses = physical_pass.is_internal_dep(dep.pass_node);
-                            if dep.requires_external_dep() {
-                                // If this is an external dependency, do not merge if it's satisfied as a subpass in the physical pass
-                                if in_subpasses { 
-                                    return false;
-                                }
-                                // If this is an external dependency and it depends on any of the subpasses in the physical pass, do not merge
-                                if physical_pass.subpasses.iter().any(|subpass| dep.pass_node.depends_on(subpass)) {
-                                    return false;
-                                }
-                            } 
-                            if !dep.requires_external_dep() {
-                                // If this is an internal dependency, do not merge if it's not satisfied as a subpass dep
-                                if !in_subpasses {
-                                    return false;
-                                }
-                            }
+            fn can_merge<'a, 'rb>(pass: &'a PassNode<'a, 'rb>, physical_passes: &[PhysicalPass<'a, 'rb>], index: usize) -> bool {
+                let physical_pass = &physical_passes[index];
+                // Subpasses share a framebuffer, so must render at the same size
+                if physical_pass.subpasses[0].sized_like != pass.sized_like {
+                    return false;
+                }
+                for dep in pass.dependencies.borrow().iter() {
+                    let in_subpasses = physical_pass.is_internal_dep(dep.pass_node);
+                    if dep.requires_external_dep() {
+                        // If this is an external dependency, do not merge if it's satisfied as a subpass in the physical pass
+                        if in_subpasses { 
+                            return false;
+                        }
+                        // If this is an external dependency and it depends on any of the subpasses in the physical pass, do not merge
+                        if physical_pass.subpasses.iter().any(|subpass| dep.pass_node.depends_on(subpass)) {
+                            return false;
+                        }
+                        // Physical passes execute in list order, so the dependency must be in an earlier one
+                        let dep_index = physical_passes.iter().position(|x| x.is_internal_dep(dep.pass_node));
+                        if dep_index.map_or(true, |dep_index| dep_index > index) {
+                            return false;
+                        }
+                    } 
+                    if !dep.requires_external_dep() {
+                        // If this is an internal dependency, do not merge if it's not satisfied as a subpass dep
+                        if !in_subpasses {
+                            return false;
                         }
-                        return true;
                     }
-                ).max_by(|physical_pass_a, physical_pass_b| {
+                }
+                return true;
+            }
+
+            let merge_physical_pass = (0..physical_passes.len())
+                .filter(|&i| can_merge(pass, &physical_passes, i))
+                .max_by(|&a, &b| {
                     // Find the physical pass with the highest merge score
-                    return merge_score(pass, physical_pass_a).cmp(&merge_score(pass, physical_pass_b));
+                    return merge_score(pass, &physical_passes[a]).cmp(&merge_score(pass, &physical_passes[b]));
                 });
-            
+        
             match merge_physical_pass {
                 // If a mergeable physical pass exists, merge into it
-                Some(x) => {
-                    x.add_subpass(pass);
+                Some(i) => {
+                    physical_passes[i].add_subpass(pass);
                 },
                 // If no physical pass can be merged into, create a new one
-                None => continue
+                None => {
+                    let mut physical_pass = PhysicalPass {
+                        subpasses: Vec::new(),
+                        external_dependencies: Vec::new()
+                    };
+                    physical_pass.add_subpass(pass);
+                    physical_passes.push(physical_pass);
+                }
             }
         }
+        return physical_passes;
+    }
+
+    // Estimate the attachment memory of the graph with a backbuffer of `dimensions`, without creating
+    // any vulkan objects. Attachments take the size of the output target they are sized like, see
+    // set_target_dimensions. They use the format selected by the last build, or their declared
+    // format before the first, since formats are only selected for a device when the renderer is built.
+    pub fn estimate_memory(&self, dimensions: [u32; 2]) -> Result<MemoryEstimate, &'static str> {
+        let arena = Arena::new();
+        let pass_nodes = self.create_pass_nodes(&arena)?;
+        let scheduled_passes = RendererBuilder::schedule_passes(pass_nodes.iter())?;
+        let pass_count = scheduled_passes.len();
+
+        let is_used = |attachment: &AttachmentDesc| {
+            return !attachment.readers.is_empty() || !attachment.writers.is_empty();
+        };
+
+        let mut attachments = Vec::new();
+        // Schedule indices each allocated attachment is alive for
+        let mut lifetimes: Vec<(u64, Range<usize>)> = Vec::new();
+        for attachment in self.attachments.iter() {
+            // History attachments get an image whenever the attachment they follow does
+            let allocated = !attachment.is_target
+                && attachment.import.is_none()
+                && is_used(attachment.history_of.map_or(attachment, |x| self.attachment(x)));
+            // History attachments copy the attachment they follow, whose format may have changed since
+            let format = attachment.history_of.map_or(attachment.format, |x| self.attachment(x).format);
+            let target = self.attachment(self.attachment_size(attachment.id));
+            let attachment_dimensions = if target.id == self.get_backbuffer_attachment() {
+                dimensions
+            } else {
+                target.estimate_dimensions.unwrap_or(dimensions)
+            };
+            let bytes = image_bytes(format, attachment.samples, attachment_dimensions);
+
+            if allocated {
+                let uses: Vec<usize> = scheduled_passes.iter()
+                    .enumerate()
+                    .filter(|(_, x)| x.pass.uses_image(attachment.id))
+                    .map(|(i, _)| i)
+                    .collect();
+                // Contents kept between frames live for the whole frame, and read back ones until its end
+                let lifetime = if attachment.history_of.is_some() || attachment.has_history {
+                    0..pass_count
+                } else if attachment.usage.transfer_source {
+                    uses.first().cloned().unwrap_or(0)..pass_count
+                } else {
+                    uses.first().cloned().unwrap_or(0)..uses.last().map_or(0, |x| x + 1)
+                };
+                lifetimes.push((bytes, lifetime));
+            }
+
+            debug!(
+                target: BUILD_LOG_TARGET,
+                "attachment={} dimensions={:?} bytes={} allocated={}",
+                attachment.name, attachment_dimensions, bytes, allocated
+            );
+            attachments.push(AttachmentMemory {
+                name: attachment.name,
+                format,
+                samples: attachment.samples,
+                dimensions: attachment_dimensions,
+                bytes,
+                allocated
+            });
+        }
+
+        let passes: Vec<PassMemory> = scheduled_passes.iter()
+            .enumerate()
+            .map(|(i, pass_node)| {
+                return PassMemory {
+                    pass: pass_node.pass.name,
+                    live_bytes: lifetimes.iter()
+                        .filter(|(_, lifetime)| lifetime.contains(&i))
+                        .map(|(bytes, _)| bytes)
+                        .sum()
+                };
+            })
+            .collect();
+
+        let estimate = MemoryEstimate {
+            dimensions,
+            total_bytes: lifetimes.iter().map(|(bytes, _)| bytes).sum(),
+            aliased_total_bytes: passes.iter().map(|x| x.live_bytes).max().unwrap_or(0),
+            attachments,
+            passes,
+            budget: self.memory_budget.map(|x| x.0)
+        };
+        info!(
+            target: BUILD_LOG_TARGET,
+            "memory dimensions={:?} total_bytes={} aliased_total_bytes={}",
+            dimensions, estimate.total_bytes, estimate.aliased_total_bytes
+        );
+        // The renderer doesn't alias attachments yet, so exceeding only the non-aliased total still warns
+        if estimate.exceeds_budget_with_aliasing() {
+            warn!(
+                target: BUILD_LOG_TARGET,
+                "attachment memory exceeds budget even with aliasing: total_bytes={} aliased_total_bytes={} budget={}",
+                estimate.total_bytes, estimate.aliased_total_bytes, estimate.budget.unwrap()
+            );
+        } else if estimate.exceeds_budget() {
+            warn!(
+                target: BUILD_LOG_TARGET,
+                "attachment memory exceeds budget without aliasing: total_bytes={} aliased_total_bytes={} budget={}",
+                estimate.total_bytes, estimate.aliased_total_bytes, estimate.budget.unwrap()
+            );
+        }
+        return Ok(estimate);
+    }
+
+    // Estimate memory at the budget's dimensions if a budget is set, which warns if it's exceeded
+    fn check_memory_budget(&self) -> Result<Option<MemoryEstimate>, &'static str> {
+        return match self.memory_budget {
+            Some((_, dimensions)) => self.estimate_memory(dimensions).map(Some),
+            None => Ok(None)
+        };
+    }
+
+    // Render pass descriptions of the physical passes, which execute in order
+    fn render_pass_descs<'a, 'rb>(&self, physical_passes: &[PhysicalPass<'a, 'rb>]) -> Vec<GraphRenderPassDesc> {
+        let mut written_attachments: Vec<AttachmentId> = Vec::new();
+        let mut descs = Vec::new();
+        for (i, physical_pass) in physical_passes.iter().enumerate() {
+            let later_attachments: Vec<_> = physical_passes[i + 1..].iter()
+                .flat_map(|x| x.attachments())
+                .collect();
+            descs.push(physical_pass.render_pass_desc(self, &written_attachments, &later_attachments));
+
+            for pass in physical_pass.subpasses.iter().map(|x| x.pass) {
+                for output in pass.color_outputs.iter().cloned().chain(pass.depth_output) {
+                    if !written_attachments.contains(&output) {
+                        written_attachments.push(output);
+                    }
+                }
+            }
+        }
+        return descs;
+    }
+
+    // Formats are selected for `device` on every build, so a builder can be edited and built again
+    pub fn build(&mut self, device: Arc<Device>, pipeline_cache: &mut PipelineCache) -> Result<Renderer, &'static str> {
+        let _build_span = BuildSpan::enter("build");
+
+        // Validate
+        // TODO: handle this error properly
+        {
+            let _span = BuildSpan::enter("validate");
+            self.select_formats(|format, usage, samples| is_format_supported(device.physical_device(), format, usage, samples))?;
+            self.validate_passes()?;
+        }
+        let builder: &RendererBuilder = self;
+
+        {
+            // Formats are selected by now, so the estimate matches the images build creates
+            let _span = BuildSpan::enter("budget");
+            builder.check_memory_budget()?;
+        }
+
+        let pass_node_arena = Arena::new();
+        let pass_nodes = {
+            let _span = BuildSpan::enter("create_nodes");
+            builder.create_pass_nodes(&pass_node_arena)?
+        };
+
+        // Schedule passes
+        let scheduled_passes = {
+            let _span = BuildSpan::enter("schedule");
+            RendererBuilder::schedule_passes(pass_nodes.iter())?
+        };
+        for (i, pass_node) in scheduled_passes.iter().enumerate() {
+            pass_node.display(i);
+        }
+
+        // Merge passes based on a set of criteria
+        let physical_passes = {
+            let _span = BuildSpan::enter("merge");
+            RendererBuilder::merge_passes(&scheduled_passes)
+        };
+        for (i, physical_pass) in physical_passes.iter().enumerate() {
+            let subpasses: Vec<&str> = physical_pass.subpasses.iter().map(|x| x.pass.name).collect();
+            debug!(target: BUILD_LOG_TARGET, "render_pass index={} subpasses={:?}", i, subpasses);
+        }
+
+        let render_pass_descs = builder.render_pass_descs(&physical_passes);
+
+        if let Some(path) = builder.graph_report_path.as_ref() {
+            let _span = BuildSpan::enter("report");
+            let report = graph_report::graph_report(builder, &scheduled_passes, &physical_passes, &render_pass_descs);
+            fs::write(path, report).map_err(|_| "Failed to write graph report")?;
+            info!(target: BUILD_LOG_TARGET, "graph report written to {}", path.display());
+        }
+
+        // Create vulkan resources
+        let _allocate_span = BuildSpan::enter("allocate");
+
+        let vertex_input = &builder.default_vertex_input;
+        let mut render_passes: Vec<PhysicalRenderPass> = Vec::new();
+        let mut attachments: Vec<PhysicalAttachment> = Vec::new();
+        let mut imported_attachments: Vec<ImportedAttachment> = Vec::new();
+        for (physical_pass, render_pass_desc) in physical_passes.iter().zip(render_pass_descs.into_iter()) {
+            let pass_attachments = physical_pass.attachments();
+            for attachment in pass_attachments.iter().map(|x| builder.attachment(*x)) {
+                if attachment.import.is_some() && !imported_attachments.iter().any(|x| x.name == attachment.name) {
+                    imported_attachments.push(ImportedAttachment {
+                        name: attachment.name.to_string(),
+                        format: attachment.format,
+                        samples: attachment.samples as u32,
+                        usage: attachment.usage,
+                        sized_like: builder.attachment(attachment.sized_like).name.to_string()
+                    });
+                }
+                if !attachment.is_target
+                    && attachment.import.is_none()
+                    && attachment.history_of.is_none()
+                    && !attachments.iter().any(|x| x.name == attachment.name)
+                {
+                    attachments.push(PhysicalAttachment {
+                        name: attachment.name.to_string(),
+                        format: attachment.format,
+                        samples: attachment.samples as u32,
+                        usage: attachment.usage,
+                        history: None,
+                        sized_like: builder.attachment(attachment.sized_like).name.to_string()
+                    });
+                }
+            }
+
+            let clear_values = render_pass_desc.clear_values();
+            render_pass_desc.check_clear_values(&clear_values)?;
+            let render_pass = Arc::new(
+                RenderPass::new(device.clone(), render_pass_desc)
+                    .map_err(|_| "Failed to create render pass")?
+            ) as Arc<dyn RenderPassAbstract + Send + Sync>;
+
+            let mut subpasses: Vec<PhysicalSubpass> = Vec::new();
+            for (i, subpass) in physical_pass.subpasses.iter().enumerate() {
+                let pass = subpass.pass;
+                let factory = pass.pipeline_factory.as_ref().ok_or("Pass has no pipeline")?;
+                let state = &pass.pipeline_state;
+                let vulkan_subpass = Subpass::from(render_pass.clone(), i as u32).unwrap();
+
+                // One pipeline per permutation of shaders, state and vertex input
+                let pipeline_key = format!("{}|{:?}|{:?}", pass.shader_permutation, state, vertex_input);
+                let pipeline = pipeline_cache.get_or_create(&pipeline_key, &render_pass, i as u32, || {
+                    return factory(
+                        device.clone(),
+                        vulkan_subpass.clone(),
+                        vertex_input.clone(),
+                        state
+                    );
+                })?;
+
+                // Named material bindings must exist in the reflected pipeline layout
+                let mut material_bindings = HashMap::new();
+                for (name, set, binding) in pass.material_bindings.iter() {
+                    if pipeline.descriptor(*set, *binding).is_none() {
+                        return Err("Material binding is not declared by the pass's shaders.");
+                    }
+                    material_bindings.insert(name.to_string(), (*set, *binding));
+                }
+
+                subpasses.push(PhysicalSubpass {
+                    name: pass.name.to_string(),
+                    pipeline,
+                    dynamic_states: state.dynamic_states,
+                    material_bindings,
+                    executor: None,
+                    parallel_executor: None,
+                    per_view: pass.per_view,
+                    draws: Vec::new()
+                });
+            }
+
+            render_passes.push(PhysicalRenderPass {
+                render_pass,
+                attachments: pass_attachments.iter().map(|x| builder.attachment(*x).name.to_string()).collect(),
+                clear_values,
+                sized_like: builder.attachment(physical_pass.subpasses[0].sized_like).name.to_string(),
+                subpasses
+            });
+        }
+
+        // History attachments share the double-buffered images of the attachment they follow, which
+        // are cleared before first use
+        for history in builder.attachments.iter() {
+            if let Some(current) = history.history_of.map(|x| builder.attachment(x)) {
+                let physical = attachments.iter_mut()
+                    .find(|x| x.name == current.name)
+                    .ok_or("History attachment of an attachment that no pass uses.")?;
+                physical.usage = physical.usage | history.usage | ImageUsage {
+                    transfer_destination: true,
+                    ..ImageUsage::none()
+                };
+                physical.history = Some(history.name.to_string());
+            }
+        }
+
+        let frames_in_flight = builder.frames_in_flight;
+        if frames_in_flight == 0 {
+            return Err("At least one frame must be allowed in flight.");
+        }
 
         return Ok(
             Renderer {
-
+                device,
+                targets: builder.attachments.iter()
+                    .filter(|x| x.is_target)
+                    .map(|x| (x.name.to_string(), x.format, x.usage))
+                    .collect(),
+                views: HashMap::new(),
+                attachments,
+                imported_attachments,
+                imported_buffers: builder.imported_buffers.iter().map(|x| x.to_string()).collect(),
+                render_passes,
+                vertex_input: vertex_input.clone(),
+                attachment_images: HashMap::new(),
+                buffers: HashMap::new(),
+                uninitialized_history: Vec::new(),
+                pending_readbacks: Vec::new(),
+                recorded_readbacks: Vec::new(),
+                queue: None,
+                frames_in_flight,
+                recording_threads: thread::available_parallelism().map_or(1, |x| x.get()),
+                recording_pool: None,
+                frame_count: 0,
+                current_frame: None,
+                frame_futures: (0..frames_in_flight).map(|_| None).collect(),
+                last_frame_future: None
             }
         );
     }
 }
 
+// A region of an output target rendered with its own camera, e.g. one player's half of the screen.
+// The origin and dimensions are fractions of the target's size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    pub origin: [f32; 2],
+    pub dimensions: [f32; 2]
+}
+
+impl View {
+    // The view of targets that aren't split
+    pub fn full() -> View {
+        return View {
+            origin: [0.0, 0.0],
+            dimensions: [1.0, 1.0]
+        };
+    }
+}
+
+impl PhysicalSubpass {
+    // Dynamic state covering `view` of a framebuffer of `dimensions`, for the state the pipeline left dynamic
+    fn dynamic_state(&self, dimensions: [f32; 2], view: &View) -> DynamicState {
+        let origin = [view.origin[0] * dimensions[0], view.origin[1] * dimensions[1]];
+        let dimensions = [view.dimensions[0] * dimensions[0], view.dimensions[1] * dimensions[1]];
+        return DynamicState {
+            viewports: Some(vec![
+                Viewport {
+                    origin,
+                    dimensions,
+                    depth_range: 0.0 .. 1.0
+                }
+            ]),
+            scissors: if self.dynamic_states.scissor {
+                Some(vec![
+                    Scissor {
+                        origin: [origin[0] as i32, origin[1] as i32],
+                        dimensions: [dimensions[0] as u32, dimensions[1] as u32]
+                    }
+                ])
+            } else {
+                None
+            },
+            line_width: if self.dynamic_states.line_width {
+                Some(1.0)
+            } else {
+                None
+            },
+            ..DynamicState::none()
+        };
+    }
+}
+
+// Signals when a frame submitted with execute_frame has finished on the GPU
+pub type FrameFuture = FenceSignalFuture<Box<dyn GpuFuture>>;
+
+type DrawCommand = dyn Fn(&mut AutoCommandBufferBuilder, &DynamicState) -> Result<(), &'static str> + Send;
+
+struct PhysicalSubpass {
+    name: String,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    dynamic_states: DynamicStates,
+    material_bindings: HashMap<String, (usize, usize)>,
+    executor: Option<Box<dyn PassExecutor>>,
+    // Recorded into secondary command buffers instead of inline when set
+    parallel_executor: Option<Arc<dyn ParallelPassExecutor>>,
+    // Whether the executor runs once per view of the output target
+    per_view: bool,
+    // Draws submitted since the subpass was last recorded
+    draws: Vec<Box<DrawCommand>>
+}
+
+struct PhysicalRenderPass {
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    // Attachment names in render pass attachment order
+    attachments: Vec<String>,
+    clear_values: Vec<ClearValue>,
+    // Output target the framebuffer is sized like
+    sized_like: String,
+    subpasses: Vec<PhysicalSubpass>
+}
+
+// An attachment whose image is owned by the renderer, sized to match the backbuffer
+struct PhysicalAttachment {
+    name: String,
+    format: Format,
+    samples: u32,
+    usage: ImageUsage,
+    // Name of the history attachment holding last frame's contents, if any
+    history: Option<String>,
+    // Output target whose size the images take
+    sized_like: String
+}
+
+// An attachment whose image is set by the user with set_imported_image
+struct ImportedAttachment {
+    name: String,
+    format: Format,
+    samples: u32,
+    // What the graph uses the image for
+    usage: ImageUsage,
+    // Output target the image must match the size of
+    sized_like: String
+}
+
 pub struct Renderer {
+    device: Arc<Device>,
+    // Output targets with the formats the render passes were built for and the usage the graph needs,
+    // starting with the backbuffer
+    targets: Vec<(String, Format, ImageUsage)>,
+    // Views of each output target that has more than the one covering it
+    views: HashMap<String, Vec<View>>,
+    attachments: Vec<PhysicalAttachment>,
+    imported_attachments: Vec<ImportedAttachment>,
+    imported_buffers: Vec<String>,
+    render_passes: Vec<PhysicalRenderPass>,
+    // Vertex bindings every pass's pipeline was built with
+    vertex_input: VertexInputDesc,
+    // Images backing the attachments for the current backbuffer size, including the backbuffer
+    attachment_images: HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>>,
+    // Imported buffers that have been set
+    buffers: HashMap<String, Arc<dyn BufferAccess + Send + Sync>>,
+    // Freshly created history images, cleared at the start of the next frame recorded
+    uninitialized_history: Vec<(Arc<AttachmentImage>, Format)>,
+    // Attachments to copy back at the end of the next frame recorded, and copies recorded but not
+    // yet submitted
+    pending_readbacks: Vec<(String, Readback)>,
+    recorded_readbacks: Vec<Readback>,
+    // Queue the last frame was submitted to
+    queue: Option<Arc<Queue>>,
+
+    frames_in_flight: usize,
+    recording_threads: usize,
+    // Started when a parallel pass is first recorded
+    recording_pool: Option<RecordingPool>,
+    frame_count: u64,
+    // Slot of the frame being recorded, once something has been submitted for it
+    current_frame: Option<usize>,
+    // Submission of the last frame recorded in each slot. A slot's resources are reused only
+    // after its fence signals.
+    frame_futures: Vec<Option<Arc<FrameFuture>>>,
+    last_frame_future: Option<Arc<FrameFuture>>
+}
+
+impl Renderer {
+    pub fn frames_in_flight(&self) -> usize {
+        return self.frames_in_flight;
+    }
+
+    // Slot of the frame being recorded. Entering a new frame waits until the GPU is done with the
+    // last frame recorded in the same slot.
+    pub fn begin_frame(&mut self) -> Result<usize, &'static str> {
+        if let Some(frame) = self.current_frame {
+            return Ok(frame);
+        }
+
+        for future in self.frame_futures.iter_mut().flatten() {
+            future.cleanup_finished();
+        }
+
+        let frame = (self.frame_count % self.frames_in_flight as u64) as usize;
+        if let Some(future) = self.frame_futures[frame].take() {
+            future.wait(None).map_err(|_| "Failed to wait for frame in flight")?;
+        }
+
+        self.current_frame = Some(frame);
+        return Ok(frame);
+    }
+
+    pub fn pipeline(&self, pass_name: &str) -> Option<Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+        return self.render_passes.iter()
+            .flat_map(|x| x.subpasses.iter())
+            .find(|x| x.name == pass_name)
+            .map(|x| x.pipeline.clone());
+    }
+
+    pub fn render_pass(&self, pass_name: &str) -> Option<Arc<dyn RenderPassAbstract + Send + Sync>> {
+        return self.render_passes.iter()
+            .find(|x| x.subpasses.iter().any(|subpass| subpass.name == pass_name))
+            .map(|x| x.render_pass.clone());
+    }
+
+    pub fn material_bindings(&self, pass_name: &str) -> Option<&HashMap<String, (usize, usize)>> {
+        return self.render_passes.iter()
+            .flat_map(|x| x.subpasses.iter())
+            .find(|x| x.name == pass_name)
+            .map(|x| &x.material_bindings);
+    }
+
+    fn subpass_mut(&mut self, pass_name: &str) -> Option<&mut PhysicalSubpass> {
+        return self.render_passes.iter_mut()
+            .flat_map(|x| x.subpasses.iter_mut())
+            .find(|x| x.name == pass_name);
+    }
+
+    // Queue a draw of a mesh into the material's pass. The instance buffers are bound after the
+    // mesh's vertex buffers, matching the per-instance bindings of the render config.
+    pub fn draw<U, Pc>(
+        &mut self,
+        mesh: &Mesh,
+        material: &Material<U, Pc>,
+        instance_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>>
+    ) -> Result<(), &'static str>
+        where
+            U: Copy + Send + Sync + 'static,
+            Pc: Copy + Send + Sync + 'static
+    {
+        let vertex_buffers: Vec<_> = mesh.vertex_buffers().iter()
+            .cloned()
+            .chain(instance_buffers)
+            .collect();
+        self.vertex_input.check_buffer_count(vertex_buffers.len())?;
+
+        let frame = self.begin_frame()?;
+        let frame_count = self.frame_count;
+        let subpass = self.subpass_mut(material.pass_name()).ok_or("Material refers to an unknown pass.")?;
+        if !Arc::ptr_eq(&subpass.pipeline, material.pipeline()) {
+            return Err("Material was created for a different renderer.");
+        }
+
+        let pipeline = subpass.pipeline.clone();
+        let descriptor_sets = material.descriptor_sets(frame, frame_count)?;
+        let push_constants = material.push_constants();
+
+        // Per-view passes record the draw once per view
+        subpass.draws.push(Box::new(move |cmd_buf_builder, dynamic_state| {
+            cmd_buf_builder
+                .draw(pipeline.clone(), dynamic_state, vertex_buffers.clone(), descriptor_sets.clone(), push_constants)
+                .map_err(|_| "Failed to record draw")?;
+            return Ok(());
+        }));
+
+        return Ok(());
+    }
+
+    // Register what a pass draws each frame. Replaces any executor already set for the pass.
+    pub fn set_pass_executor<E>(&mut self, pass_name: &str, executor: E) -> Result<(), &'static str>
+        where E: PassExecutor + 'static
+    {
+        let subpass = self.subpass_mut(pass_name).ok_or("Unknown pass.")?;
+        subpass.executor = Some(Box::new(executor));
+        subpass.parallel_executor = None;
+        return Ok(());
+    }
+
+    // Like set_pass_executor, but the pass is recorded in chunks on the renderer's recording
+    // threads. Draws queued for the pass are recorded after the chunks.
+    pub fn set_parallel_pass_executor<E>(&mut self, pass_name: &str, executor: E) -> Result<(), &'static str>
+        where E: ParallelPassExecutor + 'static
+    {
+        let subpass = self.subpass_mut(pass_name).ok_or("Unknown pass.")?;
+        // Secondary command buffers are recorded before the views are known
+        if subpass.per_view {
+            return Err("Passes run per view need a serial pass executor.");
+        }
+        subpass.executor = None;
+        subpass.parallel_executor = Some(Arc::new(executor));
+        return Ok(());
+    }
+
+    // Threads used to record passes with a parallel executor. Defaults to the number of CPUs. The
+    // threads are started when a parallel pass is first recorded and kept until the renderer is
+    // dropped or this is called with another count.
+    pub fn set_recording_threads(&mut self, thread_count: usize) {
+        let thread_count = thread_count.max(1);
+        if thread_count != self.recording_threads {
+            self.recording_threads = thread_count;
+            self.recording_pool = None;
+        }
+    }
+
+    // Back an imported attachment with an image owned elsewhere, e.g. a swapchain image or a
+    // storage image written by a compute pass. It must match the backbuffer's size when recorded.
+    pub fn set_imported_image(&mut self, name: &str, image: Arc<dyn ImageViewAccess + Send + Sync>) -> Result<(), &'static str> {
+        let attachment = self.imported_attachments.iter()
+            .find(|x| x.name == name)
+            .ok_or("Unknown imported attachment.")?;
+        if image.format() != attachment.format || image.samples() != attachment.samples {
+            return Err("Imported image does not match the attachment's format and samples.");
+        }
+
+        let view = image.inner();
+        if (attachment.usage.color_attachment && !view.usage_color_attachment())
+            || (attachment.usage.depth_stencil_attachment && !view.usage_depth_stencil_attachment())
+            || (attachment.usage.input_attachment && !view.usage_input_attachment())
+        {
+            return Err("Imported image was not created with the usage its passes need.");
+        }
+
+        self.attachment_images.insert(name.to_string(), image);
+        return Ok(());
+    }
+
+    // The image to render an output target added with add_output_target into this frame. The
+    // backbuffer is passed to execute_frame instead.
+    pub fn set_target_image(&mut self, name: &str, image: Arc<dyn ImageViewAccess + Send + Sync>) -> Result<(), &'static str> {
+        if name == BACKBUFFER_NAME || !self.targets.iter().any(|x| x.0 == name) {
+            return Err("Unknown output target.");
+        }
+        self.attachment_images.insert(name.to_string(), image);
+        return Ok(());
+    }
+
+    // Split an output target into views, each rendered by the target's per-view passes with its
+    // own viewport. Executors tell views apart by PassContext::view, e.g. to pick a camera.
+    pub fn set_views(&mut self, target: &str, views: Vec<View>) -> Result<(), &'static str> {
+        if !self.targets.iter().any(|x| x.0 == target) {
+            return Err("Unknown output target.");
+        }
+        if views.is_empty() {
+            return Err("An output target needs at least one view.");
+        }
+        self.views.insert(target.to_string(), views);
+        return Ok(());
+    }
+
+    pub fn set_imported_buffer(&mut self, name: &str, buffer: Arc<dyn BufferAccess + Send + Sync>) -> Result<(), &'static str> {
+        if !self.imported_buffers.iter().any(|x| x == name) {
+            return Err("Unknown imported buffer.");
+        }
+        self.buffers.insert(name.to_string(), buffer);
+        return Ok(());
+    }
+
+    // Copy an attachment back to the CPU at the end of the next frame recorded. Attachments owned
+    // by the renderer must have readback enabled in the render config.
+    pub fn read_attachment(&mut self, name: &str) -> Result<Readback, &'static str> {
+        let known = self.targets.iter().any(|x| x.0 == name)
+            || self.imported_attachments.iter().any(|x| x.name == name)
+            || self.attachments.iter().any(|x| x.name == name || x.history.as_ref().map_or(false, |history| history == name));
+        if !known {
+            return Err("Unknown attachment.");
+        }
+
+        let readback = Readback::new();
+        self.pending_readbacks.push((name.to_string(), readback.clone()));
+        return Ok(readback);
+    }
+
+    // Save the backbuffer of the last frame submitted with execute_frame, converted to RGBA8
+    pub fn screenshot<P: AsRef<Path>>(&mut self, path: P) -> Result<(), &'static str> {
+        let queue = self.queue.clone().ok_or("No frame has been submitted.")?;
+        let backbuffer = self.attachment_images.get(BACKBUFFER_NAME)
+            .cloned()
+            .ok_or("No frame has been submitted.")?;
+
+        let readback = Readback::new();
+        let mut cmd_buf_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            queue.family()
+        ).map_err(|_| "Failed to create command buffer")?;
+        readback.record(self.device.clone(), &mut cmd_buf_builder, backbuffer)?;
+        let cmd_buf = cmd_buf_builder.build().map_err(|_| "Failed to build command buffer")?;
+
+        let previous = match self.last_frame_future.clone() {
+            Some(last_frame_future) => Box::new(last_frame_future) as Box<dyn GpuFuture>,
+            None => vulkano::sync::now(self.device.clone()).boxed()
+        };
+        let future = Arc::new(
+            previous
+                .then_execute(queue, cmd_buf)
+                .map_err(|_| "Failed to execute command buffer")?
+                .boxed()
+                .then_signal_fence_and_flush()
+                .map_err(|_| "Failed to submit screenshot copy")?
+        );
+
+        // Later frames write the backbuffer again, so they must wait for the copy
+        self.last_frame_future = Some(future.clone());
+        readback.set_future(future);
+        return readback.save(path);
+    }
 
+    // Size of an output target's image this frame
+    fn target_dimensions(&self, target: &str) -> Result<[u32; 2], &'static str> {
+        let image = self.attachment_images.get(target).ok_or("Output target has no image.")?;
+        return Ok(image.dimensions().width_height());
+    }
+
+    // Output targets and imported resources must be set before a frame is recorded
+    fn check_imports(&self) -> Result<(), &'static str> {
+        for (name, format, usage) in self.targets.iter() {
+            let image = self.attachment_images.get(name).ok_or("Output target has no image.")?;
+            if image.parent().format() != *format {
+                return Err("Output target format does not match the format the renderer was built for.");
+            }
+            // Swapchains only have the usage their surface supports, see swapchain::swapchain_usage
+            if usage.input_attachment && !image.inner().usage_input_attachment() {
+                return Err("Output target image was not created with the input attachment usage its passes need.");
+            }
+        }
+        for attachment in self.imported_attachments.iter() {
+            let image = self.attachment_images.get(&attachment.name).ok_or("Imported attachment has no image.")?;
+            if image.dimensions().width_height() != self.target_dimensions(&attachment.sized_like)? {
+                return Err("Imported image does not match the size of its output target.");
+            }
+        }
+        if self.imported_buffers.iter().any(|x| !self.buffers.contains_key(x)) {
+            return Err("Imported buffer has not been set.");
+        }
+        return Ok(());
+    }
+
+    // (Re)create the renderer's attachment images whose output target changed size
+    fn prepare_attachments(&mut self, backbuffer: Arc<dyn ImageViewAccess + Send + Sync>) -> Result<(), &'static str> {
+        self.attachment_images.insert(BACKBUFFER_NAME.to_string(), backbuffer);
+        self.check_imports()?;
+
+        let device = &self.device;
+        for attachment in self.attachments.iter() {
+            let dimensions = self.target_dimensions(&attachment.sized_like)?;
+            let resized = self.attachment_images.get(&attachment.name)
+                .map_or(true, |x| x.dimensions().width_height() != dimensions);
+            if !resized {
+                continue;
+            }
+
+            let create_image = || {
+                return AttachmentImage::multisampled_with_usage(
+                    device.clone(),
+                    dimensions,
+                    attachment.samples,
+                    attachment.format,
+                    attachment.usage
+                ).map_err(|_| "Failed to create attachment image");
+            };
+
+            let image = create_image()?;
+            self.attachment_images.insert(attachment.name.clone(), image as Arc<dyn ImageViewAccess + Send + Sync>);
+
+            if let Some(history) = attachment.history.as_ref() {
+                let history_image = create_image()?;
+                self.uninitialized_history.push((history_image.clone(), attachment.format));
+                self.attachment_images.insert(history.clone(), history_image as Arc<dyn ImageViewAccess + Send + Sync>);
+            }
+        }
+        return Ok(());
+    }
+
+    // This frame's attachments become next frame's history
+    fn swap_history(&mut self) {
+        for attachment in self.attachments.iter() {
+            if let Some(history) = attachment.history.as_ref() {
+                let current = self.attachment_images.remove(&attachment.name).unwrap();
+                let previous = self.attachment_images.insert(history.clone(), current).unwrap();
+                self.attachment_images.insert(attachment.name.clone(), previous);
+            }
+        }
+    }
+
+    // Record every pass of the graph into a primary command buffer, rendering into `backbuffer`.
+    // Each subpass runs its executor then the material draws queued for it, once per view of its
+    // output target for per-view passes. Subpasses with a
+    // parallel executor are recorded into secondary command buffers for `queue_family`.
+    pub fn record_frame(
+        &mut self,
+        queue_family: QueueFamily,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder,
+        backbuffer: Arc<dyn ImageViewAccess + Send + Sync>
+    ) -> Result<(), &'static str> {
+        self.prepare_attachments(backbuffer)?;
+
+        // There is no previous frame for new history images to hold
+        for (image, format) in self.uninitialized_history.drain(..) {
+            cmd_buf_builder
+                .clear_color_image(image, clear_value(format))
+                .map_err(|_| "Failed to clear history attachment")?;
+        }
+
+        let device = &self.device;
+        let recording_threads = self.recording_threads;
+        let recording_pool = &mut self.recording_pool;
+        let attachment_images = &self.attachment_images;
+        let buffers = &self.buffers;
+        let full_view = [View::full()];
+        for render_pass in self.render_passes.iter_mut() {
+            let views = self.views.get(&render_pass.sized_like).map_or(&full_view[..], |x| &x[..]);
+            let images = render_pass.attachments.iter()
+                .map(|x| attachment_images.get(x).cloned().ok_or("Attachment has no image."))
+                .collect::<Result<Vec<_>, _>>()?;
+            let framebuffer = GraphFramebuffer::new(render_pass.render_pass.clone(), &images)?;
+            let dimensions = [framebuffer.width() as f32, framebuffer.height() as f32];
+
+            // Whether a subpass's contents come from secondary command buffers is fixed when it begins
+            let first_secondary = render_pass.subpasses.first().map_or(false, |x| x.parallel_executor.is_some());
+            cmd_buf_builder
+                .begin_render_pass(framebuffer, first_secondary, render_pass.clear_values.clone())
+                .map_err(|_| "Failed to begin render pass")?;
+
+            for (i, subpass) in render_pass.subpasses.iter_mut().enumerate() {
+                if i > 0 {
+                    cmd_buf_builder
+                        .next_subpass(subpass.parallel_executor.is_some())
+                        .map_err(|_| "Failed to begin subpass")?;
+                }
+
+                let dynamic_state = subpass.dynamic_state(dimensions, &full_view[0]);
+                if let Some(executor) = subpass.parallel_executor.as_ref() {
+                    // Chunks are recorded on other threads, so they get their own copies
+                    let resources = SubpassResources {
+                        pass_name: subpass.name.clone(),
+                        subpass: Subpass::from(render_pass.render_pass.clone(), i as u32).ok_or("Subpass out of range.")?,
+                        pipeline: subpass.pipeline.clone(),
+                        dynamic_state: dynamic_state.clone(),
+                        attachments: attachment_images.clone(),
+                        buffers: buffers.clone()
+                    };
+                    let draws = subpass.draws.drain(..).collect();
+                    let command_buffers = pass_executor::record_secondaries(
+                        recording_pool.get_or_insert_with(|| RecordingPool::new(recording_threads)),
+                        device,
+                        queue_family,
+                        resources,
+                        executor,
+                        draws
+                    )?;
+                    for command_buffer in command_buffers {
+                        // The secondaries were recorded for this subpass and only touch its attachments, which
+                        // the render pass synchronizes. Vulkano doesn't track anything else they use, see
+                        // ParallelPassExecutor.
+                        unsafe {
+                            cmd_buf_builder
+                                .execute_commands(command_buffer)
+                                .map_err(|_| "Failed to execute secondary command buffer")?;
+                        }
+                    }
+                    continue;
+                }
+
+                let view_dynamic_states: Vec<DynamicState> = if subpass.per_view {
+                    views.iter().map(|x| subpass.dynamic_state(dimensions, x)).collect()
+                } else {
+                    vec![dynamic_state.clone()]
+                };
+                let draws: Vec<Box<DrawCommand>> = subpass.draws.drain(..).collect();
+                for (view, view_dynamic_state) in view_dynamic_states.iter().enumerate() {
+                    if let Some(executor) = subpass.executor.as_mut() {
+                        let mut context = PassContext::new(
+                            &subpass.name,
+                            view,
+                            cmd_buf_builder,
+                            &subpass.pipeline,
+                            view_dynamic_state,
+                            attachment_images,
+                            buffers
+                        );
+                        executor.execute(&mut context)?;
+                    }
+                    for draw in draws.iter() {
+                        draw(cmd_buf_builder, view_dynamic_state)?;
+                    }
+                }
+            }
+
+            cmd_buf_builder.end_render_pass().map_err(|_| "Failed to end render pass")?;
+        }
+
+        for (name, readback) in self.pending_readbacks.drain(..) {
+            let image = attachment_images.get(&name).cloned().ok_or("Attachment has no image.")?;
+            readback.record(self.device.clone(), cmd_buf_builder, image)?;
+            self.recorded_readbacks.push(readback);
+        }
+
+        self.swap_history();
+        return Ok(());
+    }
+
+    // Record and submit a whole frame after `previous`, which carries whatever the frame must wait
+    // on (e.g. swapchain image acquisition). Subpasses are synchronized by the render pass's subpass
+    // dependencies; between render passes, vulkano's command buffer builder inserts the barriers
+    // for the attachments and resources each pass uses.
+    // The frame is also ordered after the previous frame, which shares the renderer's attachments.
+    // Command buffers come from the device's standard pool and are recycled once their frame's
+    // future is cleaned up.
+    pub fn execute_frame(
+        &mut self,
+        queue: Arc<Queue>,
+        backbuffer: Arc<dyn ImageViewAccess + Send + Sync>,
+        previous: Box<dyn GpuFuture>
+    ) -> Result<Arc<FrameFuture>, &'static str> {
+        return self.submit_frame(queue, backbuffer, previous, |execution| Box::new(execution));
+    }
+
+    // Render into image `image_index` of `swapchain` and present it once the frame is done.
+    // `previous` must include the future of acquiring the image. Fails with
+    // swapchain::SWAPCHAIN_OUT_OF_DATE when the swapchain no longer matches its surface.
+    pub fn execute_frame_and_present<W: Send + Sync + 'static>(
+        &mut self,
+        queue: Arc<Queue>,
+        swapchain: Arc<Swapchain<W>>,
+        image_index: usize,
+        backbuffer: Arc<dyn ImageViewAccess + Send + Sync>,
+        previous: Box<dyn GpuFuture>
+    ) -> Result<Arc<FrameFuture>, &'static str> {
+        let present_queue = queue.clone();
+        return self.submit_frame(queue, backbuffer, previous, move |execution| {
+            return Box::new(execution.then_swapchain_present(present_queue, swapchain, image_index));
+        });
+    }
+
+    // Record a frame and submit it after `previous`. `then` appends work to the frame's execution,
+    // and the frame's fence signals after it.
+    fn submit_frame<F>(
+        &mut self,
+        queue: Arc<Queue>,
+        backbuffer: Arc<dyn ImageViewAccess + Send + Sync>,
+        previous: Box<dyn GpuFuture>,
+        then: F
+    ) -> Result<Arc<FrameFuture>, &'static str>
+        where
+            F: FnOnce(CommandBufferExecFuture<Box<dyn GpuFuture>, AutoCommandBuffer>) -> Box<dyn GpuFuture>
+    {
+        let frame = self.begin_frame()?;
+
+        let mut cmd_buf_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            queue.family()
+        ).map_err(|_| "Failed to create command buffer")?;
+
+        self.record_frame(queue.family(), &mut cmd_buf_builder, backbuffer)?;
+
+        let cmd_buf = cmd_buf_builder.build().map_err(|_| "Failed to build command buffer")?;
+
+        // The last frame's future stays until this frame is submitted, so if submission fails
+        // (e.g. the swapchain went out of date) the next frame still waits on it
+        let previous = match self.last_frame_future.clone() {
+            Some(last_frame_future) => Box::new(previous.join(last_frame_future)) as Box<dyn GpuFuture>,
+            None => previous
+        };
+
+        // Move on to the next frame even if submission fails, so the slot isn't left half used
+        self.current_frame = None;
+        self.frame_count += 1;
+        self.queue = Some(queue.clone());
+        let readbacks: Vec<_> = self.recorded_readbacks.drain(..).collect();
+
+        let submitted = previous
+            .then_execute(queue, cmd_buf)
+            .map_err(|_| "Failed to execute command buffer")
+            .and_then(|execution| {
+                return then(execution)
+                    .then_signal_fence_and_flush()
+                    .map_err(|err| {
+                        return match err {
+                            FlushError::OutOfDate => swapchain::SWAPCHAIN_OUT_OF_DATE,
+                            _ => "Failed to submit frame"
+                        };
+                    });
+            });
+        let future = match submitted {
+            Ok(future) => Arc::new(future),
+            Err(err) => {
+                // The copies were never submitted
+                for readback in readbacks {
+                    readback.fail(err);
+                }
+                return Err(err);
+            }
+        };
+
+        for readback in readbacks {
+            readback.set_future(future.clone());
+        }
+
+        self.frame_futures[frame] = Some(future.clone());
+        self.last_frame_future = Some(future.clone());
+        return Ok(future);
+    }
+
+    // Record the draws queued for a pass. The command buffer must be inside the pass's subpass.
+    pub fn record_draws(
+        &mut self,
+        pass_name: &str,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState
+    ) -> Result<(), &'static str> {
+        let subpass = self.subpass_mut(pass_name).ok_or("Unknown pass.")?;
+        for draw in subpass.draws.drain(..) {
+            draw(cmd_buf_builder, dynamic_state)?;
+        }
+        return Ok(());
+    }
 }
 
 #[macro_export]
 macro_rules! color_output {
     (backbuffer, $gfx_pass_name:ident, $builder:ident) => (
         let backbuffer = $builder.get_backbuffer_attachment();
-        $gfx_pass_name.add_color_output(backbuffer);
+        $builder.add_color_output($gfx_pass_name, backbuffer);
     );
     ($color_output_atch:ident, $gfx_pass_name:ident, $builder:ident) => (
-        $gfx_pass_name.add_color_output($color_output_atch);
+        $builder.add_color_output($gfx_pass_name, $color_output_atch);
+    );
+}
+
+#[macro_export]
+macro_rules! input_attachment {
+    ($input_attachment_atch:ident @ prev, $gfx_pass_name:ident, $builder:ident) => (
+        let history = $builder.add_history_attachment(
+            std::concat!(std::stringify!($input_attachment_atch), "@prev"),
+            $input_attachment_atch
+        );
+        $builder.add_input_attachment($gfx_pass_name, history);
+    );
+    ($input_attachment_atch:ident, $gfx_pass_name:ident, $builder:ident) => (
+        $builder.add_input_attachment($gfx_pass_name, $input_attachment_atch);
+    );
+}
+
+#[macro_export]
+macro_rules! sampled_input {
+    ($sampled_input_atch:ident @ prev, $gfx_pass_name:ident, $builder:ident) => (
+        let history = $builder.add_history_attachment(
+            std::concat!(std::stringify!($sampled_input_atch), "@prev"),
+            $sampled_input_atch
+        );
+        $builder.add_sampled_input($gfx_pass_name, history);
+    );
+    ($sampled_input_atch:ident, $gfx_pass_name:ident, $builder:ident) => (
+        $builder.add_sampled_input($gfx_pass_name, $sampled_input_atch);
     );
 }
 
 #[macro_export]
 macro_rules! attachment {
+    ($atch_name:ident, $builder:ident, $format:expr, $samples:literal, import: ($initial_layout:expr, $final_layout:expr)) => (
+        let $atch_name = $builder.import_attachment(std::stringify!($atch_name), $format, $samples, $initial_layout, $final_layout);
+    );
+    ($atch_name:ident, $builder:ident, $format:expr, import: ($initial_layout:expr, $final_layout:expr)) => (
+        let $atch_name = $builder.import_attachment(std::stringify!($atch_name), $format, 1, $initial_layout, $final_layout);
+    );
     ($atch_name:ident, $builder:ident, $format:expr, $samples:literal) => (
         let $atch_name = $builder.add_attachment(std::stringify!($atch_name), $format, $samples);
     );
@@ -556,18 +2378,81 @@ macro_rules! attachment {
     );
 }
 
+// Specialization constants of a shader stage. Stages without overrides use their defaults, which
+// is `()` for shaders that don't declare any.
+#[macro_export]
+macro_rules! specialization_constants {
+    ($($shader:ident)::+$(,)?) => (
+        Default::default()
+    );
+    ($($shader:ident)::+, $($name:ident: $value:expr),+) => (
+        $($shader::)+SpecializationConstants {
+            $($name: $value,)+
+            ..Default::default()
+        }
+    );
+}
+
+// One shader module per stage, all compiled with the same preprocessor defines
+#[doc(hidden)]
+#[macro_export]
+macro_rules! shader_modules {
+    ($defines:tt $($module:ident: $ty:tt $path:tt),*$(,)?) => (
+        $(
+            pub mod $module {
+                vulkano_shaders::shader!{
+                    ty: $ty,
+                    path: $path,
+                    define: $defines
+                }
+            }
+        )*
+    );
+}
+
+// A pass's shader modules: compiled for the pass, or re-exported from the pass named by
+// `shaders_from`, which must have the same shader paths and defines
+#[doc(hidden)]
+#[macro_export]
+macro_rules! pass_shader_modules {
+    ([$shader_source:ident] $sources:expr, $($modules:tt)*) => (
+        pub use super::$shader_source::*;
+    );
+    ([] $sources:expr, $defines:tt $($modules:tt)*) => (
+        pub const SHADER_SOURCES: &str = $sources;
+        $crate::shader_modules!{$defines $($modules)*}
+    );
+}
+
 #[macro_export(local_inner_macros)]
 macro_rules! render_config {
     {
         name: $render_config_name:ident,
+        $(
+            output_targets: {
+                $($target_name:ident: {format: $target_format:expr$(,)?}),*$(,)?
+            },
+        )?
         attachments: {
             $(
                 $atch_name:ident: {
                     format: $format:expr
                     $(,samples: $samples:literal)?
+                    $(,fallback_formats: [$($fallback_format:expr),*$(,)?])?
+                    $(,sized_like: $sized_like:ident)?
+                    $(,import: {
+                        initial_layout: $initial_layout:expr,
+                        final_layout: $final_layout:expr$(,)?
+                    })?
                 }
             ),*
         },
+        $(
+            imported_buffers: [$($imported_buffer:ident),*$(,)?],
+        )?
+        $(
+            readback_attachments: [$($readback_atch:ident),*$(,)?],
+        )?
         default_vertex_bindings: [
             $(
                 {
@@ -586,31 +2471,139 @@ macro_rules! render_config {
                 $gfx_pass_name:ident: {
                     color_outputs: [$($color_output_atch:ident),*], // Write only color output
                     depth_stencil_output: {$($depth_output_atch:ident)?},
-                    input_attachments: [$($input_attachment_atch:ident),*]$(,)* // Read only color input
+                    input_attachments: [$($input_attachment_atch:ident $(@ $input_attachment_history:ident)?),*]$(,)* // Read only color input
+                    $(sampled_inputs: [$($sampled_input_atch:ident $(@ $sampled_input_history:ident)?),*],)? // Sampled by the shaders
                     depth_stencil_input: {$($depth_input_atch:ident)?},
                     pipeline: {
                         shader_paths: {
-                            vertex: $vertex_path:literal$(,)?
-                            $(geometry: $geometry_path:literal,)?
-                            $(tess_ctrl: $tess_ctrl_path:literal,)?
-                            $(tess_eval: $tess_eval_path:literal,)?
-                            fragment: $fragment_path:literal
-                        }
+                            vertex: $vertex_path:literal $({$($vs_spec_name:ident: $vs_spec_value:expr),*$(,)?})?$(,)?
+                            $(geometry: $geometry_path:literal $({$($gs_spec_name:ident: $gs_spec_value:expr),*$(,)?})?,)?
+                            $(
+                                tess_ctrl: $tess_ctrl_path:literal $({$($tcs_spec_name:ident: $tcs_spec_value:expr),*$(,)?})?,
+                                tess_eval: $tess_eval_path:literal $({$($tes_spec_name:ident: $tes_spec_value:expr),*$(,)?})?,
+                            )?
+                            fragment: $fragment_path:literal $({$($fs_spec_name:ident: $fs_spec_value:expr),*$(,)?})?$(,)?
+                        }$(,)?
+                        $(
+                            defines: [$(($define_name:literal, $define_value:literal)),*$(,)?]$(,)?
+                        )?
+                        $(
+                            shaders_from: $shader_source:ident$(,)?
+                        )?
+                        $(
+                            material_bindings: {
+                                $($material_binding_name:ident: ($material_binding_set:literal, $material_binding_index:literal)),*$(,)?
+                            }$(,)?
+                        )?
+                        $(
+                            state: {
+                                $($state_field:ident: $state_value:expr),*$(,)?
+                            }$(,)?
+                        )?
                     }
                 }
             ),*
         }
     } => (
         mod $render_config_name {
+            #[allow(unused_imports)]
+            use super::*;
+            use std::sync::Arc;
             use vulkano::format::Format;
-            pub fn build() -> Result<crate::rendering::Renderer, &'static str> {
-                let builder = crate::rendering::RendererBuilder::new();
+            #[allow(unused_imports)]
+            use $crate::rendering::pipeline_state::*;
+
+            // Shaders of each pass, compiled with the pass's defines unless the pass shares
+            // those of another pass. The generated types (e.g. `gbuffer::fs::ty`) are the
+            // uniform and push constant blocks used by its materials.
+            $(
+                pub mod $gfx_pass_name {
+                    pass_shader_modules!{
+                        [$($shader_source)?]
+                        std::stringify!(
+                            $vertex_path
+                            $($geometry_path)?
+                            $($tess_ctrl_path $tess_eval_path)?
+                            $fragment_path
+                            $([$(($define_name, $define_value)),*])?
+                        ),
+                        [$($(($define_name, $define_value)),*)?]
+                        vs: "vertex" $vertex_path,
+                        $(gs: "geometry" $geometry_path,)?
+                        $(tcs: "tess_ctrl" $tess_ctrl_path, tes: "tess_eval" $tess_eval_path,)?
+                        fs: "fragment" $fragment_path
+                    }
+                }
+            )*
+
+            // Configs that adjust the builder before building may use neither build function
+            #[allow(dead_code)]
+            pub fn build(device: Arc<vulkano::device::Device>) -> Result<$crate::rendering::Renderer, &'static str> {
+                return build_with_cache(device, &mut $crate::rendering::pipeline_cache::PipelineCache::new());
+            }
+
+            #[allow(dead_code)]
+            pub fn build_with_cache(
+                device: Arc<vulkano::device::Device>,
+                pipeline_cache: &mut $crate::rendering::pipeline_cache::PipelineCache
+            ) -> Result<$crate::rendering::Renderer, &'static str> {
+                return builder()?.build(device, pipeline_cache);
+            }
+
+            // The graph described by the config, for adding to or building later
+            pub fn builder() -> Result<$crate::rendering::RendererBuilder, &'static str> {
+                let mut builder = $crate::rendering::RendererBuilder::new();
+                $($(
+                    let $target_name = builder.add_output_target(std::stringify!($target_name), $target_format);
+                )*)?
                 $(
-                    attachment!($atch_name, builder, $format$(, $samples)?);
+                    attachment!($atch_name, builder, $format$(, $samples)?$(, import: ($initial_layout, $final_layout))?);
+                    $($(
+                        builder.add_fallback_format($atch_name, $fallback_format);
+                    )*)?
+                    $(
+                        builder.set_sized_like($atch_name, $sized_like);
+                    )?
                 )*
+                $($(
+                    builder.import_buffer(std::stringify!($imported_buffer));
+                )*)?
+                $($(
+                    builder.enable_readback($readback_atch);
+                )*)?
 
                 $(
                     {
+                        let input_rate = if $input_rate == 0 {
+                            vulkano::pipeline::vertex::InputRate::Vertex
+                        } else {
+                            vulkano::pipeline::vertex::InputRate::Instance
+                        };
+                        let binding = $crate::rendering::vertex_input::VertexBinding::new::<$vertex_type_name>(input_rate);
+                        $(
+                            binding.check_attribute(
+                                std::stringify!($attribute_name),
+                                <$attribute_type as vulkano::pipeline::vertex::VertexMember>::format()
+                            )?;
+                        )+
+                        builder.add_default_vertex_binding(binding);
+                    }
+                )+
+
+                $(
+                    {
+                        // Shared shader modules must come from the same sources
+                        let shader_sources = std::stringify!(
+                            $vertex_path
+                            $($geometry_path)?
+                            $($tess_ctrl_path $tess_eval_path)?
+                            $fragment_path
+                            $([$(($define_name, $define_value)),*])?
+                        );
+                        if $gfx_pass_name::SHADER_SOURCES != shader_sources {
+                            return Err("A pass can only share the shaders of a pass with the same shader paths and defines.");
+                        }
+
                         // Create pass
                         let $gfx_pass_name = builder.add_pass(std::stringify!($gfx_pass_name));
 
@@ -619,20 +2612,104 @@ macro_rules! render_config {
                             color_output!($color_output_atch, $gfx_pass_name, builder);
                         )*
                         $(
-                            $gfx_pass_name.set_depth_output($depth_output_atch);
+                            builder.set_depth_output($gfx_pass_name, $depth_output_atch);
                         )?
 
                         // Add inputs
                         $(
-                            $gfx_pass_name.add_input_attachment($input_attachment_atch);
+                            input_attachment!($input_attachment_atch $(@ $input_attachment_history)?, $gfx_pass_name, builder);
                         )*
+                        $($(
+                            sampled_input!($sampled_input_atch $(@ $sampled_input_history)?, $gfx_pass_name, builder);
+                        )*)?
                         $(
-                            $gfx_pass_name.set_depth_input($depth_input_atch);
+                            builder.set_depth_input($gfx_pass_name, $depth_input_atch);
                         )?
+
+                        // Fixed function state
+                        #[allow(unused_mut)]
+                        let mut state = PipelineStateDesc::default();
+                        $($(
+                            state.$state_field = $state_value;
+                        )*)?
+                        builder.set_pipeline_state($gfx_pass_name, state);
+
+                        // Material resources bound by name
+                        $($(
+                            builder.add_material_binding(
+                                $gfx_pass_name,
+                                std::stringify!($material_binding_name),
+                                $material_binding_set,
+                                $material_binding_index
+                            );
+                        )*)?
+
+                        // The backbuffer's format is only known once a surface is chosen
+                        builder.set_fragment_outputs($gfx_pass_name, &$gfx_pass_name::fs::MainOutput);
+
+                        // Passes with the same permutation and state can share pipelines
+                        builder.set_shader_permutation($gfx_pass_name, std::stringify!(
+                            $vertex_path $({$($vs_spec_name: $vs_spec_value),*})?
+                            $($geometry_path $({$($gs_spec_name: $gs_spec_value),*})?)?
+                            $(
+                                $tess_ctrl_path $({$($tcs_spec_name: $tcs_spec_value),*})?
+                                $tess_eval_path $({$($tes_spec_name: $tes_spec_value),*})?
+                            )?
+                            $fragment_path $({$($fs_spec_name: $fs_spec_value),*})?
+                            $([$(($define_name, $define_value)),*])?
+                        ));
+
+                        builder.set_pipeline_factory($gfx_pass_name, |device, subpass, vertex_input, state| {
+                            let vs = $gfx_pass_name::vs::Shader::load(device.clone()).map_err(|_| "Failed to load vertex shader")?;
+                            let fs = $gfx_pass_name::fs::Shader::load(device.clone()).map_err(|_| "Failed to load fragment shader")?;
+                            $(
+                                let _ = $geometry_path;
+                                let gs = $gfx_pass_name::gs::Shader::load(device.clone()).map_err(|_| "Failed to load geometry shader")?;
+                            )?
+                            $(
+                                let _ = ($tess_ctrl_path, $tess_eval_path);
+                                let tcs = $gfx_pass_name::tcs::Shader::load(device.clone()).map_err(|_| "Failed to load tessellation control shader")?;
+                                let tes = $gfx_pass_name::tes::Shader::load(device.clone()).map_err(|_| "Failed to load tessellation evaluation shader")?;
+                            )?
+
+                            let pipeline_builder = vulkano::pipeline::GraphicsPipeline::start()
+                                .vertex_input(vertex_input)
+                                .vertex_shader(
+                                    vs.main_entry_point(),
+                                    specialization_constants!($gfx_pass_name::vs $(, $($vs_spec_name: $vs_spec_value),*)?)
+                                )
+                                .fragment_shader(
+                                    fs.main_entry_point(),
+                                    specialization_constants!($gfx_pass_name::fs $(, $($fs_spec_name: $fs_spec_value),*)?)
+                                )
+                                .render_pass(subpass);
+                            $(
+                                let _ = $geometry_path;
+                                let pipeline_builder = pipeline_builder.geometry_shader(
+                                    gs.main_entry_point(),
+                                    specialization_constants!($gfx_pass_name::gs $(, $($gs_spec_name: $gs_spec_value),*)?)
+                                );
+                            )?
+                            $(
+                                let _ = ($tess_ctrl_path, $tess_eval_path);
+                                let pipeline_builder = pipeline_builder.tessellation_shaders(
+                                    tcs.main_entry_point(),
+                                    specialization_constants!($gfx_pass_name::tcs $(, $($tcs_spec_name: $tcs_spec_value),*)?),
+                                    tes.main_entry_point(),
+                                    specialization_constants!($gfx_pass_name::tes $(, $($tes_spec_name: $tes_spec_value),*)?)
+                                );
+                            )?
+
+                            let pipeline = state.apply(pipeline_builder)
+                                .build(device)
+                                .map_err(|_| "Failed to create graphics pipeline")?;
+
+                            return Ok(Arc::new(pipeline) as Arc<dyn vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync>);
+                        });
                     }
                 )*
 
-                return builder.build();
+                return Ok(builder);
             }
         }
     )
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::descriptor::descriptor::DescriptorDesc;
+use vulkano::descriptor::descriptor::DescriptorDescTy;
+use vulkano::descriptor::descriptor_set::DescriptorPool;
+use vulkano::descriptor::descriptor_set::DescriptorPoolAlloc;
+use vulkano::descriptor::descriptor_set::DescriptorSet;
+use vulkano::descriptor::descriptor_set::DescriptorSetDesc;
+use vulkano::descriptor::descriptor_set::DescriptorWrite;
+use vulkano::descriptor::descriptor_set::StdDescriptorPoolAlloc;
+use vulkano::descriptor::descriptor_set::UnsafeDescriptorSet;
+use vulkano::descriptor::descriptor_set::UnsafeDescriptorSetLayout;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
+use vulkano::image::ImageViewAccess;
+use vulkano::sampler::Sampler;
+
+// A resource bound to a single (non-array) descriptor
+#[derive(Clone)]
+pub enum DescriptorResource {
+    UniformBuffer(Arc<dyn BufferAccess + Send + Sync>),
+    StorageBuffer(Arc<dyn BufferAccess + Send + Sync>),
+    // Combined image sampler
+    Texture(Arc<dyn ImageViewAccess + Send + Sync>, Arc<Sampler>),
+    // Sampled or storage image, depending on the shader's declaration
+    Image(Arc<dyn ImageViewAccess + Send + Sync>),
+    Sampler(Arc<Sampler>),
+}
+
+// Descriptor set written from a list of resources known only at runtime, one per binding of the
+// layout. PersistentDescriptorSet's builder needs the bindings in its type, so it can't be used
+// for sets assembled from named material bindings.
+pub struct RuntimeDescriptorSet {
+    inner: StdDescriptorPoolAlloc,
+    layout: Arc<UnsafeDescriptorSetLayout>,
+    buffers: Vec<(Arc<dyn BufferAccess + Send + Sync>, u32)>,
+    images: Vec<(Arc<dyn ImageViewAccess + Send + Sync>, u32)>,
+    // Kept alive for as long as the set is
+    _samplers: Vec<Arc<Sampler>>,
+}
+
+impl RuntimeDescriptorSet {
+    // `resources` is indexed by binding. Bindings the layout doesn't declare are ignored.
+    pub fn new(layout: Arc<UnsafeDescriptorSetLayout>, resources: &[Option<DescriptorResource>]) -> Result<RuntimeDescriptorSet, &'static str> {
+        let mut writes = Vec::new();
+        let mut buffers = Vec::new();
+        let mut images = Vec::new();
+        let mut samplers = Vec::new();
+
+        for binding in 0..layout.num_bindings() {
+            let desc = match layout.descriptor(binding) {
+                Some(desc) => desc,
+                None => continue
+            };
+            let resource = resources.get(binding)
+                .and_then(|x| x.as_ref())
+                .ok_or("Descriptor set binding has no resource.")?;
+
+            let binding = binding as u32;
+            let write = match (&desc.ty, resource) {
+                (DescriptorDescTy::Buffer(buffer_desc), DescriptorResource::UniformBuffer(buffer)) if !buffer_desc.storage => {
+                    if !buffer.inner().buffer.usage_uniform_buffer() {
+                        return Err("Uniform buffer was not created with uniform buffer usage.");
+                    }
+                    buffers.push((buffer.clone(), binding));
+                    unsafe { DescriptorWrite::uniform_buffer(binding, 0, buffer) }
+                },
+                (DescriptorDescTy::Buffer(buffer_desc), DescriptorResource::StorageBuffer(buffer)) if buffer_desc.storage => {
+                    if !buffer.inner().buffer.usage_storage_buffer() {
+                        return Err("Storage buffer was not created with storage buffer usage.");
+                    }
+                    buffers.push((buffer.clone(), binding));
+                    unsafe { DescriptorWrite::storage_buffer(binding, 0, buffer) }
+                },
+                (DescriptorDescTy::CombinedImageSampler(_), DescriptorResource::Texture(image, sampler)) => {
+                    if !image.can_be_sampled(sampler) {
+                        return Err("Texture image can't be sampled with its sampler.");
+                    }
+                    images.push((image.clone(), binding));
+                    samplers.push(sampler.clone());
+                    DescriptorWrite::combined_image_sampler(binding, 0, sampler, image)
+                },
+                (DescriptorDescTy::Image(image_desc), DescriptorResource::Image(image)) => {
+                    images.push((image.clone(), binding));
+                    if image_desc.sampled {
+                        DescriptorWrite::sampled_image(binding, 0, image)
+                    } else {
+                        DescriptorWrite::storage_image(binding, 0, image)
+                    }
+                },
+                (DescriptorDescTy::Sampler, DescriptorResource::Sampler(sampler)) => {
+                    samplers.push(sampler.clone());
+                    DescriptorWrite::sampler(binding, 0, sampler)
+                },
+                _ => return Err("Descriptor resource does not match the shader's descriptor type.")
+            };
+            writes.push(write);
+        }
+
+        let device = layout.device().clone();
+        let mut pool = Device::standard_descriptor_pool(&device);
+        let mut inner = pool.alloc(&layout).map_err(|_| "Failed to allocate descriptor set.")?;
+        unsafe {
+            inner.inner_mut().write(&device, writes.into_iter());
+        }
+
+        return Ok(
+            RuntimeDescriptorSet {
+                inner,
+                layout,
+                buffers,
+                images,
+                _samplers: samplers,
+            }
+        );
+    }
+}
+
+unsafe impl DescriptorSet for RuntimeDescriptorSet {
+    #[inline]
+    fn inner(&self) -> &UnsafeDescriptorSet {
+        return self.inner.inner();
+    }
+
+    #[inline]
+    fn num_buffers(&self) -> usize {
+        return self.buffers.len();
+    }
+
+    #[inline]
+    fn buffer(&self, index: usize) -> Option<(&dyn BufferAccess, u32)> {
+        return self.buffers.get(index).map(|(buffer, binding)| (&**buffer as &dyn BufferAccess, *binding));
+    }
+
+    #[inline]
+    fn num_images(&self) -> usize {
+        return self.images.len();
+    }
+
+    #[inline]
+    fn image(&self, index: usize) -> Option<(&dyn ImageViewAccess, u32)> {
+        return self.images.get(index).map(|(image, binding)| (&**image as &dyn ImageViewAccess, *binding));
+    }
+}
+
+unsafe impl DescriptorSetDesc for RuntimeDescriptorSet {
+    #[inline]
+    fn num_bindings(&self) -> usize {
+        return self.layout.num_bindings();
+    }
+
+    #[inline]
+    fn descriptor(&self, binding: usize) -> Option<DescriptorDesc> {
+        return self.layout.descriptor(binding);
+    }
+}
+
+unsafe impl DeviceOwned for RuntimeDescriptorSet {
+    #[inline]
+    fn device(&self) -> &Arc<Device> {
+        return self.layout.device();
+    }
+}
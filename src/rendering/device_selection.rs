@@ -0,0 +1,294 @@
+use std::env;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+
+use log::info;
+use log::warn;
+
+use vulkano::device::DeviceExtensions;
+use vulkano::device::Features;
+use vulkano::device::RawDeviceExtensions;
+use vulkano::format::Format;
+use vulkano::image::ImageCreateFlags;
+use vulkano::image::ImageTiling;
+use vulkano::image::ImageType;
+use vulkano::image::ImageUsage;
+use vulkano::instance::Instance;
+use vulkano::instance::PhysicalDevice;
+use vulkano::instance::PhysicalDeviceType;
+use vulkano::instance::QueueFamily;
+use vulkano::VulkanObject;
+
+pub const DEVICE_LOG_TARGET: &str = "device_selection";
+
+// Environment variable picking the physical device by index or name, e.g. SEKIRBO_DEVICE=lavapipe
+pub const DEVICE_VAR: &str = "SEKIRBO_DEVICE";
+
+// Command line flag doing the same, taking precedence over DEVICE_VAR
+pub const DEVICE_ARG: &str = "--device";
+
+// A device picked explicitly, instead of the best scoring one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceChoice {
+    // Index in PhysicalDevice::enumerate
+    Index(usize),
+    // Case-insensitive part of the device's name
+    Name(String)
+}
+
+impl DeviceChoice {
+    pub fn parse(value: &str) -> DeviceChoice {
+        return match value.parse() {
+            Ok(index) => DeviceChoice::Index(index),
+            Err(_) => DeviceChoice::Name(value.to_lowercase())
+        };
+    }
+
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        return match self {
+            DeviceChoice::Index(choice) => *choice == index,
+            // Mesa's lavapipe reports itself as llvmpipe
+            DeviceChoice::Name(choice) if choice == "lavapipe" => name.to_lowercase().contains("llvmpipe"),
+            DeviceChoice::Name(choice) => name.to_lowercase().contains(choice.as_str())
+        };
+    }
+}
+
+// `--device <index|name>`, else DEVICE_VAR
+pub fn device_choice() -> Option<DeviceChoice> {
+    let args: Vec<String> = env::args().collect();
+    let arg = args.iter()
+        .position(|x| x == DEVICE_ARG)
+        .and_then(|index| args.get(index + 1).cloned());
+    return arg.or_else(|| env::var(DEVICE_VAR).ok())
+        .filter(|x| !x.is_empty())
+        .map(|x| DeviceChoice::parse(&x));
+}
+
+// An image format need, met by a device supporting any one of `candidates`
+#[derive(Clone, Debug)]
+pub struct FormatRequirement {
+    pub name: &'static str,
+    pub candidates: Vec<Format>,
+    pub usage: ImageUsage,
+    pub samples: usize
+}
+
+// What a device needs to run a binary: usually RendererBuilder::device_requirements plus the
+// extensions the binary itself uses. Devices are created with these features and extensions.
+#[derive(Clone, Debug)]
+pub struct DeviceRequirements {
+    pub features: Features,
+    pub extensions: DeviceExtensions,
+    pub formats: Vec<FormatRequirement>
+}
+
+impl DeviceRequirements {
+    pub fn none() -> DeviceRequirements {
+        return DeviceRequirements {
+            features: Features::none(),
+            extensions: DeviceExtensions::none(),
+            formats: Vec::new()
+        };
+    }
+
+    // Why `physical` can't meet the requirements, if it can't
+    pub fn rejection(&self, physical: PhysicalDevice) -> Option<String> {
+        let missing_features = self.features.difference(physical.supported_features());
+        if missing_features != Features::none() {
+            return Some(format!("missing features {}", enabled_features(&missing_features).join(", ")));
+        }
+
+        let missing_extensions = self.extensions.difference(&DeviceExtensions::supported_by_device(physical));
+        if missing_extensions != DeviceExtensions::none() {
+            let names: Vec<String> = RawDeviceExtensions::from(&missing_extensions).iter()
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect();
+            return Some(format!("missing extensions {}", names.join(", ")));
+        }
+
+        for format in self.formats.iter() {
+            if !format.candidates.iter().any(|x| is_format_supported(physical, *x, format.usage, format.samples)) {
+                return Some(format!("no supported format for {}, candidates {:?}", format.name, format.candidates));
+            }
+        }
+        return None;
+    }
+}
+
+// Names of the enabled fields of a Features, out of the listed ones
+macro_rules! enabled_feature_names {
+    ($features:expr, $($field:ident),*$(,)?) => (
+        [$(($features.$field, std::stringify!($field))),*]
+            .iter()
+            .filter(|x| x.0)
+            .map(|x| x.1)
+            .collect::<Vec<&'static str>>()
+    );
+}
+
+// Names of the features that are enabled, covering every feature vulkano 0.19 knows
+pub fn enabled_features(features: &Features) -> Vec<&'static str> {
+    return enabled_feature_names!(
+        features,
+        robust_buffer_access,
+        full_draw_index_uint32,
+        image_cube_array,
+        independent_blend,
+        geometry_shader,
+        tessellation_shader,
+        sample_rate_shading,
+        dual_src_blend,
+        logic_op,
+        multi_draw_indirect,
+        draw_indirect_first_instance,
+        depth_clamp,
+        depth_bias_clamp,
+        fill_mode_non_solid,
+        depth_bounds,
+        wide_lines,
+        large_points,
+        alpha_to_one,
+        multi_viewport,
+        sampler_anisotropy,
+        texture_compression_etc2,
+        texture_compression_astc_ldr,
+        texture_compression_bc,
+        occlusion_query_precise,
+        pipeline_statistics_query,
+        vertex_pipeline_stores_and_atomics,
+        fragment_stores_and_atomics,
+        shader_tessellation_and_geometry_point_size,
+        shader_image_gather_extended,
+        shader_storage_image_extended_formats,
+        shader_storage_image_multisample,
+        shader_storage_image_read_without_format,
+        shader_storage_image_write_without_format,
+        shader_uniform_buffer_array_dynamic_indexing,
+        shader_sampled_image_array_dynamic_indexing,
+        shader_storage_buffer_array_dynamic_indexing,
+        shader_storage_image_array_dynamic_indexing,
+        shader_clip_distance,
+        shader_cull_distance,
+        shader_f3264,
+        shader_int64,
+        shader_int16,
+        shader_resource_residency,
+        shader_resource_min_lod,
+        sparse_binding,
+        sparse_residency_buffer,
+        sparse_residency_image2d,
+        sparse_residency_image3d,
+        sparse_residency2_samples,
+        sparse_residency4_samples,
+        sparse_residency8_samples,
+        sparse_residency16_samples,
+        sparse_residency_aliased,
+        variable_multisample_rate,
+        inherited_queries,
+        buffer_device_address,
+        buffer_device_address_capture_replay,
+        buffer_device_address_multi_device
+    );
+}
+
+fn usage_bits(usage: ImageUsage) -> vk_sys::ImageUsageFlags {
+    let bits = [
+        (usage.transfer_source, vk_sys::IMAGE_USAGE_TRANSFER_SRC_BIT),
+        (usage.transfer_destination, vk_sys::IMAGE_USAGE_TRANSFER_DST_BIT),
+        (usage.sampled, vk_sys::IMAGE_USAGE_SAMPLED_BIT),
+        (usage.storage, vk_sys::IMAGE_USAGE_STORAGE_BIT),
+        (usage.color_attachment, vk_sys::IMAGE_USAGE_COLOR_ATTACHMENT_BIT),
+        (usage.depth_stencil_attachment, vk_sys::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT),
+        (usage.transient_attachment, vk_sys::IMAGE_USAGE_TRANSIENT_ATTACHMENT_BIT),
+        (usage.input_attachment, vk_sys::IMAGE_USAGE_INPUT_ATTACHMENT_BIT)
+    ];
+    return bits.iter()
+        .filter(|x| x.0)
+        .fold(0, |acc, x| acc | x.1);
+}
+
+// Whether optimally tiled 2D images of the format can be created with the usage and sample count.
+// This asks the physical device, so it works before a device is created as well as after.
+pub fn is_format_supported(physical: PhysicalDevice, format: Format, usage: ImageUsage, samples: usize) -> bool {
+    let vk = physical.instance().pointers();
+    let mut properties = MaybeUninit::<vk_sys::ImageFormatProperties>::uninit();
+    let result = unsafe {
+        vk.GetPhysicalDeviceImageFormatProperties(
+            physical.internal_object(),
+            format as u32,
+            ImageType::Dim2d.into(),
+            ImageTiling::Optimal.into(),
+            usage_bits(usage),
+            ImageCreateFlags::none().into(),
+            properties.as_mut_ptr()
+        )
+    };
+    if result != vk_sys::SUCCESS {
+        return false;
+    }
+    let properties = unsafe { properties.assume_init() };
+    return properties.sampleCounts & samples as u32 != 0;
+}
+
+// Device type ranks highest, then device local memory
+pub fn device_score(physical: PhysicalDevice) -> (u32, usize) {
+    let device_type_rank = match physical.ty() {
+        PhysicalDeviceType::DiscreteGpu => 5,
+        PhysicalDeviceType::IntegratedGpu => 4,
+        PhysicalDeviceType::VirtualGpu => 3,
+        PhysicalDeviceType::Cpu => 2,
+        PhysicalDeviceType::Other => 1,
+    };
+    let memory = physical.memory_heaps()
+        .filter(|x| x.is_device_local())
+        .map(|x| x.size())
+        .sum();
+    return (device_type_rank, memory);
+}
+
+// The best scoring device meeting `requirements` that has a queue family accepted by
+// `queue_filter`, along with that family. With a `choice` only the chosen device is considered,
+// and it is an error for it not to meet the requirements. Rejected devices are logged with why.
+pub fn select_device<'a, F>(
+    instance: &'a Arc<Instance>,
+    requirements: &DeviceRequirements,
+    choice: Option<&DeviceChoice>,
+    queue_filter: F
+) -> Result<(PhysicalDevice<'a>, QueueFamily<'a>), &'static str>
+    where F: Fn(QueueFamily) -> bool
+{
+    let mut selected: Option<(PhysicalDevice<'a>, QueueFamily<'a>, (u32, usize))> = None;
+    for physical in PhysicalDevice::enumerate(instance) {
+        if let Some(choice) = choice {
+            if !choice.matches(physical.index(), physical.name()) {
+                info!(target: DEVICE_LOG_TARGET, "index={} name=\"{}\" skipped, not the chosen device {:?}", physical.index(), physical.name(), choice);
+                continue;
+            }
+        }
+
+        let queue_family = physical.queue_families().find(|x| queue_filter(*x));
+        let rejection = match queue_family {
+            Some(_) => requirements.rejection(physical),
+            None => Some("no queue family supports the required operations".to_string())
+        };
+        if let Some(reason) = rejection {
+            warn!(target: DEVICE_LOG_TARGET, "index={} name=\"{}\" rejected: {}", physical.index(), physical.name(), reason);
+            continue;
+        }
+
+        let score = device_score(physical);
+        info!(target: DEVICE_LOG_TARGET, "index={} name=\"{}\" type={:?} score={:?}", physical.index(), physical.name(), physical.ty(), score);
+        if selected.as_ref().map_or(true, |x| score > x.2) {
+            selected = Some((physical, queue_family.unwrap(), score));
+        }
+    }
+
+    let (physical, queue_family, _) = match (selected, choice) {
+        (Some(x), _) => x,
+        (None, Some(_)) => return Err("The chosen physical device doesn't exist or doesn't meet the requirements."),
+        (None, None) => return Err("No physical device meets the requirements.")
+    };
+    info!(target: DEVICE_LOG_TARGET, "selected index={} name=\"{}\"", physical.index(), physical.name());
+    return Ok((physical, queue_family));
+}
@@ -0,0 +1,296 @@
+use vulkano::framebuffer::AttachmentDescription;
+use vulkano::framebuffer::PassDescription;
+use vulkano::framebuffer::RenderPassDesc;
+use vulkano::image::ImageLayout;
+
+use super::AttachmentDesc;
+use super::GraphRenderPassDesc;
+use super::PassNode;
+use super::PassNodeDependency;
+use super::PhysicalPass;
+use super::RendererBuilder;
+use super::BACKBUFFER_NAME;
+
+// Minimal JSON document. Objects keep their keys in insertion order so reports of the same graph
+// are identical and diff line by line.
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(usize),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>)
+}
+
+impl Json {
+    pub fn string<S: ToString>(value: S) -> Json {
+        return Json::String(value.to_string());
+    }
+
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, 0);
+        out.push('\n');
+        return out;
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Number(value) => out.push_str(&value.to_string()),
+            Json::String(value) => write_string(out, value),
+            Json::Array(values) => {
+                if values.is_empty() {
+                    out.push_str("[]");
+                    return;
+                }
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent + 1);
+                    value.write(out, indent + 1);
+                }
+                newline(out, indent);
+                out.push(']');
+            },
+            Json::Object(fields) => {
+                if fields.is_empty() {
+                    out.push_str("{}");
+                    return;
+                }
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, indent + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                }
+                newline(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+fn option<T, F: FnOnce(T) -> Json>(value: Option<T>, f: F) -> Json {
+    return value.map_or(Json::Null, f);
+}
+
+// How a dependency reads the attachment of the pass it depends on
+fn usage_name(usage: vk_sys::ImageUsageFlagBits) -> &'static str {
+    if usage & vk_sys::IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT != 0 {
+        return "depth_stencil_attachment";
+    }
+    if usage & vk_sys::IMAGE_USAGE_INPUT_ATTACHMENT_BIT != 0 {
+        return "input_attachment";
+    }
+    if usage & vk_sys::IMAGE_USAGE_STORAGE_BIT != 0 {
+        return "storage";
+    }
+    if usage & vk_sys::IMAGE_USAGE_SAMPLED_BIT != 0 {
+        return "sampled";
+    }
+    return "other";
+}
+
+fn dependency(dependency: &PassNodeDependency) -> Json {
+    return Json::Object(vec![
+        ("pass", Json::string(dependency.pass_node.pass.name)),
+        ("attachment", Json::string(dependency.attachment.name)),
+        ("usage", Json::string(usage_name(dependency.usage))),
+        ("external", Json::Bool(dependency.requires_external_dep()))
+    ]);
+}
+
+fn layout(layout: ImageLayout) -> Json {
+    return Json::string(format!("{:?}", layout));
+}
+
+// Attachment reference of a subpass, by attachment name
+fn attachment_ref(names: &[&'static str], (index, attachment_layout): (usize, ImageLayout)) -> Json {
+    return Json::Object(vec![
+        ("attachment", Json::string(names[index])),
+        ("layout", layout(attachment_layout))
+    ]);
+}
+
+fn attachment_ops(name: &'static str, desc: &AttachmentDescription) -> Json {
+    return Json::Object(vec![
+        ("attachment", Json::string(name)),
+        ("format", Json::string(format!("{:?}", desc.format))),
+        ("samples", Json::Number(desc.samples as usize)),
+        ("load", Json::string(format!("{:?}", desc.load))),
+        ("store", Json::string(format!("{:?}", desc.store))),
+        ("stencil_load", Json::string(format!("{:?}", desc.stencil_load))),
+        ("stencil_store", Json::string(format!("{:?}", desc.stencil_store))),
+        ("initial_layout", layout(desc.initial_layout)),
+        ("final_layout", layout(desc.final_layout))
+    ]);
+}
+
+fn subpass(name: &'static str, names: &[&'static str], desc: &PassDescription) -> Json {
+    return Json::Object(vec![
+        ("pass", Json::string(name)),
+        ("color_attachments", Json::Array(desc.color_attachments.iter().map(|x| attachment_ref(names, *x)).collect())),
+        ("depth_stencil", option(desc.depth_stencil, |x| attachment_ref(names, x))),
+        ("input_attachments", Json::Array(desc.input_attachments.iter().map(|x| attachment_ref(names, *x)).collect())),
+        ("preserve_attachments", Json::Array(desc.preserve_attachments.iter().map(|x| Json::string(names[*x])).collect()))
+    ]);
+}
+
+fn physical_pass<'a, 'rb>(
+    builder: &RendererBuilder,
+    index: usize,
+    physical_pass: &PhysicalPass<'a, 'rb>,
+    desc: &GraphRenderPassDesc
+) -> Json {
+    let names: Vec<&'static str> = physical_pass.attachments().iter()
+        .map(|x| builder.attachment(*x).name)
+        .collect();
+
+    let attachments = (0..desc.num_attachments())
+        .map(|i| attachment_ops(names[i], &desc.attachment_desc(i).unwrap()))
+        .collect();
+    let subpasses = physical_pass.subpasses.iter()
+        .enumerate()
+        .map(|(i, x)| subpass(x.pass.name, &names, &desc.subpass_desc(i).unwrap()))
+        .collect();
+    let subpass_dependencies = (0..desc.num_dependencies())
+        .map(|i| {
+            let dependency = desc.dependency_desc(i).unwrap();
+            return Json::Object(vec![
+                ("source", Json::string(physical_pass.subpasses[dependency.source_subpass].pass.name)),
+                ("destination", Json::string(physical_pass.subpasses[dependency.destination_subpass].pass.name)),
+                ("by_region", Json::Bool(dependency.by_region))
+            ]);
+        })
+        .collect();
+
+    return Json::Object(vec![
+        ("index", Json::Number(index)),
+        ("attachments", Json::Array(attachments)),
+        ("subpasses", Json::Array(subpasses)),
+        ("subpass_dependencies", Json::Array(subpass_dependencies)),
+        ("external_dependencies", Json::Array(physical_pass.external_dependencies.iter().map(dependency).collect()))
+    ]);
+}
+
+// The image an attachment is backed by at runtime
+fn image(builder: &RendererBuilder, attachment: &AttachmentDesc) -> Json {
+    let (kind, name) = if attachment.name == BACKBUFFER_NAME {
+        ("backbuffer", attachment.name)
+    } else if attachment.is_target {
+        ("target", attachment.name)
+    } else if attachment.import.is_some() {
+        ("imported", attachment.name)
+    } else if let Some(current) = attachment.history_of {
+        // Shares the double-buffered images of the attachment it follows
+        ("history", builder.attachment(current).name)
+    } else {
+        ("owned", attachment.name)
+    };
+    return Json::Object(vec![
+        ("kind", Json::string(kind)),
+        ("name", Json::string(name))
+    ]);
+}
+
+fn attachment<'a, 'rb>(
+    builder: &RendererBuilder,
+    attachment: &AttachmentDesc,
+    scheduled_passes: &[&'a PassNode<'a, 'rb>],
+    physical_passes: &[PhysicalPass<'a, 'rb>]
+) -> Json {
+    // First and last index using the attachment, if any does
+    fn span<I: Iterator<Item = usize> + Clone>(indices: I) -> Json {
+        return option(indices.clone().min().zip(indices.max()), |(first, last)| {
+            return Json::Object(vec![
+                ("first", Json::Number(first)),
+                ("last", Json::Number(last))
+            ]);
+        });
+    }
+    let scheduled = scheduled_passes.iter()
+        .enumerate()
+        .filter(|(_, x)| x.pass.uses_image(attachment.id))
+        .map(|(i, _)| i);
+    let physical = physical_passes.iter()
+        .enumerate()
+        .filter(|(_, x)| x.attachments().contains(&attachment.id))
+        .map(|(i, _)| i);
+
+    return Json::Object(vec![
+        ("name", Json::string(attachment.name)),
+        ("format", Json::string(format!("{:?}", attachment.format))),
+        ("samples", Json::Number(attachment.samples)),
+        ("image", image(builder, attachment)),
+        ("sized_like", Json::string(builder.attachment(builder.attachment_size(attachment.id)).name)),
+        ("schedule_lifetime", span(scheduled)),
+        ("physical_pass_lifetime", span(physical))
+    ]);
+}
+
+// JSON description of a compiled graph: the pass schedule, physical passes with their inferred
+// load/store ops and layouts, and the lifetime, size and backing image of each attachment.
+// `render_pass_descs` are the descriptions of `physical_passes`, in order.
+pub fn graph_report<'a, 'rb>(
+    builder: &RendererBuilder,
+    scheduled_passes: &[&'a PassNode<'a, 'rb>],
+    physical_passes: &[PhysicalPass<'a, 'rb>],
+    render_pass_descs: &[GraphRenderPassDesc]
+) -> String {
+    let schedule = scheduled_passes.iter()
+        .enumerate()
+        .map(|(i, pass_node)| {
+            return Json::Object(vec![
+                ("index", Json::Number(i)),
+                ("pass", Json::string(pass_node.pass.name)),
+                ("overlap_score", option(pass_node.overlap_score.get(), Json::Number)),
+                ("dependencies", Json::Array(pass_node.dependencies.borrow().iter().map(dependency).collect()))
+            ]);
+        })
+        .collect();
+    let physical = physical_passes.iter()
+        .zip(render_pass_descs.iter())
+        .enumerate()
+        .map(|(i, (x, desc))| physical_pass(builder, i, x, desc))
+        .collect();
+    let attachments = builder.attachments.iter()
+        .map(|x| attachment(builder, x, scheduled_passes, physical_passes))
+        .collect();
+
+    return Json::Object(vec![
+        ("schedule", Json::Array(schedule)),
+        ("physical_passes", Json::Array(physical)),
+        ("attachments", Json::Array(attachments))
+    ]).to_pretty_string();
+}
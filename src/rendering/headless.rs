@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::AttachmentImage;
+use vulkano::image::ImageUsage;
+use vulkano::image::ImageViewAccess;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use super::BACKBUFFER_NAME;
+use super::Renderer;
+
+// Format of the backbuffer in render configs, and so of offscreen backbuffers
+pub const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+// Image to render BACKBUFFER into when there is no window or swapchain, e.g. on a software ICD
+// such as lavapipe. It can be read back like any other attachment.
+pub fn offscreen_backbuffer(device: Arc<Device>, dimensions: [u32; 2]) -> Result<Arc<AttachmentImage>, &'static str> {
+    return AttachmentImage::with_usage(
+        device,
+        dimensions,
+        OFFSCREEN_FORMAT,
+        ImageUsage {
+            color_attachment: true,
+            input_attachment: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        }
+    ).map_err(|_| "Failed to create offscreen backbuffer");
+}
+
+// Render `frame_count` frames into `backbuffer` and save each one as `frame_<index>.png` in
+// `output_dir`. Frames are submitted back to back and only waited on once all are in flight.
+pub fn render_to_disk<P: AsRef<Path>>(
+    renderer: &mut Renderer,
+    queue: Arc<Queue>,
+    backbuffer: Arc<dyn ImageViewAccess + Send + Sync>,
+    frame_count: usize,
+    output_dir: P
+) -> Result<Vec<PathBuf>, &'static str> {
+    let output_dir = output_dir.as_ref();
+    fs::create_dir_all(output_dir).map_err(|_| "Failed to create output directory")?;
+
+    let mut readbacks = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        readbacks.push(renderer.read_attachment(BACKBUFFER_NAME)?);
+        renderer.execute_frame(
+            queue.clone(),
+            backbuffer.clone(),
+            sync::now(queue.device().clone()).boxed()
+        )?;
+    }
+
+    let mut paths = Vec::with_capacity(frame_count);
+    for (i, readback) in readbacks.iter().enumerate() {
+        let path = output_dir.join(format!("frame_{:04}.png", i));
+        readback.save(&path)?;
+        paths.push(path);
+    }
+    return Ok(paths);
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::descriptor::DescriptorSet;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::descriptor::descriptor::DescriptorDescTy;
+use vulkano::descriptor::descriptor_set::UnsafeDescriptorSetLayout;
+use vulkano::device::DeviceOwned;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+
+use super::Renderer;
+use super::descriptor_set::RuntimeDescriptorSet;
+
+pub use super::descriptor_set::DescriptorResource;
+
+// Uniform blocks of a material live at set 0, binding 0
+pub const UNIFORM_SET: usize = 0;
+pub const UNIFORM_BINDING: usize = 0;
+
+// Vertex buffers of a mesh, bound in the order of the render config's per-vertex bindings
+#[derive(Clone)]
+pub struct Mesh {
+    vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+}
+
+impl Mesh {
+    pub fn new(vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>>) -> Mesh {
+        return Mesh {
+            vertex_buffers
+        };
+    }
+
+    pub fn vertex_buffers(&self) -> &[Arc<dyn BufferAccess + Send + Sync>] {
+        return &self.vertex_buffers;
+    }
+}
+
+// Resources of one descriptor set of a material. The vulkan set is created on first use and
+// recreated only after one of its resources changes.
+struct MaterialSet {
+    layout: Arc<UnsafeDescriptorSetLayout>,
+    // Indexed by binding
+    resources: Vec<Option<DescriptorResource>>,
+    cached: Mutex<Option<Arc<dyn DescriptorSet + Send + Sync>>>,
+}
+
+impl MaterialSet {
+    fn set_resource(&mut self, binding: usize, resource: DescriptorResource) {
+        if self.resources.len() <= binding {
+            self.resources.resize(binding + 1, None);
+        }
+        self.resources[binding] = Some(resource);
+        *self.cached.get_mut().unwrap() = None;
+    }
+
+    fn descriptor_set(&self) -> Result<Arc<dyn DescriptorSet + Send + Sync>, &'static str> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some(descriptor_set) = cached.as_ref() {
+            return Ok(descriptor_set.clone());
+        }
+
+        let descriptor_set = Arc::new(
+            RuntimeDescriptorSet::new(self.layout.clone(), &self.resources)?
+        ) as Arc<dyn DescriptorSet + Send + Sync>;
+        *cached = Some(descriptor_set.clone());
+        return Ok(descriptor_set);
+    }
+}
+
+// GPU copy of a material for one frame in flight. It is only touched again once the renderer has
+// waited for that frame's fence.
+struct MaterialFrame<U> {
+    uniform_buffer: Option<Arc<CpuAccessibleBuffer<U>>>,
+    // Version of the uniforms last written to uniform_buffer, and the frame count of the last
+    // frame drawn with it
+    uniform_version: Mutex<(u64, Option<u64>)>,
+    sets: Vec<MaterialSet>,
+}
+
+// Parameters for drawing with a pass's pipeline. U is the uniform block and Pc the push constant
+// block, normally the structs generated from the pass's shaders (e.g. `gbuffer::fs::ty::Material`).
+// Use () for either when the shaders don't declare one.
+// Textures, samplers and other buffers are bound by the names declared in the pass's
+// `material_bindings`.
+pub struct Material<U, Pc> {
+    pass_name: String,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    bindings: HashMap<String, (usize, usize)>,
+    uniforms: U,
+    uniform_version: u64,
+    frames: Vec<MaterialFrame<U>>,
+    push_constants: Pc,
+}
+
+impl<U, Pc> Material<U, Pc>
+    where
+        U: Copy + Send + Sync + 'static,
+        Pc: Copy + Send + Sync + 'static
+{
+    pub fn new(renderer: &Renderer, pass_name: &str, uniforms: U, push_constants: Pc) -> Result<Material<U, Pc>, &'static str> {
+        let pipeline = renderer.pipeline(pass_name).ok_or("Material refers to a pass without a pipeline.")?;
+
+        check_push_constants::<Pc>(&pipeline)?;
+
+        let bindings = renderer.material_bindings(pass_name)
+            .cloned()
+            .unwrap_or_default();
+
+        let has_uniforms = mem::size_of::<U>() != 0;
+        if has_uniforms {
+            match pipeline.descriptor(UNIFORM_SET, UNIFORM_BINDING).map(|x| x.ty) {
+                Some(DescriptorDescTy::Buffer(ref desc)) if !desc.storage => (),
+                Some(_) => return Err("Material uniform binding is not a uniform buffer."),
+                None => return Err("Material has uniforms but the pass's shaders declare no uniform block.")
+            }
+        }
+
+        let mut frames = Vec::with_capacity(renderer.frames_in_flight());
+        for _ in 0..renderer.frames_in_flight() {
+            let mut sets = Vec::with_capacity(pipeline.num_sets());
+            for set in 0..pipeline.num_sets() {
+                let layout = pipeline.descriptor_set_layout(set)
+                    .ok_or("Pass pipeline is missing a descriptor set layout.")?
+                    .clone();
+                sets.push(MaterialSet {
+                    layout,
+                    resources: Vec::new(),
+                    cached: Mutex::new(None)
+                });
+            }
+
+            let uniform_buffer = if has_uniforms {
+                let buffer = CpuAccessibleBuffer::from_data(
+                    pipeline.device().clone(),
+                    BufferUsage::uniform_buffer(),
+                    false,
+                    uniforms
+                ).map_err(|_| "Failed to allocate material uniform buffer.")?;
+
+                sets[UNIFORM_SET].set_resource(UNIFORM_BINDING, DescriptorResource::UniformBuffer(buffer.clone()));
+                Some(buffer)
+            } else {
+                None
+            };
+
+            frames.push(MaterialFrame {
+                uniform_buffer,
+                uniform_version: Mutex::new((0, None)),
+                sets
+            });
+        }
+
+        return Ok(
+            Material {
+                pass_name: pass_name.to_string(),
+                pipeline,
+                bindings,
+                uniforms,
+                uniform_version: 0,
+                frames,
+                push_constants,
+            }
+        );
+    }
+
+    pub fn pass_name(&self) -> &str {
+        return &self.pass_name;
+    }
+
+    pub fn pipeline(&self) -> &Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        return &self.pipeline;
+    }
+
+    pub fn uniforms(&self) -> &U {
+        return &self.uniforms;
+    }
+
+    // New uniform values are copied into each frame's buffer the next time it's drawn with. Every
+    // draw of a material in a frame reads the same buffer, so the uniforms can only change between
+    // frames; use a material per set of uniforms drawn in the same frame.
+    pub fn set_uniforms(&mut self, uniforms: U) {
+        self.uniforms = uniforms;
+        self.uniform_version += 1;
+    }
+
+    // Bind a texture, sampler or buffer by name. Only the set containing the binding is recreated.
+    pub fn set_resource(&mut self, name: &str, resource: DescriptorResource) -> Result<(), &'static str> {
+        let (set, binding) = *self.bindings.get(name).ok_or("Unknown material binding.")?;
+        if mem::size_of::<U>() != 0 && set == UNIFORM_SET && binding == UNIFORM_BINDING {
+            return Err("Material binding overlaps the material's uniform block.");
+        }
+
+        for frame in self.frames.iter_mut() {
+            let material_set = frame.sets.get_mut(set).ok_or("Material binding is not in the pass's pipeline layout.")?;
+            material_set.set_resource(binding, resource.clone());
+        }
+        return Ok(());
+    }
+
+    // Descriptor sets for drawing in the frame in flight `frame`, the `frame_count`th frame
+    // recorded, in set order. Brings the frame's uniform buffer up to date and creates any sets that
+    // were invalidated since the last call.
+    pub fn descriptor_sets(&self, frame: usize, frame_count: u64) -> Result<Vec<Arc<dyn DescriptorSet + Send + Sync>>, &'static str> {
+        let frame = self.frames.get(frame).ok_or("Frame index is out of range.")?;
+
+        if let Some(buffer) = frame.uniform_buffer.as_ref() {
+            let mut written = frame.uniform_version.lock().unwrap();
+            if needs_uniform_write(*written, self.uniform_version, frame_count)? {
+                *buffer.write().map_err(|_| "Material uniform buffer is still in use by the GPU.")? = self.uniforms;
+            }
+            *written = (self.uniform_version, Some(frame_count));
+        }
+
+        return frame.sets.iter()
+            .map(|x| x.descriptor_set())
+            .collect();
+    }
+
+    pub fn push_constants(&self) -> Pc {
+        return self.push_constants;
+    }
+
+    pub fn set_push_constants(&mut self, push_constants: Pc) {
+        self.push_constants = push_constants;
+    }
+}
+
+// Whether a frame's uniform buffer needs the uniforms of `version` written, given the version it
+// holds and the frame count it was last drawn in. Earlier draws of the frame read the buffer too,
+// so it can't be rewritten within a frame.
+pub(super) fn needs_uniform_write(written: (u64, Option<u64>), version: u64, frame_count: u64) -> Result<bool, &'static str> {
+    let (written_version, drawn_frame) = written;
+    if written_version == version {
+        return Ok(false);
+    }
+    if drawn_frame == Some(frame_count) {
+        return Err("Material uniforms changed after it was drawn this frame.");
+    }
+    return Ok(true);
+}
+
+// Push constants are pushed as one block starting at offset 0, so the reflected ranges must cover it
+fn check_push_constants<Pc>(pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>) -> Result<(), &'static str> {
+    let size = mem::size_of::<Pc>();
+    if size == 0 {
+        return Ok(());
+    }
+
+    let range_end = (0..pipeline.num_push_constants_ranges())
+        .filter_map(|i| pipeline.push_constants_range(i))
+        .map(|range| range.offset + range.size)
+        .max();
+
+    match range_end {
+        Some(end) if end >= size => Ok(()),
+        Some(_) => Err("Material push constants are larger than the pass's push constant block."),
+        None => Err("Material has push constants but the pass's shaders declare none.")
+    }
+}
@@ -0,0 +1,68 @@
+use vulkano::format::Format;
+
+// Memory one attachment of a graph needs at a given backbuffer resolution
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttachmentMemory {
+    pub name: &'static str,
+    pub format: Format,
+    pub samples: usize,
+    // Size of the output target the attachment is sized like
+    pub dimensions: [u32; 2],
+    pub bytes: u64,
+    // False for attachments whose images the renderer doesn't create: the backbuffer, imported
+    // attachments and attachments no pass uses
+    pub allocated: bool
+}
+
+// Memory of the attachments alive while a pass executes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassMemory {
+    pub pass: &'static str,
+    pub live_bytes: u64
+}
+
+// Estimated attachment memory of a render graph, see RendererBuilder::estimate_memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    // Backbuffer dimensions the estimate was made for
+    pub dimensions: [u32; 2],
+    pub attachments: Vec<AttachmentMemory>,
+    // Passes in schedule order
+    pub passes: Vec<PassMemory>,
+    // Every allocated attachment having its own image, as the renderer does today
+    pub total_bytes: u64,
+    // Attachments whose lifetimes don't overlap sharing memory, which is the peak of `passes`
+    pub aliased_total_bytes: u64,
+    pub budget: Option<u64>
+}
+
+impl MemoryEstimate {
+    pub fn exceeds_budget(&self) -> bool {
+        return self.budget.map_or(false, |budget| self.total_bytes > budget);
+    }
+
+    pub fn exceeds_budget_with_aliasing(&self) -> bool {
+        return self.budget.map_or(false, |budget| self.aliased_total_bytes > budget);
+    }
+}
+
+// Bytes of a texel block. Combined depth/stencil formats have no defined size, so this assumes
+// the stencil aspect is padded to 32 bits alongside depth, as most implementations store them.
+fn block_bytes(format: Format) -> u64 {
+    if let Some(size) = format.size() {
+        return size as u64;
+    }
+    return match format {
+        Format::D16Unorm_S8Uint | Format::D24Unorm_S8Uint => 4,
+        _ => 8
+    };
+}
+
+// Size of an image of `format` and `samples` covering `dimensions`, ignoring alignment and the
+// driver's own overhead
+pub fn image_bytes(format: Format, samples: usize, dimensions: [u32; 2]) -> u64 {
+    let (block_width, block_height) = format.block_dimensions();
+    let blocks_x = ((dimensions[0] + block_width - 1) / block_width) as u64;
+    let blocks_y = ((dimensions[1] + block_height - 1) / block_height) as u64;
+    return blocks_x * blocks_y * block_bytes(format) * samples as u64;
+}
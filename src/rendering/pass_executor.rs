@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::AutoCommandBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::DynamicState;
+use vulkano::device::Device;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::Subpass;
+use vulkano::image::ImageViewAccess;
+use vulkano::instance::QueueFamily;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+
+use super::DrawCommand;
+
+// What an executor can use while recording its pass. The command buffer is already inside the
+// pass's subpass and must be left there.
+pub struct PassContext<'a> {
+    pass_name: &'a str,
+    view: usize,
+    cmd_buf_builder: &'a mut AutoCommandBufferBuilder,
+    pipeline: &'a Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    dynamic_state: &'a DynamicState,
+    attachments: &'a HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>>,
+    buffers: &'a HashMap<String, Arc<dyn BufferAccess + Send + Sync>>,
+}
+
+impl<'a> PassContext<'a> {
+    pub fn new(
+        pass_name: &'a str,
+        view: usize,
+        cmd_buf_builder: &'a mut AutoCommandBufferBuilder,
+        pipeline: &'a Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        dynamic_state: &'a DynamicState,
+        attachments: &'a HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>>,
+        buffers: &'a HashMap<String, Arc<dyn BufferAccess + Send + Sync>>
+    ) -> PassContext<'a> {
+        return PassContext {
+            pass_name,
+            view,
+            cmd_buf_builder,
+            pipeline,
+            dynamic_state,
+            attachments,
+            buffers,
+        };
+    }
+
+    pub fn pass_name(&self) -> &str {
+        return self.pass_name;
+    }
+
+    // Index of the view being rendered, see Renderer::set_views. Always 0 for passes that aren't per view.
+    pub fn view(&self) -> usize {
+        return self.view;
+    }
+
+    pub fn cmd_buf_builder(&mut self) -> &mut AutoCommandBufferBuilder {
+        return self.cmd_buf_builder;
+    }
+
+    pub fn pipeline(&self) -> &Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        return self.pipeline;
+    }
+
+    // Viewport covering the attachments, or the current view of them, plus whatever else the pass's pipeline state makes dynamic
+    pub fn dynamic_state(&self) -> &DynamicState {
+        return self.dynamic_state;
+    }
+
+    // The image backing an attachment this frame, by the name used in the render config
+    pub fn attachment(&self, name: &str) -> Option<&Arc<dyn ImageViewAccess + Send + Sync>> {
+        return self.attachments.get(name);
+    }
+
+    // A buffer imported into the graph, by the name used in the render config
+    pub fn buffer(&self, name: &str) -> Option<&Arc<dyn BufferAccess + Send + Sync>> {
+        return self.buffers.get(name);
+    }
+}
+
+// Records the draws of a pass each frame
+pub trait PassExecutor: Send {
+    fn execute(&mut self, context: &mut PassContext) -> Result<(), &'static str>;
+}
+
+impl<F> PassExecutor for F
+    where F: FnMut(&mut PassContext) -> Result<(), &'static str> + Send
+{
+    fn execute(&mut self, context: &mut PassContext) -> Result<(), &'static str> {
+        return self(context);
+    }
+}
+
+// Records the draws of a pass split into chunks, e.g. ranges of instances. Each chunk is recorded
+// into its own secondary command buffer on a worker thread, and the secondaries are executed in
+// chunk order. Vulkano doesn't synchronize buffers and images used from secondaries, so those must
+// not be written by the GPU earlier in the same frame.
+pub trait ParallelPassExecutor: Send + Sync {
+    // Number of chunks to record this frame
+    fn chunk_count(&self) -> usize;
+
+    fn execute_chunk(&self, chunk: usize, context: &mut PassContext) -> Result<(), &'static str>;
+}
+
+// What every chunk of a subpass shares. Only the command buffer differs between threads.
+pub(super) struct SubpassResources {
+    pub pass_name: String,
+    pub subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    pub pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    pub dynamic_state: DynamicState,
+    pub attachments: HashMap<String, Arc<dyn ImageViewAccess + Send + Sync>>,
+    pub buffers: HashMap<String, Arc<dyn BufferAccess + Send + Sync>>,
+}
+
+impl SubpassResources {
+    fn secondary(&self, device: &Arc<Device>, queue_family: QueueFamily) -> Result<AutoCommandBufferBuilder, &'static str> {
+        return AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+            device.clone(),
+            queue_family,
+            self.subpass.clone()
+        ).map_err(|_| "Failed to create secondary command buffer");
+    }
+
+    fn context<'a>(&'a self, cmd_buf_builder: &'a mut AutoCommandBufferBuilder) -> PassContext<'a> {
+        return PassContext::new(
+            &self.pass_name,
+            0,
+            cmd_buf_builder,
+            &self.pipeline,
+            &self.dynamic_state,
+            &self.attachments,
+            &self.buffers
+        );
+    }
+}
+
+// Chunks recorded by each of up to `thread_count` threads. Thread i records chunks i,
+// i + thread_count, ... so chunks of similar cost are spread out.
+pub(super) fn chunk_threads(chunk_count: usize, thread_count: usize) -> Vec<Vec<usize>> {
+    let thread_count = thread_count.max(1).min(chunk_count);
+    return (0..thread_count)
+        .map(|first_chunk| (first_chunk..chunk_count).step_by(thread_count).collect())
+        .collect();
+}
+
+type RecordingJob = Box<dyn FnOnce() + Send>;
+
+// Threads recording the chunks of parallel passes. They are started once and kept by the renderer,
+// so recording a frame doesn't spawn threads and each thread keeps its command pool between frames.
+pub(super) struct RecordingPool {
+    senders: Vec<mpsc::Sender<RecordingJob>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl RecordingPool {
+    pub(super) fn new(thread_count: usize) -> RecordingPool {
+        let mut senders = Vec::new();
+        let mut threads = Vec::new();
+        for _ in 0..thread_count.max(1) {
+            let (sender, receiver) = mpsc::channel::<RecordingJob>();
+            senders.push(sender);
+            threads.push(thread::spawn(move || {
+                for job in receiver {
+                    // A panicking job reports itself by dropping its result sender, and the thread
+                    // stays around for the next frame
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+            }));
+        }
+        return RecordingPool {
+            senders,
+            threads
+        };
+    }
+
+    pub(super) fn thread_count(&self) -> usize {
+        return self.senders.len();
+    }
+
+    // Record chunks with `record_chunk` on the threads given by chunk_threads, then the queued draws
+    // with `record_draws` on this thread, if there are any. Returns what was recorded in the order it
+    // must be executed: by chunk, then the draws.
+    pub(super) fn record_in_order<T, C, D>(
+        &self,
+        chunk_count: usize,
+        record_chunk: Arc<C>,
+        record_draws: Option<D>
+    ) -> Result<Vec<T>, &'static str>
+        where
+            T: Send + 'static,
+            C: Fn(usize) -> Result<T, &'static str> + Send + Sync + 'static,
+            D: FnOnce() -> Result<T, &'static str>
+    {
+        let (result_sender, results) = mpsc::channel();
+        let thread_chunks = chunk_threads(chunk_count, self.thread_count());
+        let job_count = thread_chunks.len();
+        for (sender, chunks) in self.senders.iter().zip(thread_chunks) {
+            let record_chunk = record_chunk.clone();
+            let result_sender = result_sender.clone();
+            sender.send(Box::new(move || {
+                let recorded = chunks.into_iter()
+                    .map(|chunk| Ok((chunk, record_chunk(chunk)?)))
+                    .collect::<Result<Vec<_>, &'static str>>();
+                // Nobody is waiting anymore if another thread failed first
+                let _ = result_sender.send(recorded);
+            })).map_err(|_| "Recording thread has exited.")?;
+        }
+        drop(result_sender);
+
+        let mut recorded: Vec<(usize, T)> = Vec::new();
+        for _ in 0..job_count {
+            let thread_recorded: Vec<(usize, T)> = results.recv().unwrap_or(Err("Recording thread panicked."))?;
+            recorded.extend(thread_recorded);
+        }
+        recorded.sort_by_key(|(chunk, _)| *chunk);
+        let mut ordered: Vec<T> = recorded.into_iter().map(|(_, x)| x).collect();
+
+        if let Some(record_draws) = record_draws {
+            ordered.push(record_draws()?);
+        }
+        return Ok(ordered);
+    }
+}
+
+impl Drop for RecordingPool {
+    fn drop(&mut self) {
+        // Closing the channels ends the threads once their current job is done
+        self.senders.clear();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Record the chunks of `executor` on the pool's threads, followed by `draws` on this thread.
+// Returns the secondary command buffers in the order they must be executed.
+pub(super) fn record_secondaries(
+    pool: &RecordingPool,
+    device: &Arc<Device>,
+    queue_family: QueueFamily,
+    resources: SubpassResources,
+    executor: &Arc<dyn ParallelPassExecutor>,
+    draws: Vec<Box<DrawCommand>>
+) -> Result<Vec<AutoCommandBuffer>, &'static str> {
+    let resources = Arc::new(resources);
+    let record_chunk = {
+        let resources = resources.clone();
+        let device = device.clone();
+        let queue_family_id = queue_family.id();
+        let executor = executor.clone();
+        Arc::new(move |chunk: usize| {
+            let queue_family = device.physical_device()
+                .queue_family_by_id(queue_family_id)
+                .ok_or("Queue family not found.")?;
+            // The standard command pool is per thread, so each secondary is built where it is recorded
+            let mut cmd_buf_builder = resources.secondary(&device, queue_family)?;
+            executor.execute_chunk(chunk, &mut resources.context(&mut cmd_buf_builder))?;
+            return cmd_buf_builder.build().map_err(|_| "Failed to build secondary command buffer");
+        })
+    };
+
+    let record_draws = if draws.is_empty() {
+        None
+    } else {
+        Some(|| {
+            let mut cmd_buf_builder = resources.secondary(device, queue_family)?;
+            for draw in draws.iter() {
+                draw(&mut cmd_buf_builder, &resources.dynamic_state)?;
+            }
+            return cmd_buf_builder.build().map_err(|_| "Failed to build secondary command buffer");
+        })
+    };
+
+    return pool.record_in_order(executor.chunk_count(), record_chunk, record_draws);
+}
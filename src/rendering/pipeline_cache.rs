@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use vulkano::framebuffer::PassDependencyDescription;
+use vulkano::framebuffer::PassDescription;
+use vulkano::framebuffer::RenderPassDesc;
+use vulkano::image::ImageLayout;
+use vulkano::pipeline::GraphicsPipelineAbstract;
+use vulkano::sync::AccessFlagBits;
+
+use super::render_pass::GraphRenderPassDesc;
+
+// A cached pipeline along with the render pass and subpass it was created for
+struct CachedPipeline<P> {
+    render_pass: GraphRenderPassDesc,
+    subpass: u32,
+    pipeline: P
+}
+
+// Graphics pipelines keyed by shader permutation, fixed function state and vertex input.
+// A cached pipeline is reused for the same subpass of a compatible render pass, so passes that
+// share a permutation (or a graph that gets rebuilt) don't recompile their pipelines.
+pub struct PipelineCache<P = Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+    pipelines: HashMap<String, Vec<CachedPipeline<P>>>,
+}
+
+impl<P: Clone> PipelineCache<P> {
+    pub fn new() -> PipelineCache<P> {
+        return PipelineCache {
+            pipelines: HashMap::new()
+        };
+    }
+
+    pub fn get_or_create<R, F>(
+        &mut self,
+        key: &str,
+        render_pass: &R,
+        subpass: u32,
+        create: F
+    ) -> Result<P, &'static str>
+        where
+            R: RenderPassDesc + ?Sized,
+            F: FnOnce() -> Result<P, &'static str>
+    {
+        if let Some(pipelines) = self.pipelines.get(key) {
+            let cached = pipelines.iter().find(|x| {
+                x.subpass == subpass && is_render_pass_compatible(&x.render_pass, render_pass)
+            });
+            if let Some(cached) = cached {
+                return Ok(cached.pipeline.clone());
+            }
+        }
+
+        let pipeline = create()?;
+        self.pipelines.entry(key.to_string())
+            .or_insert_with(Vec::new)
+            .push(CachedPipeline {
+                render_pass: copy_desc(render_pass),
+                subpass,
+                pipeline: pipeline.clone()
+            });
+        return Ok(pipeline);
+    }
+
+    // Number of distinct pipelines created through the cache
+    pub fn len(&self) -> usize {
+        return self.pipelines.values().map(|x| x.len()).sum();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+impl<P: Clone> Default for PipelineCache<P> {
+    fn default() -> PipelineCache<P> {
+        return PipelineCache::new();
+    }
+}
+
+fn copy_desc<R: RenderPassDesc + ?Sized>(desc: &R) -> GraphRenderPassDesc {
+    let mut copy = GraphRenderPassDesc::new();
+    for i in 0..desc.num_attachments() {
+        copy.add_attachment(desc.attachment_desc(i).unwrap());
+    }
+    for i in 0..desc.num_subpasses() {
+        copy.add_subpass(desc.subpass_desc(i).unwrap());
+    }
+    for i in 0..desc.num_dependencies() {
+        copy.add_dependency(desc.dependency_desc(i).unwrap());
+    }
+    return copy;
+}
+
+fn attachment_indices(attachments: &[(usize, ImageLayout)]) -> Vec<usize> {
+    return attachments.iter().map(|x| x.0).collect();
+}
+
+fn is_subpass_identical(a: &PassDescription, b: &PassDescription) -> bool {
+    return attachment_indices(&a.color_attachments) == attachment_indices(&b.color_attachments)
+        && attachment_indices(&a.input_attachments) == attachment_indices(&b.input_attachments)
+        && attachment_indices(&a.resolve_attachments) == attachment_indices(&b.resolve_attachments)
+        && a.depth_stencil.map(|x| x.0) == b.depth_stencil.map(|x| x.0)
+        && a.preserve_attachments == b.preserve_attachments;
+}
+
+// AccessFlagBits has no PartialEq
+fn access_bits(access: AccessFlagBits) -> vk_sys::AccessFlags {
+    let bits = [
+        (access.indirect_command_read, vk_sys::ACCESS_INDIRECT_COMMAND_READ_BIT),
+        (access.index_read, vk_sys::ACCESS_INDEX_READ_BIT),
+        (access.vertex_attribute_read, vk_sys::ACCESS_VERTEX_ATTRIBUTE_READ_BIT),
+        (access.uniform_read, vk_sys::ACCESS_UNIFORM_READ_BIT),
+        (access.input_attachment_read, vk_sys::ACCESS_INPUT_ATTACHMENT_READ_BIT),
+        (access.shader_read, vk_sys::ACCESS_SHADER_READ_BIT),
+        (access.shader_write, vk_sys::ACCESS_SHADER_WRITE_BIT),
+        (access.color_attachment_read, vk_sys::ACCESS_COLOR_ATTACHMENT_READ_BIT),
+        (access.color_attachment_write, vk_sys::ACCESS_COLOR_ATTACHMENT_WRITE_BIT),
+        (access.depth_stencil_attachment_read, vk_sys::ACCESS_DEPTH_STENCIL_ATTACHMENT_READ_BIT),
+        (access.depth_stencil_attachment_write, vk_sys::ACCESS_DEPTH_STENCIL_ATTACHMENT_WRITE_BIT),
+        (access.transfer_read, vk_sys::ACCESS_TRANSFER_READ_BIT),
+        (access.transfer_write, vk_sys::ACCESS_TRANSFER_WRITE_BIT),
+        (access.host_read, vk_sys::ACCESS_HOST_READ_BIT),
+        (access.host_write, vk_sys::ACCESS_HOST_WRITE_BIT),
+        (access.memory_read, vk_sys::ACCESS_MEMORY_READ_BIT),
+        (access.memory_write, vk_sys::ACCESS_MEMORY_WRITE_BIT)
+    ];
+    return bits.iter()
+        .filter(|x| x.0)
+        .fold(0, |acc, x| acc | x.1);
+}
+
+fn is_dependency_identical(a: &PassDependencyDescription, b: &PassDependencyDescription) -> bool {
+    return a.source_subpass == b.source_subpass
+        && a.destination_subpass == b.destination_subpass
+        && a.source_stages == b.source_stages
+        && a.destination_stages == b.destination_stages
+        && access_bits(a.source_access) == access_bits(b.source_access)
+        && access_bits(a.destination_access) == access_bits(b.destination_access)
+        && a.by_region == b.by_region;
+}
+
+// Render pass compatibility as defined by the Vulkan spec: attachments with the same formats and
+// sample counts, and otherwise identical subpasses and dependencies. Image layouts and load/store
+// ops don't matter.
+pub fn is_render_pass_compatible<A, B>(a: &A, b: &B) -> bool
+    where
+        A: RenderPassDesc + ?Sized,
+        B: RenderPassDesc + ?Sized
+{
+    if a.num_attachments() != b.num_attachments()
+        || a.num_subpasses() != b.num_subpasses()
+        || a.num_dependencies() != b.num_dependencies()
+    {
+        return false;
+    }
+
+    for i in 0..a.num_attachments() {
+        if !a.attachment_desc(i).unwrap().is_compatible_with(&b.attachment_desc(i).unwrap()) {
+            return false;
+        }
+    }
+    for i in 0..a.num_subpasses() {
+        if !is_subpass_identical(&a.subpass_desc(i).unwrap(), &b.subpass_desc(i).unwrap()) {
+            return false;
+        }
+    }
+    for i in 0..a.num_dependencies() {
+        if !is_dependency_identical(&a.dependency_desc(i).unwrap(), &b.dependency_desc(i).unwrap()) {
+            return false;
+        }
+    }
+    return true;
+}
@@ -0,0 +1,156 @@
+use vulkano::pipeline::GraphicsPipelineBuilder;
+
+pub use vulkano::pipeline::blend::AttachmentBlend;
+pub use vulkano::pipeline::depth_stencil::Compare;
+pub use vulkano::pipeline::depth_stencil::DepthBounds;
+pub use vulkano::pipeline::depth_stencil::DepthStencil;
+pub use vulkano::pipeline::depth_stencil::Stencil;
+pub use vulkano::pipeline::depth_stencil::StencilOp;
+pub use vulkano::pipeline::input_assembly::PrimitiveTopology;
+pub use vulkano::pipeline::raster::CullMode;
+pub use vulkano::pipeline::raster::FrontFace;
+pub use vulkano::pipeline::raster::PolygonMode;
+
+// Which pieces of state are left to be set in the command buffer at draw time.
+// Viewports are always dynamic so that pipelines don't depend on attachment sizes.
+// Depth bounds and stencil masks/references are made dynamic through DepthStencil itself.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DynamicStates {
+    pub scissor: bool,
+    pub line_width: bool,
+    pub blend_constants: bool,
+}
+
+// Fixed function state of a graphics pass
+#[derive(Clone, Debug)]
+pub struct PipelineStateDesc {
+    pub topology: PrimitiveTopology,
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub polygon_mode: PolygonMode,
+    pub depth_stencil: DepthStencil,
+    // One entry per color output, in declaration order. Empty means pass through for all outputs.
+    pub blend: Vec<AttachmentBlend>,
+    pub dynamic_states: DynamicStates,
+}
+
+impl Default for PipelineStateDesc {
+    fn default() -> PipelineStateDesc {
+        return PipelineStateDesc {
+            topology: PrimitiveTopology::TriangleList,
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            polygon_mode: PolygonMode::Fill,
+            depth_stencil: DepthStencil::disabled(),
+            blend: Vec::new(),
+            dynamic_states: DynamicStates::default(),
+        };
+    }
+}
+
+impl PipelineStateDesc {
+    pub fn has_depth_test(&self) -> bool {
+        return self.depth_stencil.depth_compare != Compare::Always || self.depth_stencil.depth_write;
+    }
+
+    pub fn has_depth_write(&self) -> bool {
+        return self.depth_stencil.depth_write;
+    }
+
+    pub fn has_stencil_test(&self) -> bool {
+        // Same rule vulkano uses to enable the stencil test in the pipeline
+        fn is_enabled(stencil: &Stencil) -> bool {
+            return !stencil.always_keep();
+        }
+        return is_enabled(&self.depth_stencil.stencil_front) || is_enabled(&self.depth_stencil.stencil_back);
+    }
+
+    // Whether any stencil op that can be reached modifies the stencil aspect
+    pub fn has_stencil_write(&self) -> bool {
+        fn writes(stencil: &Stencil) -> bool {
+            return !stencil.always_keep() && stencil.write_mask != Some(0);
+        }
+        return writes(&self.depth_stencil.stencil_front) || writes(&self.depth_stencil.stencil_back);
+    }
+
+    pub fn validate(&self, num_color_outputs: usize, has_depth: bool, has_depth_output: bool, has_stencil: bool) -> Result<(), &'static str> {
+        if !self.blend.is_empty() && self.blend.len() != num_color_outputs {
+            return Err("Blend state count does not match color output count.");
+        }
+
+        if self.has_depth_test() && !has_depth {
+            return Err("Depth test enabled on a pass without a depth attachment.");
+        }
+
+        if self.has_depth_write() && !has_depth_output {
+            return Err("Depth write enabled on a pass without a depth output.");
+        }
+
+        if self.has_stencil_test() && !has_stencil {
+            return Err("Stencil test enabled on a pass without a stencil attachment.");
+        }
+
+        if self.has_stencil_write() && !has_depth_output {
+            return Err("Stencil write enabled on a pass without a depth output.");
+        }
+
+        if self.depth_stencil.depth_bounds_test != DepthBounds::Disabled && !has_depth {
+            return Err("Depth bounds test enabled on a pass without a depth attachment.");
+        }
+
+        Ok(())
+    }
+
+    pub fn apply<Vdef, Vs, Vss, Tcs, Tcss, Tes, Tess, Gs, Gss, Fs, Fss, Rp>(
+        &self,
+        builder: GraphicsPipelineBuilder<Vdef, Vs, Vss, Tcs, Tcss, Tes, Tess, Gs, Gss, Fs, Fss, Rp>
+    ) -> GraphicsPipelineBuilder<Vdef, Vs, Vss, Tcs, Tcss, Tes, Tess, Gs, Gss, Fs, Fss, Rp> {
+        let builder = builder.primitive_topology(self.topology);
+
+        let builder = match self.cull_mode {
+            CullMode::None => builder.cull_mode_disabled(),
+            CullMode::Front => builder.cull_mode_front(),
+            CullMode::Back => builder.cull_mode_back(),
+            CullMode::FrontAndBack => builder.cull_mode_front_and_back()
+        };
+
+        let builder = match self.front_face {
+            FrontFace::CounterClockwise => builder.front_face_counter_clockwise(),
+            FrontFace::Clockwise => builder.front_face_clockwise()
+        };
+
+        let builder = match self.polygon_mode {
+            PolygonMode::Fill => builder.polygon_mode_fill(),
+            PolygonMode::Line => builder.polygon_mode_line(),
+            PolygonMode::Point => builder.polygon_mode_point()
+        };
+
+        let builder = builder.depth_stencil(self.depth_stencil.clone());
+
+        let builder = if self.blend.is_empty() {
+            builder.blend_pass_through()
+        } else {
+            builder.blend_individual(self.blend.iter().cloned())
+        };
+
+        let builder = if self.dynamic_states.scissor {
+            builder.viewports_scissors_dynamic(1)
+        } else {
+            builder.viewports_dynamic_scissors_irrelevant(1)
+        };
+
+        let builder = if self.dynamic_states.line_width {
+            builder.line_width_dynamic()
+        } else {
+            builder.line_width(1.0)
+        };
+
+        let builder = if self.dynamic_states.blend_constants {
+            builder.blend_constants_dynamic()
+        } else {
+            builder.blend_constants([0.0, 0.0, 0.0, 0.0])
+        };
+
+        return builder;
+    }
+}
@@ -0,0 +1,291 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use image::ImageBuffer;
+use image::Rgba;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::AttachmentImage;
+use vulkano::image::ImageAccess;
+use vulkano::image::ImageInner;
+use vulkano::image::ImageLayout;
+use vulkano::image::ImageUsage;
+use vulkano::image::ImageViewAccess;
+use vulkano::sync::AccessError;
+
+use super::FrameFuture;
+
+// The image behind a view, for transfer commands that take an ImageAccess. The format is the one
+// transfer commands see: a depth only format reads just the depth aspect of a depth/stencil image.
+struct ViewParent(Arc<dyn ImageViewAccess + Send + Sync>, Format);
+
+unsafe impl ImageAccess for ViewParent {
+    fn inner(&self) -> ImageInner<'_> {
+        return self.0.parent().inner();
+    }
+
+    fn format(&self) -> Format {
+        return self.1;
+    }
+
+    fn initial_layout_requirement(&self) -> ImageLayout {
+        return self.0.parent().initial_layout_requirement();
+    }
+
+    fn final_layout_requirement(&self) -> ImageLayout {
+        return self.0.parent().final_layout_requirement();
+    }
+
+    fn conflicts_buffer(&self, other: &dyn BufferAccess) -> bool {
+        return self.0.parent().conflicts_buffer(other);
+    }
+
+    fn conflicts_image(&self, other: &dyn ImageAccess) -> bool {
+        return self.0.parent().conflicts_image(other);
+    }
+
+    fn conflict_key(&self) -> u64 {
+        return self.0.parent().conflict_key();
+    }
+
+    fn try_gpu_lock(&self, exclusive_access: bool, expected_layout: ImageLayout) -> Result<(), AccessError> {
+        return self.0.parent().try_gpu_lock(exclusive_access, expected_layout);
+    }
+
+    unsafe fn increase_gpu_lock(&self) {
+        self.0.parent().increase_gpu_lock();
+    }
+
+    unsafe fn unlock(&self, transitioned_layout: Option<ImageLayout>) {
+        self.0.parent().unlock(transitioned_layout);
+    }
+
+    unsafe fn layout_initialized(&self) {
+        self.0.parent().layout_initialized();
+    }
+
+    fn is_layout_initialized(&self) -> bool {
+        return self.0.parent().is_layout_initialized();
+    }
+}
+
+struct ReadbackTarget {
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    format: Format,
+    dimensions: [u32; 2],
+}
+
+struct ReadbackState {
+    target: Option<ReadbackTarget>,
+    future: Option<Arc<FrameFuture>>,
+    // Why the frame holding the copy was never submitted
+    error: Option<&'static str>,
+}
+
+// Contents of an attachment copied to the CPU at the end of a frame. The copy can be waited on
+// once the frame has been submitted with execute_frame.
+#[derive(Clone)]
+pub struct Readback {
+    state: Arc<Mutex<ReadbackState>>,
+}
+
+impl Readback {
+    pub(super) fn new() -> Readback {
+        return Readback {
+            state: Arc::new(Mutex::new(ReadbackState {
+                target: None,
+                future: None,
+                error: None
+            }))
+        };
+    }
+
+    // Record a copy of `image` into a new CPU buffer. The command buffer must be outside a render pass.
+    pub(super) fn record(
+        &self,
+        device: Arc<Device>,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder,
+        image: Arc<dyn ImageViewAccess + Send + Sync>
+    ) -> Result<(), &'static str> {
+        let image_format = image.format();
+        let format = copied_format(image_format);
+        let bytes_per_pixel = bytes_per_pixel(format).ok_or("Readback of this attachment format is not supported.")?;
+        if image.samples() != 1 {
+            return Err("Multisampled attachments can't be read back.");
+        }
+        if !image.inner().usage_transfer_source() {
+            return Err("Attachment image was not created for readback.");
+        }
+
+        let dimensions = image.dimensions().width_height();
+        let size = dimensions[0] as usize * dimensions[1] as usize * bytes_per_pixel;
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_destination(),
+            false,
+            (0..size).map(|_| 0u8)
+        ).map_err(|_| "Failed to allocate readback buffer")?;
+
+        if format != image_format {
+            // A copy to a buffer may only read one aspect, and vulkano would also use just that
+            // aspect for the barrier moving the image to the transfer layout. Copy a texel with
+            // both aspects first, so the depth read after it is in the same layout and needs no
+            // barrier.
+            let usage = ImageUsage {
+                transfer_destination: true,
+                ..ImageUsage::none()
+            };
+            let scratch = AttachmentImage::with_usage(device, [1, 1], image_format, usage)
+                .map_err(|_| "Failed to allocate readback scratch image")?;
+            cmd_buf_builder
+                .copy_image(ViewParent(image.clone(), image_format), [0, 0, 0], 0, 0, scratch, [0, 0, 0], 0, 0, [1, 1, 1], 1)
+                .map_err(|_| "Failed to record readback copy")?;
+        }
+
+        cmd_buf_builder
+            .copy_image_to_buffer(ViewParent(image, format), buffer.clone())
+            .map_err(|_| "Failed to record readback copy")?;
+
+        self.state.lock().unwrap().target = Some(ReadbackTarget {
+            buffer,
+            format,
+            dimensions
+        });
+        return Ok(());
+    }
+
+    pub(super) fn set_future(&self, future: Arc<FrameFuture>) {
+        self.state.lock().unwrap().future = Some(future);
+    }
+
+    // The frame holding the copy failed to submit, so the copy will never happen
+    pub(super) fn fail(&self, error: &'static str) {
+        self.state.lock().unwrap().error = Some(error);
+    }
+
+    // Whether the frame holding the copy has finished on the GPU, or failed to submit
+    pub fn is_ready(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        if state.error.is_some() {
+            return true;
+        }
+        return match state.future.as_ref() {
+            Some(future) => future.wait(Some(Duration::from_secs(0))).is_ok(),
+            None => false
+        };
+    }
+
+    // Block until the frame finishes, then convert the copy to RGBA8. Depth and single or two
+    // channel formats are expanded to gray or (r, g, 0) and float formats are clamped to [0, 1].
+    pub fn wait(&self) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, &'static str> {
+        let state = self.state.lock().unwrap();
+        if let Some(error) = state.error {
+            return Err(error);
+        }
+        let future = state.future.as_ref().ok_or("Readback frame has not been submitted.")?;
+        future.wait(None).map_err(|_| "Failed to wait for readback frame")?;
+
+        let target = state.target.as_ref().ok_or("Readback was not recorded.")?;
+        let content = target.buffer.read().map_err(|_| "Readback buffer is still in use by the GPU.")?;
+        return to_rgba8(target.format, target.dimensions, &content);
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), &'static str> {
+        return self.wait()?
+            .save(path)
+            .map_err(|_| "Failed to save readback image");
+    }
+}
+
+// Format of what a copy to a buffer reads from an image of `format`. Only the depth aspect of
+// depth/stencil formats is read back.
+fn copied_format(format: Format) -> Format {
+    return match format {
+        Format::D24Unorm_S8Uint => Format::X8_D24UnormPack32,
+        Format::D32Sfloat_S8Uint => Format::D32Sfloat,
+        _ => format
+    };
+}
+
+fn bytes_per_pixel(format: Format) -> Option<usize> {
+    return match format {
+        Format::R8Unorm => Some(1),
+        Format::R8G8Unorm | Format::D16Unorm => Some(2),
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb |
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb |
+        Format::R32Sfloat | Format::D32Sfloat |
+        Format::X8_D24UnormPack32 | Format::A2B10G10R10UnormPack32 => Some(4),
+        Format::R16G16B16A16Sfloat => Some(8),
+        Format::R32G32B32A32Sfloat => Some(16),
+        _ => None
+    };
+}
+
+fn unorm8(value: f32) -> u8 {
+    return (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+}
+
+pub(super) fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    return sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f => if mantissa == 0.0 { f32::INFINITY } else { f32::NAN },
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15)
+    };
+}
+
+pub(super) fn to_rgba8(format: Format, dimensions: [u32; 2], data: &[u8]) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, &'static str> {
+    let bytes_per_pixel = bytes_per_pixel(format).ok_or("Readback of this attachment format is not supported.")?;
+    let f16_at = |pixel: &[u8], i: usize| half_to_f32(u16::from_le_bytes([pixel[i * 2], pixel[i * 2 + 1]]));
+    let u32_at = |pixel: &[u8]| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+    let f32_at = |pixel: &[u8], i: usize| {
+        return f32::from_le_bytes([pixel[i * 4], pixel[i * 4 + 1], pixel[i * 4 + 2], pixel[i * 4 + 3]]);
+    };
+
+    let mut rgba = Vec::with_capacity(dimensions[0] as usize * dimensions[1] as usize * 4);
+    for pixel in data.chunks_exact(bytes_per_pixel) {
+        let converted = match format {
+            Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => [pixel[2], pixel[1], pixel[0], pixel[3]],
+            Format::R8Unorm => [pixel[0], pixel[0], pixel[0], 255],
+            Format::R8G8Unorm => [pixel[0], pixel[1], 0, 255],
+            Format::D16Unorm => {
+                let depth = unorm8(u16::from_le_bytes([pixel[0], pixel[1]]) as f32 / 65535.0);
+                [depth, depth, depth, 255]
+            },
+            Format::X8_D24UnormPack32 => {
+                let bits = u32_at(pixel) & 0xff_ffff;
+                let depth = unorm8(bits as f32 / 0xff_ffff as f32);
+                [depth, depth, depth, 255]
+            },
+            Format::A2B10G10R10UnormPack32 => {
+                let bits = u32_at(pixel);
+                let channel = |shift: u32, max: u32| unorm8(((bits >> shift) & max) as f32 / max as f32);
+                [channel(0, 0x3ff), channel(10, 0x3ff), channel(20, 0x3ff), channel(30, 0x3)]
+            },
+            Format::R32Sfloat | Format::D32Sfloat => {
+                let value = unorm8(f32_at(pixel, 0));
+                [value, value, value, 255]
+            },
+            Format::R16G16B16A16Sfloat => [
+                unorm8(f16_at(pixel, 0)), unorm8(f16_at(pixel, 1)), unorm8(f16_at(pixel, 2)), unorm8(f16_at(pixel, 3))
+            ],
+            Format::R32G32B32A32Sfloat => [
+                unorm8(f32_at(pixel, 0)), unorm8(f32_at(pixel, 1)), unorm8(f32_at(pixel, 2)), unorm8(f32_at(pixel, 3))
+            ],
+            _ => return Err("Readback of this attachment format is not supported.")
+        };
+        rgba.extend_from_slice(&converted);
+    }
+
+    return ImageBuffer::from_raw(dimensions[0], dimensions[1], rgba).ok_or("Readback buffer is smaller than the image.");
+}
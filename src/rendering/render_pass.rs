@@ -0,0 +1,174 @@
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::format::FormatTy;
+use vulkano::framebuffer::AttachmentDescription;
+use vulkano::framebuffer::AttachmentsList;
+use vulkano::framebuffer::Framebuffer;
+use vulkano::framebuffer::LoadOp;
+use vulkano::framebuffer::PassDependencyDescription;
+use vulkano::framebuffer::PassDescription;
+use vulkano::framebuffer::RenderPassDesc;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::RenderPassDescClearValues;
+use vulkano::image::ImageViewAccess;
+use vulkano::SafeDeref;
+
+// Render pass description generated from a physical pass of the render graph
+#[derive(Clone, Debug, Default)]
+pub struct GraphRenderPassDesc {
+    attachments: Vec<AttachmentDescription>,
+    subpasses: Vec<PassDescription>,
+    dependencies: Vec<PassDependencyDescription>,
+}
+
+impl GraphRenderPassDesc {
+    pub fn new() -> GraphRenderPassDesc {
+        return GraphRenderPassDesc {
+            attachments: Vec::new(),
+            subpasses: Vec::new(),
+            dependencies: Vec::new(),
+        };
+    }
+
+    pub fn add_attachment(&mut self, attachment: AttachmentDescription) -> usize {
+        self.attachments.push(attachment);
+        return self.attachments.len() - 1;
+    }
+
+    pub fn add_subpass(&mut self, subpass: PassDescription) -> usize {
+        self.subpasses.push(subpass);
+        return self.subpasses.len() - 1;
+    }
+
+    pub fn add_dependency(&mut self, dependency: PassDependencyDescription) {
+        self.dependencies.push(dependency);
+    }
+
+    // Values for beginning the render pass, see clear_value
+    pub fn clear_values(&self) -> Vec<ClearValue> {
+        return self.attachments.iter()
+            .map(|attachment| {
+                if attachment.load != LoadOp::Clear && attachment.stencil_load != LoadOp::Clear {
+                    return ClearValue::None;
+                }
+                return clear_value(attachment.format);
+            })
+            .collect();
+    }
+
+    // Check values for beginning the render pass: one per attachment, of the kind the attachment's
+    // format takes if it's cleared and ClearValue::None otherwise
+    pub fn check_clear_values(&self, values: &[ClearValue]) -> Result<(), &'static str> {
+        if values.len() != self.attachments.len() {
+            return Err("Number of clear values does not match the render pass attachments.");
+        }
+        for (attachment, value) in self.attachments.iter().zip(values.iter()) {
+            let expected = if attachment.load != LoadOp::Clear && attachment.stencil_load != LoadOp::Clear {
+                ClearValue::None
+            } else {
+                clear_value(attachment.format)
+            };
+            if mem::discriminant(value) != mem::discriminant(&expected) {
+                return Err("Clear value does not match the attachment's format and load op.");
+            }
+        }
+        return Ok(());
+    }
+}
+
+unsafe impl RenderPassDesc for GraphRenderPassDesc {
+    #[inline]
+    fn num_attachments(&self) -> usize {
+        return self.attachments.len();
+    }
+
+    #[inline]
+    fn attachment_desc(&self, num: usize) -> Option<AttachmentDescription> {
+        return self.attachments.get(num).cloned();
+    }
+
+    #[inline]
+    fn num_subpasses(&self) -> usize {
+        return self.subpasses.len();
+    }
+
+    #[inline]
+    fn subpass_desc(&self, num: usize) -> Option<PassDescription> {
+        return self.subpasses.get(num).cloned();
+    }
+
+    #[inline]
+    fn num_dependencies(&self) -> usize {
+        return self.dependencies.len();
+    }
+
+    #[inline]
+    fn dependency_desc(&self, num: usize) -> Option<PassDependencyDescription> {
+        return self.dependencies.get(num).cloned();
+    }
+}
+
+unsafe impl RenderPassDescClearValues<Vec<ClearValue>> for GraphRenderPassDesc {
+    fn convert_clear_values(&self, values: Vec<ClearValue>) -> Box<dyn Iterator<Item = ClearValue>> {
+        // The renderer checks its values with check_clear_values when it's built
+        return Box::new(values.into_iter());
+    }
+}
+
+// Value an attachment of the given format is cleared to
+pub fn clear_value(format: Format) -> ClearValue {
+    return match format.ty() {
+        FormatTy::Float | FormatTy::Compressed => ClearValue::Float([0.0, 0.0, 0.0, 0.0]),
+        FormatTy::Uint => ClearValue::Uint([0, 0, 0, 0]),
+        FormatTy::Sint => ClearValue::Int([0, 0, 0, 0]),
+        FormatTy::Depth => ClearValue::Depth(1.0),
+        FormatTy::Stencil => ClearValue::Stencil(0),
+        FormatTy::DepthStencil => ClearValue::DepthStencil((1.0, 0))
+    };
+}
+
+// Framebuffer over a list of attachments only known at runtime. vulkano can only box such a list
+// without Send + Sync, which begin_render_pass requires; every attachment added here is both.
+#[derive(Clone)]
+pub struct GraphFramebuffer {
+    framebuffer: Arc<Framebuffer<Arc<dyn RenderPassAbstract + Send + Sync>, Box<dyn AttachmentsList>>>,
+}
+
+unsafe impl Send for GraphFramebuffer {}
+unsafe impl Sync for GraphFramebuffer {}
+
+impl GraphFramebuffer {
+    pub fn new(
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        attachments: &[Arc<dyn ImageViewAccess + Send + Sync>]
+    ) -> Result<GraphFramebuffer, &'static str> {
+        let mut builder = Framebuffer::start(render_pass).boxed();
+        for attachment in attachments.iter() {
+            builder = builder.add(attachment.clone())
+                .map_err(|_| "Attachment image does not match the render pass.")?
+                .boxed();
+        }
+
+        let framebuffer = builder.build().map_err(|_| "Failed to create framebuffer.")?;
+        return Ok(
+            GraphFramebuffer {
+                framebuffer: Arc::new(framebuffer)
+            }
+        );
+    }
+}
+
+impl Deref for GraphFramebuffer {
+    type Target = Framebuffer<Arc<dyn RenderPassAbstract + Send + Sync>, Box<dyn AttachmentsList>>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        return &self.framebuffer;
+    }
+}
+
+unsafe impl SafeDeref for GraphFramebuffer {}
@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use log::info;
+use log::warn;
+
+use vulkano::device::Device;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::image::ImageUsage;
+use vulkano::image::ImageViewAccess;
+use vulkano::image::SwapchainImage;
+use vulkano::swapchain;
+use vulkano::swapchain::AcquireError;
+use vulkano::swapchain::ColorSpace;
+use vulkano::swapchain::FullscreenExclusive;
+use vulkano::swapchain::PresentMode;
+use vulkano::swapchain::SupportedPresentModes;
+use vulkano::swapchain::Surface;
+use vulkano::swapchain::SurfaceTransform;
+use vulkano::swapchain::Swapchain;
+use vulkano::swapchain::SwapchainCreationError;
+use vulkano::sync::GpuFuture;
+
+use super::Renderer;
+
+// Error of Renderer::execute_frame_and_present when the swapchain must be recreated
+pub const SWAPCHAIN_OUT_OF_DATE: &str = "Swapchain is out of date.";
+
+// Backbuffer formats in order of preference. sRGB formats come first so shaders can write linear
+// color and have it encoded on store; the rest need the composite pass to apply gamma itself.
+pub const SURFACE_FORMAT_PREFERENCE: &[Format] = &[
+    Format::B8G8R8A8Srgb,
+    Format::R8G8B8A8Srgb,
+    Format::A8B8G8R8SrgbPack32,
+    Format::B8G8R8A8Unorm,
+    Format::R8G8B8A8Unorm,
+    Format::A8B8G8R8UnormPack32,
+    Format::A2B10G10R10UnormPack32
+];
+
+pub fn is_srgb(format: Format) -> bool {
+    return match format {
+        Format::R8G8B8A8Srgb
+        | Format::B8G8R8A8Srgb
+        | Format::A8B8G8R8SrgbPack32
+        | Format::R8G8B8Srgb
+        | Format::B8G8R8Srgb
+        | Format::R8G8Srgb
+        | Format::R8Srgb => true,
+        _ => false
+    };
+}
+
+// Choose the backbuffer format and color space from a surface's supported formats, e.g.
+// `surface.capabilities(physical)?.supported_formats`. Formats in the sRGB non-linear color space
+// are chosen by SURFACE_FORMAT_PREFERENCE, falling back to the first format the surface lists.
+pub fn select_surface_format(supported_formats: &[(Format, ColorSpace)]) -> Result<(Format, ColorSpace), &'static str> {
+    for format in SURFACE_FORMAT_PREFERENCE.iter() {
+        if supported_formats.contains(&(*format, ColorSpace::SrgbNonLinear)) {
+            return Ok((*format, ColorSpace::SrgbNonLinear));
+        }
+    }
+    return supported_formats.first().cloned().ok_or("Surface supports no formats.");
+}
+
+// `preferred` if the surface supports it, else FIFO, which every surface supports
+pub fn select_present_mode(supported: SupportedPresentModes, preferred: PresentMode) -> PresentMode {
+    if supported.supports(preferred) {
+        return preferred;
+    }
+    return PresentMode::Fifo;
+}
+
+// Usage of swapchain images: color output, plus whatever else the surface allows that the graph
+// might use the backbuffer for. Readback and screenshots copy from the backbuffer and passes may
+// read it as an input attachment; a renderer needing a usage the surface lacks fails its frames.
+pub fn swapchain_usage(supported: ImageUsage) -> ImageUsage {
+    return ImageUsage {
+        color_attachment: true,
+        transfer_source: supported.transfer_source,
+        input_attachment: supported.input_attachment,
+        ..ImageUsage::none()
+    };
+}
+
+// What present_frame does next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameStep {
+    // The window has no area, nothing can be presented
+    Skip,
+    // Recreate the swapchain at the window's size first
+    Recreate,
+    Render
+}
+
+// When a Presenter's swapchain must be recreated, updated with the result of each swapchain
+// operation of a frame
+#[derive(Clone, Debug, Default)]
+pub struct SwapchainState {
+    needs_recreate: bool
+}
+
+impl SwapchainState {
+    pub fn needs_recreate(&self) -> bool {
+        return self.needs_recreate;
+    }
+
+    pub fn invalidate(&mut self) {
+        self.needs_recreate = true;
+    }
+
+    pub fn next_step(&self, dimensions: [u32; 2]) -> FrameStep {
+        if dimensions[0] == 0 || dimensions[1] == 0 {
+            return FrameStep::Skip;
+        }
+        if self.needs_recreate {
+            return FrameStep::Recreate;
+        }
+        return FrameStep::Render;
+    }
+
+    // Whether the frame can go on with the recreated swapchain
+    pub fn recreated(&mut self, result: Result<(), SwapchainCreationError>) -> Result<bool, &'static str> {
+        return match result {
+            Ok(()) => {
+                self.needs_recreate = false;
+                Ok(true)
+            },
+            // The window changed again since its size was read, try next frame
+            Err(SwapchainCreationError::UnsupportedDimensions) => Ok(false),
+            Err(_) => Err("Failed to recreate swapchain")
+        };
+    }
+
+    // Whether the acquired image, suboptimal or not, can be rendered to
+    pub fn acquired(&mut self, result: Result<bool, AcquireError>) -> Result<bool, &'static str> {
+        return match result {
+            Ok(suboptimal) => {
+                // Still presentable, recreate for the next frame
+                self.needs_recreate |= suboptimal;
+                Ok(true)
+            },
+            Err(AcquireError::OutOfDate) => {
+                self.needs_recreate = true;
+                Ok(false)
+            },
+            Err(_) => Err("Failed to acquire swapchain image")
+        };
+    }
+
+    pub fn presented(&mut self, result: Result<(), &'static str>) -> Result<(), &'static str> {
+        return match result {
+            Err(err) if err == SWAPCHAIN_OUT_OF_DATE => {
+                warn!("swapchain out of date on present");
+                self.needs_recreate = true;
+                Ok(())
+            },
+            result => result
+        };
+    }
+}
+
+// A window's swapchain, presenting the BACKBUFFER of a renderer. The swapchain is recreated when
+// it goes out of date or suboptimal, at the size the window has then; the renderer recreates its
+// attachments when it sees a backbuffer of a new size.
+pub struct Presenter<W> {
+    surface: Arc<Surface<W>>,
+    swapchain: Arc<Swapchain<W>>,
+    images: Vec<Arc<SwapchainImage<W>>>,
+    state: SwapchainState
+}
+
+impl<W: Send + Sync + 'static> Presenter<W> {
+    // `format` is usually from select_surface_format, and the format `renderer` was built with
+    pub fn new(
+        device: Arc<Device>,
+        queue: &Arc<Queue>,
+        surface: Arc<Surface<W>>,
+        format: (Format, ColorSpace),
+        present_mode: PresentMode,
+        dimensions: [u32; 2]
+    ) -> Result<Presenter<W>, &'static str> {
+        let caps = surface.capabilities(device.physical_device()).map_err(|_| "Failed to get surface capabilities")?;
+
+        let present_mode = select_present_mode(caps.present_modes, present_mode);
+        let alpha = caps.supported_composite_alpha.iter().next().ok_or("Surface supports no composite alpha mode.")?;
+        // One image more than the minimum, so acquiring doesn't wait on the presentation engine
+        let image_count = caps.max_image_count.map_or(caps.min_image_count + 1, |max| max.min(caps.min_image_count + 1));
+        let usage = swapchain_usage(caps.supported_usage_flags);
+
+        let (swapchain, images) = Swapchain::new(
+            device,
+            surface.clone(),
+            image_count,
+            format.0,
+            caps.current_extent.unwrap_or(dimensions),
+            1,
+            usage,
+            queue,
+            SurfaceTransform::Identity,
+            alpha,
+            present_mode,
+            FullscreenExclusive::Default,
+            true,
+            format.1
+        ).map_err(|_| "Failed to create swapchain")?;
+        info!("swapchain format={:?} present_mode={:?} images={}", format.0, present_mode, images.len());
+
+        return Ok(Presenter {
+            surface,
+            swapchain,
+            images,
+            state: SwapchainState::default()
+        });
+    }
+
+    pub fn surface(&self) -> &Arc<Surface<W>> {
+        return &self.surface;
+    }
+
+    // Recreate the swapchain before the next frame, e.g. when the window was resized
+    pub fn invalidate(&mut self) {
+        self.state.invalidate();
+    }
+
+    // Whether the swapchain was recreated
+    fn recreate(&mut self, dimensions: [u32; 2]) -> Result<bool, &'static str> {
+        let recreated = self.swapchain.recreate_with_dimensions(dimensions).map(|(swapchain, images)| {
+            self.swapchain = swapchain;
+            self.images = images;
+        });
+        if !self.state.recreated(recreated)? {
+            return Ok(false);
+        }
+        info!("swapchain recreated dimensions={:?}", dimensions);
+        return Ok(true);
+    }
+
+    // Render a frame of `renderer` into the next swapchain image and present it. `dimensions` is
+    // the window's current size. Frames are skipped while the window has no area, or when the
+    // swapchain went out of date, which recreates it for the next frame.
+    pub fn present_frame(&mut self, renderer: &mut Renderer, queue: Arc<Queue>, dimensions: [u32; 2]) -> Result<(), &'static str> {
+        match self.state.next_step(dimensions) {
+            FrameStep::Skip => return Ok(()),
+            FrameStep::Recreate => {
+                if !self.recreate(dimensions)? {
+                    return Ok(());
+                }
+            },
+            FrameStep::Render => ()
+        }
+
+        let acquired = swapchain::acquire_next_image(self.swapchain.clone(), None);
+        let render = self.state.acquired(acquired.as_ref().map(|x| x.1).map_err(|x| *x))?;
+        let (image_index, _, acquire_future) = match acquired {
+            Ok(x) if render => x,
+            _ => return Ok(())
+        };
+
+        let backbuffer = self.images[image_index].clone() as Arc<dyn ImageViewAccess + Send + Sync>;
+        let result = renderer.execute_frame_and_present(
+            queue,
+            self.swapchain.clone(),
+            image_index,
+            backbuffer,
+            acquire_future.boxed()
+        );
+        return self.state.presented(result.map(|_| ()));
+    }
+}
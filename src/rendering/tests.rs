@@ -0,0 +1,1901 @@
+use typed_arena::Arena;
+
+use vulkano::format::Format;
+use vulkano::framebuffer::RenderPassDesc;
+use vulkano::pipeline::vertex::InputRate;
+use vulkano::swapchain::AcquireError;
+use vulkano::swapchain::ColorSpace;
+use vulkano::swapchain::SwapchainCreationError;
+
+use super::pipeline_state::AttachmentBlend;
+use super::pipeline_state::Compare;
+use super::pipeline_state::CullMode;
+use super::pipeline_state::DepthStencil;
+use super::pipeline_state::DynamicStates;
+use super::pipeline_state::FrontFace;
+use super::pipeline_state::PrimitiveTopology;
+use super::pipeline_state::StencilOp;
+use super::swapchain::FrameStep;
+use super::swapchain::SwapchainState;
+
+use super::*;
+
+use std::sync::Mutex;
+use std::sync::Once;
+use std::thread::ThreadId;
+
+// The render config from the Journal's 10/12 entry, without pipelines
+fn journal_example(builder: &mut RendererBuilder) {
+    let depth = builder.add_attachment("depth", Format::D24Unorm_S8Uint, 1);
+    let albedo = builder.add_attachment("albedo", Format::R8G8B8A8Unorm, 1);
+    let normal = builder.add_attachment("normal", Format::R8G8Unorm, 1);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let blur = builder.add_attachment("blur", Format::R8G8B8A8Unorm, 1);
+    let blur2 = builder.add_attachment("blur2", Format::R8G8B8A8Unorm, 1);
+    let velocity = builder.add_attachment("velocity", Format::R8G8B8A8Unorm, 1);
+    let motion_blur = builder.add_attachment("motion_blur", Format::R8G8B8A8Unorm, 1);
+
+    let gbuffer = builder.add_pass("gbuffer");
+    builder.add_color_output(gbuffer, albedo);
+    builder.add_color_output(gbuffer, normal);
+    builder.set_depth_output(gbuffer, depth);
+
+    let lighting = builder.add_pass("lighting");
+    builder.add_color_output(lighting, color);
+    builder.add_input_attachment(lighting, albedo);
+    builder.add_input_attachment(lighting, normal);
+    builder.set_depth_input(lighting, depth);
+
+    let blur_pass = builder.add_pass("blur_pass");
+    builder.add_color_output(blur_pass, blur);
+    builder.add_input_attachment(blur_pass, color);
+
+    let blur_pass2 = builder.add_pass("blur_pass2");
+    builder.add_color_output(blur_pass2, blur2);
+    builder.add_input_attachment(blur_pass2, blur);
+
+    let composite_pass = builder.add_pass("composite_pass");
+    builder.add_color_output(composite_pass, builder.get_backbuffer_attachment());
+    builder.add_input_attachment(composite_pass, color);
+    builder.add_input_attachment(composite_pass, blur);
+    builder.add_input_attachment(composite_pass, blur2);
+    builder.add_input_attachment(composite_pass, motion_blur);
+
+    let velocity_pass = builder.add_pass("velocity_pass");
+    builder.add_color_output(velocity_pass, velocity);
+
+    let motion_blur_pass = builder.add_pass("motion_blur_pass");
+    builder.add_color_output(motion_blur_pass, motion_blur);
+    builder.add_input_attachment(motion_blur_pass, velocity);
+    builder.add_input_attachment(motion_blur_pass, color);
+}
+
+fn schedule(builder: &RendererBuilder) -> Result<Vec<&'static str>, &'static str> {
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena)?;
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter())?;
+    return Ok(scheduled.iter().map(|x| x.pass.name).collect());
+}
+
+fn validate(builder: &RendererBuilder) -> Result<(), &'static str> {
+    return builder.validate_passes();
+}
+
+#[test]
+fn create_pass_nodes_links_writers_and_readers() {
+    let mut builder = RendererBuilder::new();
+    journal_example(&mut builder);
+
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    assert_eq!(pass_nodes.len(), 7);
+
+    let node = |name| *pass_nodes.iter().find(|x| x.pass.name == name).unwrap();
+    let dependencies = |name| {
+        let mut names: Vec<(&str, &str)> = node(name).dependencies.borrow().iter()
+            .map(|x| (x.pass_node.pass.name, x.attachment.name))
+            .collect();
+        names.sort();
+        return names;
+    };
+
+    assert!(dependencies("gbuffer").is_empty());
+    assert!(dependencies("velocity_pass").is_empty());
+    assert_eq!(dependencies("lighting"), vec![("gbuffer", "albedo"), ("gbuffer", "depth"), ("gbuffer", "normal")]);
+    assert_eq!(dependencies("motion_blur_pass"), vec![("lighting", "color"), ("velocity_pass", "velocity")]);
+    assert_eq!(
+        dependencies("composite_pass"),
+        vec![("blur_pass", "blur"), ("blur_pass2", "blur2"), ("lighting", "color"), ("motion_blur_pass", "motion_blur")]
+    );
+
+    // Every dependency has a matching dependent on the writer's side
+    for pass_node in pass_nodes.iter() {
+        for dependency in pass_node.dependencies.borrow().iter() {
+            assert!(dependency.pass_node.dependents.borrow().iter()
+                .any(|x| eq(x.pass_node, *pass_node) && eq(x.attachment, dependency.attachment)));
+        }
+    }
+}
+
+#[test]
+fn create_pass_nodes_rejects_name_collisions() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let pass = builder.add_pass("pass");
+    builder.add_color_output(pass, color);
+    let pass = builder.add_pass("pass");
+    builder.add_input_attachment(pass, color);
+
+    assert_eq!(schedule(&builder), Err("Pass name collision"));
+}
+
+#[test]
+fn builder_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RendererBuilder>();
+}
+
+#[test]
+fn builder_can_be_edited_after_scheduling() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let lighting = builder.add_pass("lighting");
+    builder.add_color_output(lighting, color);
+    assert_eq!(schedule(&builder), Ok(vec!["lighting"]));
+
+    // Handles stay valid as the graph grows
+    let tonemap = builder.add_pass("tonemap");
+    builder.add_input_attachment(tonemap, color);
+    builder.add_color_output(tonemap, builder.get_backbuffer_attachment());
+    assert_eq!(schedule(&builder), Ok(vec!["lighting", "tonemap"]));
+}
+
+#[test]
+fn schedule_passes_matches_journal_order() {
+    let mut builder = RendererBuilder::new();
+    journal_example(&mut builder);
+
+    assert_eq!(
+        schedule(&builder).unwrap(),
+        vec!["gbuffer", "velocity_pass", "lighting", "blur_pass", "blur_pass2", "motion_blur_pass", "composite_pass"]
+    );
+}
+
+#[test]
+fn schedule_passes_rejects_cycles() {
+    let mut builder = RendererBuilder::new();
+    let a = builder.add_attachment("a", Format::R8G8B8A8Unorm, 1);
+    let b = builder.add_attachment("b", Format::R8G8B8A8Unorm, 1);
+    let c = builder.add_attachment("c", Format::R8G8B8A8Unorm, 1);
+
+    let first = builder.add_pass("first");
+    builder.add_color_output(first, c);
+
+    let second = builder.add_pass("second");
+    builder.add_input_attachment(second, c);
+    builder.add_input_attachment(second, b);
+    builder.add_color_output(second, a);
+
+    let third = builder.add_pass("third");
+    builder.add_input_attachment(third, a);
+    builder.add_color_output(third, b);
+
+    assert_eq!(schedule(&builder), Err("Cyclical render graph provided"));
+}
+
+#[test]
+fn validate_passes_checks_depth_formats() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let pass = builder.add_pass("depth_output");
+    builder.set_depth_output(pass, color);
+    assert_eq!(validate(&builder), Err("Cannot set non-depth attachment to depth output."));
+
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let pass = builder.add_pass("depth_input");
+    builder.set_depth_input(pass, color);
+    assert_eq!(validate(&builder), Err("Cannot set non-depth attachment to depth input."));
+
+    let mut builder = RendererBuilder::new();
+    for (i, format) in [Format::D16Unorm, Format::D24Unorm_S8Uint, Format::D32Sfloat, Format::D32Sfloat_S8Uint].iter().enumerate() {
+        let depth = builder.add_attachment(["depth_a", "depth_b", "depth_c", "depth_d"][i], *format, 1);
+        let pass = builder.add_pass(["a", "b", "c", "d"][i]);
+        builder.set_depth_output(pass, depth);
+    }
+    assert_eq!(validate(&builder), Ok(()));
+}
+
+#[test]
+fn validate_passes_checks_read_only_depth() {
+    let mut builder = RendererBuilder::new();
+    let depth = builder.add_attachment("depth", Format::D24Unorm_S8Uint, 1);
+    let other = builder.add_attachment("other", Format::D24Unorm_S8Uint, 1);
+    let pass = builder.add_pass("pass");
+    builder.set_depth_input(pass, depth);
+    builder.set_depth_output(pass, other);
+    assert_eq!(validate(&builder), Err("Depth input and depth output of a pass must be the same attachment."));
+
+    let mut builder = RendererBuilder::new();
+    let depth = builder.add_attachment("depth", Format::D24Unorm_S8Uint, 1);
+    let pass = builder.add_pass("pass");
+    builder.set_depth_input(pass, depth);
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        depth_stencil: DepthStencil::simple_depth_test(),
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Err("Depth write enabled on a pass without a depth output."));
+
+    let mut stencil_write = DepthStencil {
+        depth_write: false,
+        ..DepthStencil::simple_depth_test()
+    };
+    stencil_write.stencil_front.compare = Compare::Always;
+    stencil_write.stencil_front.pass_op = StencilOp::Replace;
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        depth_stencil: stencil_write.clone(),
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Err("Stencil write enabled on a pass without a depth output."));
+
+    // Testing and writing the same attachment is fine
+    builder.set_depth_output(pass, depth);
+    assert_eq!(validate(&builder), Ok(()));
+}
+
+#[test]
+fn validate_passes_checks_pipeline_state_against_attachments() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let depth = builder.add_attachment("depth", Format::D32Sfloat, 1);
+    let pass = builder.add_pass("pass");
+    builder.add_color_output(pass, color);
+    assert_eq!(validate(&builder), Ok(()));
+
+    // Blend states are given per color output or not at all
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        blend: vec![AttachmentBlend::alpha_blending(), AttachmentBlend::pass_through()],
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Err("Blend state count does not match color output count."));
+
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        depth_stencil: DepthStencil {
+            depth_write: false,
+            ..DepthStencil::simple_depth_test()
+        },
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Err("Depth test enabled on a pass without a depth attachment."));
+
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        depth_stencil: DepthStencil {
+            depth_bounds_test: DepthBounds::Fixed(0.0..0.5),
+            ..DepthStencil::disabled()
+        },
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Err("Depth bounds test enabled on a pass without a depth attachment."));
+
+    // D32Sfloat has no stencil aspect to test against
+    let mut stencil_test = DepthStencil::simple_depth_test();
+    stencil_test.stencil_front.compare = Compare::Equal;
+    stencil_test.stencil_front.pass_op = StencilOp::Replace;
+    builder.set_depth_output(pass, depth);
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        depth_stencil: stencil_test,
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Err("Stencil test enabled on a pass without a stencil attachment."));
+
+    builder.set_pipeline_state(pass, PipelineStateDesc {
+        depth_stencil: DepthStencil::simple_depth_test(),
+        blend: vec![AttachmentBlend::alpha_blending()],
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Ok(()));
+}
+
+#[derive(Default, Copy, Clone)]
+struct StateVertex {
+    position: [f32; 2]
+}
+
+vulkano::impl_vertex!(StateVertex, position);
+
+crate::render_config!(
+    name: state_config,
+    attachments: {
+        depth: {
+            format: Format::D24Unorm_S8Uint
+        },
+        albedo: {
+            format: Format::R8G8B8A8Unorm
+        },
+        trail: {
+            format: Format::R8G8B8A8Unorm
+        }
+    },
+    default_vertex_bindings: [
+        {
+            vertex_type_name: StateVertex,
+            input_rate: 0,
+            attributes: {
+                position: [f32; 2]
+            }
+        }
+    ],
+    graphics_passes: {
+        gbuffer: {
+            color_outputs: [albedo],
+            depth_stencil_output: {depth},
+            input_attachments: [],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                state: {
+                    cull_mode: CullMode::Back,
+                    front_face: FrontFace::Clockwise,
+                    depth_stencil: DepthStencil::simple_depth_test()
+                }
+            }
+        },
+        sword_trail: {
+            color_outputs: [trail],
+            depth_stencil_output: {},
+            input_attachments: [],
+            depth_stencil_input: {depth},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: gbuffer,
+                state: {
+                    topology: PrimitiveTopology::TriangleStrip,
+                    polygon_mode: PolygonMode::Line,
+                    depth_stencil: DepthStencil {
+                        depth_compare: Compare::LessOrEqual,
+                        depth_write: false,
+                        ..DepthStencil::disabled()
+                    },
+                    blend: vec![AttachmentBlend::alpha_blending()],
+                    dynamic_states: DynamicStates {
+                        scissor: true,
+                        ..DynamicStates::default()
+                    }
+                }
+            }
+        }
+    }
+);
+
+#[test]
+fn render_config_declares_pass_state() {
+    let builder = state_config::builder().unwrap();
+    let state = |name| &builder.passes.iter().find(|x| x.name == name).unwrap().pipeline_state;
+
+    let gbuffer = state("gbuffer");
+    assert!(matches!(gbuffer.cull_mode, CullMode::Back));
+    assert!(matches!(gbuffer.front_face, FrontFace::Clockwise));
+    assert!(gbuffer.has_depth_test());
+    assert!(gbuffer.has_depth_write());
+    assert_eq!(gbuffer.depth_stencil.depth_compare, Compare::Less);
+    // Fields the config leaves out keep their defaults
+    assert_eq!(gbuffer.topology, PrimitiveTopology::TriangleList);
+    assert!(gbuffer.blend.is_empty());
+    assert_eq!(gbuffer.dynamic_states, DynamicStates::default());
+
+    let trail = state("sword_trail");
+    assert_eq!(trail.topology, PrimitiveTopology::TriangleStrip);
+    assert_eq!(trail.polygon_mode, PolygonMode::Line);
+    assert!(matches!(trail.cull_mode, CullMode::None));
+    assert!(trail.has_depth_test());
+    assert!(!trail.has_depth_write());
+    assert_eq!(trail.blend, vec![AttachmentBlend::alpha_blending()]);
+    assert!(trail.dynamic_states.scissor);
+    assert!(!trail.dynamic_states.line_width);
+
+    assert_eq!(validate(&builder), Ok(()));
+}
+
+#[test]
+fn sampled_inputs_depend_on_writers_across_render_passes() {
+    let mut builder = RendererBuilder::new();
+    let shadow = builder.add_attachment("shadow", Format::D32Sfloat, 1);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let shadow_pass = builder.add_pass("shadow_pass");
+    builder.set_depth_output(shadow_pass, shadow);
+    let lighting = builder.add_pass("lighting");
+    builder.add_sampled_input(lighting, shadow);
+    builder.add_color_output(lighting, color);
+    assert_eq!(validate(&builder), Ok(()));
+    assert!(builder.attachment(shadow).usage.sampled);
+    assert!(!builder.attachment(shadow).usage.input_attachment);
+
+    {
+        let arena = Arena::new();
+        let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+        let dependencies = pass_nodes[1].dependencies.borrow();
+        assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0].usage, vk_sys::IMAGE_USAGE_SAMPLED_BIT);
+        assert!(dependencies[0].requires_external_dep());
+        assert_eq!(dependencies[0].destination_scope().0, PipelineStages { fragment_shader: true, ..PipelineStages::none() });
+
+        // The sampled image isn't part of the reader's framebuffer
+        let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+        let physical_passes = RendererBuilder::merge_passes(&scheduled);
+        assert_eq!(physical_passes.len(), 2);
+        assert_eq!(physical_passes[1].attachments(), vec![color]);
+    }
+
+    // Sampling an attachment of the pass's own framebuffer is a feedback loop
+    builder.add_sampled_input(lighting, color);
+    assert_eq!(validate(&builder), Err("A pass cannot sample an attachment it also uses as an attachment."));
+}
+
+#[test]
+fn read_write_depth_passes_are_ordered_by_declaration() {
+    let mut builder = RendererBuilder::new();
+    let depth = builder.add_attachment("depth", Format::D24Unorm_S8Uint, 1);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+
+    let pass = builder.add_pass("gbuffer");
+    builder.set_depth_output(pass, depth);
+    for name in ["decal_a", "decal_b"].iter() {
+        let decal = builder.add_pass(name);
+        builder.set_depth_input(decal, depth);
+        builder.set_depth_output(decal, depth);
+    }
+    let lighting = builder.add_pass("lighting");
+    builder.set_depth_input(lighting, depth);
+    builder.add_color_output(lighting, color);
+
+    assert_eq!(schedule(&builder), Ok(vec!["gbuffer", "decal_a", "decal_b", "lighting"]));
+}
+
+#[test]
+fn depth_input_uses_read_only_layout_in_merged_pass() {
+    let mut builder = RendererBuilder::new();
+    let depth = builder.add_attachment("depth", Format::D24Unorm_S8Uint, 1);
+    let albedo = builder.add_attachment("albedo", Format::R8G8B8A8Unorm, 1);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+
+    let gbuffer = builder.add_pass("gbuffer");
+    builder.add_color_output(gbuffer, albedo);
+    builder.set_depth_output(gbuffer, depth);
+    builder.set_pipeline_state(gbuffer, PipelineStateDesc {
+        depth_stencil: DepthStencil::simple_depth_test(),
+        ..PipelineStateDesc::default()
+    });
+
+    let lighting = builder.add_pass("lighting");
+    builder.add_input_attachment(lighting, albedo);
+    builder.add_color_output(lighting, color);
+    builder.set_depth_input(lighting, depth);
+    builder.set_pipeline_state(lighting, PipelineStateDesc {
+        depth_stencil: DepthStencil {
+            depth_write: false,
+            ..DepthStencil::simple_depth_test()
+        },
+        ..PipelineStateDesc::default()
+    });
+    assert_eq!(validate(&builder), Ok(()));
+
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+    let physical_passes = RendererBuilder::merge_passes(&scheduled);
+    assert_eq!(physical_passes.len(), 1);
+
+    let desc = physical_passes[0].render_pass_desc(&builder, &[], &[]);
+    let depth_index = physical_passes[0].attachments().iter().position(|x| *x == depth).unwrap();
+    let depth_layout = |subpass| desc.subpass_desc(subpass).unwrap().depth_stencil.unwrap();
+    assert_eq!(depth_layout(0), (depth_index, ImageLayout::DepthStencilAttachmentOptimal));
+    assert_eq!(depth_layout(1), (depth_index, ImageLayout::DepthStencilReadOnlyOptimal));
+
+    // Nothing tests against stencil, so it is neither cleared nor kept
+    let depth_desc = desc.attachment_desc(depth_index).unwrap();
+    assert_eq!(depth_desc.load, LoadOp::Clear);
+    assert_eq!(depth_desc.stencil_load, LoadOp::DontCare);
+    assert_eq!(depth_desc.stencil_store, StoreOp::DontCare);
+}
+
+// Names of the subpasses of each physical pass
+fn merged(builder: &RendererBuilder) -> Vec<Vec<&'static str>> {
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+    return RendererBuilder::merge_passes(&scheduled).iter()
+        .map(|x| x.subpasses.iter().map(|subpass| subpass.pass.name).collect())
+        .collect();
+}
+
+#[test]
+fn merge_passes_keeps_attachment_dependencies_in_a_render_pass() {
+    let mut builder = RendererBuilder::new();
+    journal_example(&mut builder);
+
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+    let physical_passes = RendererBuilder::merge_passes(&scheduled);
+
+    // A pass reading attachments joins the render pass of their writers, after them, with a
+    // subpass dependency on each
+    for (i, physical_pass) in physical_passes.iter().enumerate() {
+        let desc = physical_pass.render_pass_desc(&builder, &[], &[]);
+        for (subpass, pass_node) in physical_pass.subpasses.iter().enumerate() {
+            for dependency in pass_node.dependencies.borrow().iter() {
+                let source = physical_pass.subpass_index(dependency.pass_node.pass.id);
+                assert!(
+                    source.map_or(false, |x| x < subpass),
+                    "{} is not after {} in render pass {}", pass_node.pass.name, dependency.pass_node.pass.name, i
+                );
+                assert!((0..desc.num_dependencies())
+                    .map(|x| desc.dependency_desc(x).unwrap())
+                    .any(|x| x.source_subpass == source.unwrap() && x.destination_subpass == subpass));
+            }
+        }
+        assert!(physical_pass.external_dependencies.is_empty());
+    }
+}
+
+#[test]
+fn merge_passes_splits_on_external_dependencies() {
+    let mut builder = RendererBuilder::new();
+    let shadow = builder.add_attachment("shadow", Format::D32Sfloat, 1);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let post = builder.add_attachment("post", Format::R8G8B8A8Unorm, 1);
+
+    let shadow_pass = builder.add_pass("shadow_pass");
+    builder.set_depth_output(shadow_pass, shadow);
+    let lighting = builder.add_pass("lighting");
+    builder.add_sampled_input(lighting, shadow);
+    builder.add_color_output(lighting, color);
+    let post_pass = builder.add_pass("post_pass");
+    builder.add_input_attachment(post_pass, color);
+    builder.add_color_output(post_pass, post);
+
+    // Sampling the shadow map needs it written in an earlier render pass
+    assert_eq!(merged(&builder), vec![vec!["shadow_pass"], vec!["lighting", "post_pass"]]);
+
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+    let physical_passes = RendererBuilder::merge_passes(&scheduled);
+    assert!(physical_passes[1].is_external_dep(pass_nodes[0]));
+
+    // Another pass sampling the shadow map prefers the render pass that already waits on it
+    let fog = builder.add_attachment("fog", Format::R8G8B8A8Unorm, 1);
+    let fog_pass = builder.add_pass("fog_pass");
+    builder.add_sampled_input(fog_pass, shadow);
+    builder.add_color_output(fog_pass, fog);
+    assert_eq!(merged(&builder), vec![vec!["shadow_pass"], vec!["lighting", "fog_pass", "post_pass"]]);
+}
+
+#[test]
+fn merge_passes_needs_every_attachment_writer_in_the_render_pass() {
+    let mut builder = RendererBuilder::new();
+    let shadow = builder.add_attachment("shadow", Format::D32Sfloat, 1);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let composite = builder.add_attachment("composite", Format::R8G8B8A8Unorm, 1);
+
+    let shadow_pass = builder.add_pass("shadow_pass");
+    builder.set_depth_output(shadow_pass, shadow);
+    let lighting = builder.add_pass("lighting");
+    builder.add_sampled_input(lighting, shadow);
+    builder.add_color_output(lighting, color);
+
+    // Reading attachments written in two different render passes fits in neither
+    let debug_view = builder.add_pass("debug_view");
+    builder.set_depth_input(debug_view, shadow);
+    builder.add_input_attachment(debug_view, color);
+    builder.add_color_output(debug_view, composite);
+    assert_eq!(merged(&builder), vec![vec!["shadow_pass"], vec!["lighting"], vec!["debug_view"]]);
+}
+
+// Randomized checks of the scheduler and merge step over generated graphs. Each case is generated
+// from its seed, which is included in every assertion message so failures can be reproduced.
+
+const FUZZ_CASES: u64 = 500;
+
+// xorshift64, enough to generate graphs without pulling in a rand dependency
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // The state must never be zero
+        return Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        return x;
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        return (self.next() % n as u64) as usize;
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        return self.next() % 100 < percent;
+    }
+}
+
+#[derive(Default)]
+struct RandomPass {
+    color_outputs: Vec<usize>,
+    depth_output: Option<usize>,
+    inputs: Vec<usize>,
+    depth_input: Option<usize>,
+    // Read through a sampler, which splits the pass from the writer's render pass
+    sampled: Vec<usize>,
+}
+
+// A random DAG: every pass writes fresh attachments and only reads attachments written by passes
+// generated before it. Attachments are indices into `depth`, which says whether each is a depth one.
+struct RandomGraph {
+    depth: Vec<bool>,
+    passes: Vec<RandomPass>,
+    // Order the passes are added to the builder in
+    insertion_order: Vec<usize>,
+}
+
+impl RandomGraph {
+    fn generate(rng: &mut Rng) -> RandomGraph {
+        let mut graph = RandomGraph {
+            depth: Vec::new(),
+            passes: Vec::new(),
+            insertion_order: Vec::new(),
+        };
+
+        let pass_count = 1 + rng.below(12);
+        for _ in 0..pass_count {
+            let mut pass = RandomPass::default();
+
+            for (attachment, is_depth) in graph.depth.iter().enumerate() {
+                if !is_depth && rng.chance(25) {
+                    pass.inputs.push(attachment);
+                }
+            }
+
+            let written_depth: Vec<usize> = (0..graph.depth.len()).filter(|x| graph.depth[*x]).collect();
+            if !written_depth.is_empty() && rng.chance(30) {
+                pass.depth_input = Some(written_depth[rng.below(written_depth.len())]);
+            } else if rng.chance(20) {
+                graph.depth.push(true);
+                pass.depth_output = Some(graph.depth.len() - 1);
+            }
+
+            // Anything not already read as an attachment of the pass
+            for attachment in 0..graph.depth.len() {
+                let is_read = pass.inputs.contains(&attachment) || pass.depth_input == Some(attachment);
+                if !is_read && pass.depth_output != Some(attachment) && rng.chance(10) {
+                    pass.sampled.push(attachment);
+                }
+            }
+
+            for _ in 0..1 + rng.below(2) {
+                graph.depth.push(false);
+                pass.color_outputs.push(graph.depth.len() - 1);
+            }
+
+            graph.passes.push(pass);
+        }
+
+        // Shuffle so the schedule can't just follow declaration order
+        graph.insertion_order = (0..pass_count).collect();
+        for i in (1..pass_count).rev() {
+            let j = rng.below(i + 1);
+            graph.insertion_order.swap(i, j);
+        }
+
+        return graph;
+    }
+
+    // Passes are named `p<generation index>`
+    fn build(&self, builder: &mut RendererBuilder) {
+        let leak = |name: String| -> &'static str { Box::leak(name.into_boxed_str()) };
+
+        let attachments: Vec<AttachmentId> = self.depth.iter()
+            .enumerate()
+            .map(|(i, is_depth)| {
+                let format = if *is_depth { Format::D32Sfloat } else { Format::R8G8B8A8Unorm };
+                return builder.add_attachment(leak(format!("a{}", i)), format, 1);
+            })
+            .collect();
+
+        for i in self.insertion_order.iter() {
+            let random_pass = &self.passes[*i];
+            let pass = builder.add_pass(leak(format!("p{}", i)));
+            for output in random_pass.color_outputs.iter() {
+                builder.add_color_output(pass, attachments[*output]);
+            }
+            if let Some(output) = random_pass.depth_output {
+                builder.set_depth_output(pass, attachments[output]);
+            }
+            for input in random_pass.inputs.iter() {
+                builder.add_input_attachment(pass, attachments[*input]);
+            }
+            if let Some(input) = random_pass.depth_input {
+                builder.set_depth_input(pass, attachments[input]);
+            }
+            for input in random_pass.sampled.iter() {
+                builder.add_sampled_input(pass, attachments[*input]);
+            }
+        }
+    }
+}
+
+// Index of the pass writing each attachment, by generation index
+fn writers(graph: &RandomGraph) -> Vec<usize> {
+    let mut writers = vec![0; graph.depth.len()];
+    for (i, pass) in graph.passes.iter().enumerate() {
+        for output in pass.color_outputs.iter().chain(pass.depth_output.iter()) {
+            writers[*output] = i;
+        }
+    }
+    return writers;
+}
+
+fn pass_index(name: &str) -> usize {
+    return name[1..].parse().unwrap();
+}
+
+#[test]
+fn fuzz_schedule_is_topological() {
+    for seed in 0..FUZZ_CASES {
+        let graph = RandomGraph::generate(&mut Rng::new(seed));
+        let mut builder = RendererBuilder::new();
+        graph.build(&mut builder);
+
+        let scheduled = schedule(&builder).unwrap_or_else(|e| panic!("seed {}: {}", seed, e));
+
+        // Every pass appears exactly once
+        let mut indices: Vec<usize> = scheduled.iter().map(|x| pass_index(x)).collect();
+        indices.sort();
+        assert_eq!(indices, (0..graph.passes.len()).collect::<Vec<_>>(), "seed {}", seed);
+
+        // Writers of everything a pass reads come before it
+        let writers = writers(&graph);
+        let position = |pass: usize| scheduled.iter().position(|x| pass_index(x) == pass).unwrap();
+        for (i, pass) in graph.passes.iter().enumerate() {
+            for input in pass.inputs.iter().chain(pass.depth_input.iter()).chain(pass.sampled.iter()) {
+                assert!(position(writers[*input]) < position(i), "seed {}: p{} scheduled before its input a{}", seed, i, input);
+            }
+        }
+    }
+}
+
+#[test]
+fn fuzz_cycles_are_rejected() {
+    let mut cycles = 0;
+    for seed in 0..FUZZ_CASES {
+        let mut rng = Rng::new(seed);
+        let mut graph = RandomGraph::generate(&mut rng);
+
+        // Close a cycle by making a pass read the output of a pass that reads from it
+        let writers = writers(&graph);
+        let edges: Vec<(usize, usize)> = graph.passes.iter()
+            .enumerate()
+            .flat_map(|(i, pass)| {
+                return pass.inputs.iter()
+                    .chain(pass.depth_input.iter())
+                    .chain(pass.sampled.iter())
+                    .map(move |x| (i, *x));
+            })
+            .map(|(reader, input)| (writers[input], reader))
+            .collect();
+        if edges.is_empty() {
+            continue;
+        }
+        let (writer, reader) = edges[rng.below(edges.len())];
+        let back_edge = graph.passes[reader].color_outputs[0];
+        graph.passes[writer].inputs.push(back_edge);
+        cycles += 1;
+
+        let mut builder = RendererBuilder::new();
+        graph.build(&mut builder);
+        assert_eq!(schedule(&builder), Err("Cyclical render graph provided"), "seed {}", seed);
+    }
+    assert!(cycles > FUZZ_CASES / 2);
+}
+
+#[test]
+fn json_strings_are_escaped() {
+    let json = graph_report::Json::Object(vec![
+        ("name", graph_report::Json::string("a \"quoted\" \\ path\n")),
+        ("empty", graph_report::Json::Array(Vec::new())),
+        ("score", graph_report::Json::Null)
+    ]);
+    assert_eq!(
+        json.to_pretty_string(),
+        "{\n  \"name\": \"a \\\"quoted\\\" \\\\ path\\n\",\n  \"empty\": [],\n  \"score\": null\n}\n"
+    );
+}
+
+#[test]
+fn graph_report_describes_compiled_graph() {
+    let mut builder = RendererBuilder::new();
+    journal_example(&mut builder);
+
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+    let physical_passes = RendererBuilder::merge_passes(&scheduled);
+    let descs = builder.render_pass_descs(&physical_passes);
+    let report = graph_report::graph_report(&builder, &scheduled, &physical_passes, &descs);
+
+    // The first pass had no dependencies, so was never scored
+    assert!(report.contains("\"pass\": \"gbuffer\",\n      \"overlap_score\": null"));
+    assert!(report.contains("\"pass\": \"lighting\",\n      \"overlap_score\": 0"));
+    assert!(report.contains("\"pass\": \"blur_pass\",\n      \"overlap_score\": 1"));
+
+    // No pass tests depth, so neither aspect is loaded or stored
+    assert!(report.contains(concat!(
+        "\"attachment\": \"depth\",\n",
+        "          \"format\": \"D24Unorm_S8Uint\",\n",
+        "          \"samples\": 1,\n",
+        "          \"load\": \"DontCare\",\n",
+        "          \"store\": \"DontCare\""
+    )));
+    assert!(report.contains("\"layout\": \"DepthStencilReadOnlyOptimal\""));
+    assert!(report.contains(concat!(
+        "\"name\": \"BACKBUFFER\",\n",
+        "      \"format\": \"R8G8B8A8Unorm\",\n",
+        "      \"samples\": 1,\n",
+        "      \"image\": {\n",
+        "        \"kind\": \"backbuffer\""
+    )));
+
+    // Reports of the same graph are identical
+    let descs = builder.render_pass_descs(&physical_passes);
+    assert_eq!(report, graph_report::graph_report(&builder, &scheduled, &physical_passes, &descs));
+}
+
+#[test]
+fn image_bytes_covers_formats_and_samples() {
+    assert_eq!(memory_budget::image_bytes(Format::R8G8B8A8Unorm, 1, [16, 8]), 16 * 8 * 4);
+    assert_eq!(memory_budget::image_bytes(Format::R8G8B8A8Unorm, 4, [16, 8]), 16 * 8 * 4 * 4);
+    assert_eq!(memory_budget::image_bytes(Format::D24Unorm_S8Uint, 1, [16, 8]), 16 * 8 * 4);
+    assert_eq!(memory_budget::image_bytes(Format::D32Sfloat_S8Uint, 1, [16, 8]), 16 * 8 * 8);
+    // Partial blocks take a whole block
+    assert_eq!(memory_budget::image_bytes(Format::BC1_RGBUnormBlock, 1, [6, 4]), 2 * 8);
+}
+
+#[test]
+fn estimate_memory_tracks_live_attachments() {
+    let mut builder = RendererBuilder::new();
+    let gbuffer_color = builder.add_attachment("gbuffer_color", Format::R8G8B8A8Unorm, 1);
+    let lit = builder.add_attachment("lit", Format::R16G16B16A16Sfloat, 1);
+    builder.add_attachment("unused", Format::R8G8B8A8Unorm, 1);
+    let history = builder.add_history_attachment("lit@prev", lit);
+
+    let gbuffer = builder.add_pass("gbuffer");
+    builder.add_color_output(gbuffer, gbuffer_color);
+    let lighting = builder.add_pass("lighting");
+    builder.add_input_attachment(lighting, gbuffer_color);
+    builder.add_input_attachment(lighting, history);
+    builder.add_color_output(lighting, lit);
+    let composite = builder.add_pass("composite");
+    builder.add_input_attachment(composite, lit);
+    builder.add_color_output(composite, builder.get_backbuffer_attachment());
+
+    let estimate = builder.estimate_memory([4, 4]).unwrap();
+    let allocated: Vec<(&str, u64)> = estimate.attachments.iter()
+        .filter(|x| x.allocated)
+        .map(|x| (x.name, x.bytes))
+        .collect();
+    assert_eq!(allocated, vec![("gbuffer_color", 64), ("lit", 128), ("lit@prev", 128)]);
+    assert!(!estimate.attachments.iter().any(|x| x.name == "unused" && x.allocated));
+
+    // lit and its history persist across frames, gbuffer_color is dead after lighting
+    let passes: Vec<(&str, u64)> = estimate.passes.iter().map(|x| (x.pass, x.live_bytes)).collect();
+    assert_eq!(passes, vec![("gbuffer", 320), ("lighting", 320), ("composite", 256)]);
+    assert_eq!(estimate.total_bytes, 320);
+    assert_eq!(estimate.aliased_total_bytes, 320);
+    assert!(!estimate.exceeds_budget());
+
+    builder.set_memory_budget(300, [4, 4]);
+    let estimate = builder.estimate_memory([4, 4]).unwrap();
+    assert!(estimate.exceeds_budget());
+    assert!(estimate.exceeds_budget_with_aliasing());
+}
+
+#[test]
+fn estimate_memory_aliases_disjoint_lifetimes() {
+    let mut builder = RendererBuilder::new();
+    let mut previous = None;
+    for (pass_name, attachment_name) in [("a", "a_out"), ("b", "b_out"), ("c", "c_out"), ("d", "d_out")].iter() {
+        let output = builder.add_attachment(attachment_name, Format::R8G8B8A8Unorm, 1);
+        let pass = builder.add_pass(pass_name);
+        builder.add_color_output(pass, output);
+        if let Some(input) = previous {
+            builder.add_input_attachment(pass, input);
+        }
+        previous = Some(output);
+    }
+
+    // A chain only ever needs its current input and output
+    let estimate = builder.estimate_memory([8, 8]).unwrap();
+    assert_eq!(estimate.total_bytes, 4 * 256);
+    assert_eq!(estimate.aliased_total_bytes, 2 * 256);
+
+    builder.set_memory_budget(800, [8, 8]);
+    let records = capture_build_log(|| {
+        let estimate = builder.estimate_memory([8, 8]).unwrap();
+        assert!(estimate.exceeds_budget());
+        assert!(!estimate.exceeds_budget_with_aliasing());
+    });
+    assert!(records.iter().any(|x| {
+        return x.0 == log::Level::Warn && x.1.starts_with("attachment memory exceeds budget without aliasing");
+    }));
+}
+
+#[test]
+fn memory_budget_is_checked_at_its_dimensions() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let pass = builder.add_pass("pass");
+    builder.add_color_output(pass, color);
+    assert_eq!(builder.check_memory_budget(), Ok(None));
+
+    // 16x16 needs 1024 bytes, 32x32 needs 4096
+    builder.set_memory_budget(2048, [16, 16]);
+    let records = capture_build_log(|| {
+        let estimate = builder.check_memory_budget().unwrap().unwrap();
+        assert_eq!(estimate.dimensions, [16, 16]);
+        assert!(!estimate.exceeds_budget());
+    });
+    assert!(records.iter().all(|x| x.0 != log::Level::Warn));
+
+    builder.set_memory_budget(2048, [32, 32]);
+    let records = capture_build_log(|| {
+        assert!(builder.check_memory_budget().unwrap().unwrap().exceeds_budget());
+    });
+    assert!(records.iter().any(|x| x.0 == log::Level::Warn && x.1.starts_with("attachment memory exceeds budget")));
+}
+
+// Fragment shader writing a vec4 and a uvec4, like the interfaces vulkano_shaders generates
+struct FragmentOutputs;
+
+unsafe impl ShaderInterfaceDef for FragmentOutputs {
+    type Iter = std::vec::IntoIter<vulkano::pipeline::shader::ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        let entry = |location: u32, format: Format| {
+            return vulkano::pipeline::shader::ShaderInterfaceDefEntry {
+                location: location..location + 1,
+                format,
+                name: None
+            };
+        };
+        return vec![entry(0, Format::R32G32B32A32Sfloat), entry(1, Format::R32G32B32A32Uint)].into_iter();
+    }
+}
+
+#[test]
+fn validate_passes_checks_backbuffer_outputs() {
+    let mut builder = RendererBuilder::new();
+    let ids = builder.add_attachment("ids", Format::R32Uint, 1);
+    let composite = builder.add_pass("composite");
+    builder.add_color_output(composite, builder.get_backbuffer_attachment());
+    builder.add_color_output(composite, ids);
+    builder.set_fragment_outputs(composite, &FragmentOutputs);
+    assert_eq!(validate(&builder), Ok(()));
+
+    // sRGB formats are written with float outputs too
+    builder.set_backbuffer_format(Format::B8G8R8A8Srgb);
+    assert_eq!(validate(&builder), Ok(()));
+
+    builder.set_backbuffer_format(Format::R8G8B8A8Uint);
+    assert_eq!(validate(&builder), Err("Fragment shader output is incompatible with the output target's format."));
+
+    // The uvec4 output at location 1 doesn't fit a float backbuffer
+    let mut builder = RendererBuilder::new();
+    let ids = builder.add_attachment("ids", Format::R32Uint, 1);
+    let composite = builder.add_pass("composite");
+    builder.add_color_output(composite, ids);
+    builder.add_color_output(composite, builder.get_backbuffer_attachment());
+    builder.set_fragment_outputs(composite, &FragmentOutputs);
+    assert_eq!(validate(&builder), Err("Fragment shader output is incompatible with the output target's format."));
+
+    let mut builder = RendererBuilder::new();
+    let first = builder.add_attachment("first", Format::R8G8B8A8Unorm, 1);
+    let second = builder.add_attachment("second", Format::R8G8B8A8Unorm, 1);
+    let composite = builder.add_pass("composite");
+    builder.add_color_output(composite, first);
+    builder.add_color_output(composite, second);
+    builder.add_color_output(composite, builder.get_backbuffer_attachment());
+    builder.set_fragment_outputs(composite, &FragmentOutputs);
+    assert_eq!(validate(&builder), Err("Pass writing an output target has no fragment shader output for it."));
+
+    // Other output targets are checked the same way
+    let mut builder = RendererBuilder::new();
+    let mirror = builder.add_output_target("mirror", Format::R8G8B8A8Uint);
+    let mirror_pass = builder.add_pass("mirror_pass");
+    builder.add_color_output(mirror_pass, mirror);
+    builder.set_fragment_outputs(mirror_pass, &FragmentOutputs);
+    assert_eq!(validate(&builder), Err("Fragment shader output is incompatible with the output target's format."));
+}
+
+#[test]
+fn validate_passes_checks_attachment_sizes() {
+    let mut builder = RendererBuilder::new();
+    let mirror = builder.add_output_target("mirror", Format::B8G8R8A8Srgb);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let mirror_color = builder.add_attachment("mirror_color", Format::R8G8B8A8Unorm, 1);
+    builder.set_sized_like(mirror_color, mirror);
+
+    let scene = builder.add_pass("scene");
+    builder.add_color_output(scene, color);
+    let mirror_scene = builder.add_pass("mirror_scene");
+    builder.add_color_output(mirror_scene, mirror_color);
+    let mirror_composite = builder.add_pass("mirror_composite");
+    builder.add_input_attachment(mirror_composite, mirror_color);
+    builder.add_color_output(mirror_composite, mirror);
+    assert_eq!(validate(&builder), Ok(()));
+
+    // The mirror's framebuffer can't hold an attachment at the size of the backbuffer
+    builder.add_input_attachment(mirror_composite, color);
+    assert_eq!(validate(&builder), Err("Attachments of a pass must be sized like the same output target."));
+
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let other = builder.add_attachment("other", Format::R8G8B8A8Unorm, 1);
+    builder.set_sized_like(other, color);
+    assert_eq!(validate(&builder), Err("Attachments can only be sized like an output target."));
+}
+
+#[test]
+fn merge_passes_splits_on_output_target() {
+    let mut builder = RendererBuilder::new();
+    let mirror = builder.add_output_target("mirror", Format::B8G8R8A8Srgb);
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let mirror_color = builder.add_attachment("mirror_color", Format::R8G8B8A8Unorm, 1);
+    builder.set_sized_like(mirror_color, mirror);
+
+    let scene = builder.add_pass("scene");
+    builder.add_color_output(scene, color);
+    let mirror_scene = builder.add_pass("mirror_scene");
+    builder.add_color_output(mirror_scene, mirror_color);
+    let composite = builder.add_pass("composite");
+    builder.add_input_attachment(composite, color);
+    builder.add_color_output(composite, builder.get_backbuffer_attachment());
+    let mirror_composite = builder.add_pass("mirror_composite");
+    builder.add_input_attachment(mirror_composite, mirror_color);
+    builder.add_color_output(mirror_composite, mirror);
+    assert_eq!(validate(&builder), Ok(()));
+
+    let arena = Arena::new();
+    let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+    let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+    let physical_passes = RendererBuilder::merge_passes(&scheduled);
+
+    // Passes of different sizes can't share a framebuffer, so each target gets its own render passes
+    assert!(physical_passes.len() >= 2);
+    for physical_pass in physical_passes.iter() {
+        let size = physical_pass.subpasses[0].sized_like;
+        assert!(physical_pass.subpasses.iter().all(|x| x.sized_like == size));
+    }
+    let mirror_pass = physical_passes.iter()
+        .find(|x| x.subpasses.iter().any(|x| x.pass.name == "mirror_composite"))
+        .unwrap();
+    assert_eq!(mirror_pass.subpasses[0].sized_like, mirror);
+    assert!(mirror_pass.attachments().iter().all(|x| *x == mirror || *x == mirror_color));
+}
+
+#[test]
+fn select_surface_format_prefers_srgb() {
+    let supported = [
+        (Format::B8G8R8A8Unorm, ColorSpace::SrgbNonLinear),
+        (Format::R8G8B8A8Srgb, ColorSpace::ExtendedSrgbLinear),
+        (Format::R8G8B8A8Srgb, ColorSpace::SrgbNonLinear)
+    ];
+    assert_eq!(swapchain::select_surface_format(&supported), Ok((Format::R8G8B8A8Srgb, ColorSpace::SrgbNonLinear)));
+    assert_eq!(swapchain::select_surface_format(&supported[..1]), Ok((Format::B8G8R8A8Unorm, ColorSpace::SrgbNonLinear)));
+
+    // Nothing preferred, so whatever the surface lists first
+    let supported = [(Format::R16G16B16A16Sfloat, ColorSpace::ExtendedSrgbLinear)];
+    assert_eq!(swapchain::select_surface_format(&supported), Ok(supported[0]));
+    assert_eq!(swapchain::select_surface_format(&[]), Err("Surface supports no formats."));
+
+    assert!(swapchain::is_srgb(Format::B8G8R8A8Srgb));
+    assert!(!swapchain::is_srgb(Format::B8G8R8A8Unorm));
+}
+
+// Logger capturing build records, tagged with the thread that logged them so parallel tests
+// don't see each other's output
+struct CaptureLogger {
+    records: Mutex<Vec<(ThreadId, log::Level, String)>>,
+}
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        return metadata.target() == BUILD_LOG_TARGET;
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let entry = (thread::current().id(), record.level(), format!("{}", record.args()));
+            self.records.lock().unwrap().push(entry);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger { records: Mutex::new(Vec::new()) };
+static CAPTURE_INIT: Once = Once::new();
+
+// Run f and return the build records it logged
+fn capture_build_log<F: FnOnce()>(f: F) -> Vec<(log::Level, String)> {
+    CAPTURE_INIT.call_once(|| {
+        log::set_logger(&CAPTURE_LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+    });
+
+    let id = thread::current().id();
+    f();
+
+    let mut records = CAPTURE_LOGGER.records.lock().unwrap();
+    let (ours, others) = records.drain(..).partition(|x| x.0 == id);
+    *records = others;
+    return ours.into_iter().map(|(_, level, message)| (level, message)).collect();
+}
+
+#[test]
+fn build_phases_are_logged_with_timings() {
+    let mut builder = RendererBuilder::new();
+    journal_example(&mut builder);
+
+    let records = capture_build_log(|| {
+        let arena = Arena::new();
+        let pass_nodes = {
+            let _span = BuildSpan::enter("create_nodes");
+            builder.create_pass_nodes(&arena).unwrap()
+        };
+        let scheduled = {
+            let _span = BuildSpan::enter("schedule");
+            RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap()
+        };
+        for (i, pass_node) in scheduled.iter().enumerate() {
+            pass_node.display(i);
+        }
+    });
+
+    assert_eq!(records[0], (log::Level::Debug, "begin phase=create_nodes".to_string()));
+    assert_eq!(records[1].0, log::Level::Info);
+    assert!(records[1].1.starts_with("end phase=create_nodes elapsed_us="));
+    assert_eq!(records[2], (log::Level::Debug, "begin phase=schedule".to_string()));
+    assert!(records[3].1.starts_with("end phase=schedule elapsed_us="));
+
+    // Every scheduled pass is reported at debug level
+    let scheduled: Vec<&(log::Level, String)> = records.iter()
+        .filter(|x| x.1.starts_with("scheduled "))
+        .collect();
+    assert_eq!(scheduled.len(), builder.passes.len());
+    assert!(scheduled.iter().all(|x| x.0 == log::Level::Debug));
+}
+
+// (physical pass, subpass) of a pass, which is the order passes execute in
+fn location<'a, 'rb>(seed: u64, physical_passes: &[PhysicalPass<'a, 'rb>], pass: &PassDesc) -> (usize, usize) {
+    let mut found = Vec::new();
+    for (i, physical_pass) in physical_passes.iter().enumerate() {
+        for (j, subpass) in physical_pass.subpasses.iter().enumerate() {
+            if eq(subpass.pass, pass) {
+                found.push((i, j));
+            }
+        }
+    }
+    assert_eq!(found.len(), 1, "seed {}: {} is not in exactly one physical pass", seed, pass.name);
+    return found[0];
+}
+
+#[test]
+fn fuzz_merged_passes_respect_dependencies() {
+    let mut external_dependencies = 0;
+    for seed in 0..FUZZ_CASES {
+        let graph = RandomGraph::generate(&mut Rng::new(seed));
+        let mut builder = RendererBuilder::new();
+        graph.build(&mut builder);
+        assert_eq!(validate(&builder), Ok(()), "seed {}", seed);
+
+        let arena = Arena::new();
+        let pass_nodes = builder.create_pass_nodes(&arena).unwrap();
+        let scheduled = RendererBuilder::schedule_passes(pass_nodes.iter()).unwrap();
+        let physical_passes = RendererBuilder::merge_passes(&scheduled);
+
+        for pass_node in pass_nodes.iter() {
+            let (physical_pass, subpass) = location(seed, &physical_passes, pass_node.pass);
+            for dependency in pass_node.dependencies.borrow().iter() {
+                let (dependency_physical_pass, dependency_subpass) = location(seed, &physical_passes, dependency.pass_node.pass);
+                if dependency.requires_external_dep() {
+                    external_dependencies += 1;
+                    assert!(
+                        dependency_physical_pass < physical_pass,
+                        "seed {}: {} has an external dependency on {} in the same or a later physical pass",
+                        seed, pass_node.pass.name, dependency.pass_node.pass.name
+                    );
+                } else {
+                    assert!(
+                        (dependency_physical_pass, dependency_subpass) < (physical_pass, subpass),
+                        "seed {}: {} runs before its dependency {}",
+                        seed, pass_node.pass.name, dependency.pass_node.pass.name
+                    );
+                }
+            }
+        }
+
+        for (i, physical_pass) in physical_passes.iter().enumerate() {
+            for dependency in physical_pass.external_dependencies.iter() {
+                assert!(
+                    !physical_pass.is_internal_dep(dependency.pass_node),
+                    "seed {}: physical pass {} has an external dependency on its own subpass {}",
+                    seed, i, dependency.pass_node.pass.name
+                );
+            }
+        }
+    }
+    // Sampled reads are common enough for the merge rules around them to be exercised
+    assert!(external_dependencies > FUZZ_CASES as usize / 2);
+}
+
+#[test]
+fn device_requirements_cover_graph() {
+    let mut builder = RendererBuilder::new();
+    journal_example(&mut builder);
+
+    let requirements = builder.device_requirements();
+    let depth = requirements.formats.iter().find(|x| x.name == "depth").unwrap();
+    assert_eq!(depth.candidates, vec![Format::D24Unorm_S8Uint]);
+    assert!(depth.usage.depth_stencil_attachment);
+    // The backbuffer's format comes from the surface
+    assert!(requirements.formats.iter().all(|x| x.name != BACKBUFFER_NAME));
+    assert_eq!(requirements.features, vulkano::device::Features::none());
+
+    let wireframe = builder.add_pass("wireframe");
+    builder.set_pipeline_state(wireframe, PipelineStateDesc {
+        polygon_mode: pipeline_state::PolygonMode::Line,
+        ..PipelineStateDesc::default()
+    });
+    let requirements = builder.device_requirements();
+    assert!(requirements.features.fill_mode_non_solid);
+    assert!(!requirements.features.independent_blend);
+}
+
+// A graph with one depth attachment from add_depth_attachment, built for a device supporting
+// `supported` depth formats
+fn selected_depth_format(supported: &[Format]) -> Result<Format, &'static str> {
+    let mut builder = RendererBuilder::new();
+    let depth = builder.add_depth_attachment("depth", 1);
+    let pass = builder.add_pass("depth_prepass");
+    builder.set_depth_output(pass, depth);
+
+    builder.select_formats(|format, usage, samples| {
+        assert!(usage.depth_stencil_attachment);
+        assert_eq!(samples, 1);
+        return supported.contains(&format);
+    })?;
+    return Ok(builder.attachment(depth).format);
+}
+
+#[test]
+fn select_formats_prefers_first_candidate() {
+    let all = [Format::D24Unorm_S8Uint, Format::D32Sfloat_S8Uint, Format::D32Sfloat];
+    assert_eq!(selected_depth_format(&all), Ok(Format::D24Unorm_S8Uint));
+}
+
+#[test]
+fn select_formats_falls_back_in_order() {
+    assert_eq!(
+        selected_depth_format(&[Format::D32Sfloat_S8Uint, Format::D32Sfloat]),
+        Ok(Format::D32Sfloat_S8Uint)
+    );
+    assert_eq!(selected_depth_format(&[Format::D32Sfloat]), Ok(Format::D32Sfloat));
+    // Formats that aren't candidates are never picked
+    assert_eq!(
+        selected_depth_format(&[Format::D16Unorm, Format::D32Sfloat]),
+        Ok(Format::D32Sfloat)
+    );
+}
+
+#[test]
+fn select_formats_fails_without_supported_candidate() {
+    assert_eq!(
+        selected_depth_format(&[Format::D16Unorm]),
+        Err("The device supports none of an attachment's formats for its usage.")
+    );
+}
+
+#[test]
+fn device_choice_matches_index_or_name() {
+    use device_selection::DeviceChoice;
+
+    assert_eq!(DeviceChoice::parse("1"), DeviceChoice::Index(1));
+    assert!(DeviceChoice::parse("1").matches(1, "NVIDIA GeForce"));
+    assert!(!DeviceChoice::parse("1").matches(0, "NVIDIA GeForce"));
+
+    assert!(DeviceChoice::parse("geforce").matches(0, "NVIDIA GeForce"));
+    assert!(!DeviceChoice::parse("radeon").matches(0, "NVIDIA GeForce"));
+
+    // CI picks the CPU device by the name Mesa ships it under
+    let lavapipe = "llvmpipe (LLVM 12.0.0, 256 bits)";
+    assert!(DeviceChoice::parse("lavapipe").matches(2, lavapipe));
+    assert!(DeviceChoice::parse("LLVMpipe").matches(2, lavapipe));
+}
+
+#[test]
+fn enabled_features_are_named() {
+    let features = vulkano::device::Features {
+        independent_blend: true,
+        depth_bounds: true,
+        ..vulkano::device::Features::none()
+    };
+    assert_eq!(device_selection::enabled_features(&features), vec!["independent_blend", "depth_bounds"]);
+    assert!(device_selection::enabled_features(&vulkano::device::Features::none()).is_empty());
+}
+
+// Two subpasses, the second reading the first's color output as an input attachment
+fn two_subpass_desc(format: Format, load: LoadOp, preserve: Vec<usize>, by_region: bool) -> GraphRenderPassDesc {
+    let mut desc = GraphRenderPassDesc::new();
+    for attachment_format in [format, Format::R8G8B8A8Unorm, Format::R8G8B8A8Unorm].iter() {
+        desc.add_attachment(AttachmentDescription {
+            format: *attachment_format,
+            samples: 1,
+            load,
+            store: StoreOp::Store,
+            stencil_load: LoadOp::DontCare,
+            stencil_store: StoreOp::DontCare,
+            initial_layout: ImageLayout::Undefined,
+            final_layout: ImageLayout::ColorAttachmentOptimal
+        });
+    }
+    desc.add_subpass(PassDescription {
+        color_attachments: vec![(0, ImageLayout::ColorAttachmentOptimal)],
+        depth_stencil: None,
+        input_attachments: Vec::new(),
+        resolve_attachments: Vec::new(),
+        preserve_attachments: preserve
+    });
+    desc.add_subpass(PassDescription {
+        color_attachments: vec![(1, ImageLayout::ColorAttachmentOptimal)],
+        depth_stencil: None,
+        input_attachments: vec![(0, ImageLayout::ShaderReadOnlyOptimal)],
+        resolve_attachments: Vec::new(),
+        preserve_attachments: Vec::new()
+    });
+    desc.add_dependency(PassDependencyDescription {
+        source_subpass: 0,
+        destination_subpass: 1,
+        source_stages: PipelineStages { color_attachment_output: true, ..PipelineStages::none() },
+        destination_stages: PipelineStages { fragment_shader: true, ..PipelineStages::none() },
+        source_access: AccessFlagBits { color_attachment_write: true, ..AccessFlagBits::none() },
+        destination_access: AccessFlagBits { input_attachment_read: true, ..AccessFlagBits::none() },
+        by_region
+    });
+    return desc;
+}
+
+#[test]
+fn render_pass_compatibility_ignores_load_ops_only() {
+    let desc = two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, Vec::new(), true);
+    let compatible = |other: &GraphRenderPassDesc| pipeline_cache::is_render_pass_compatible(&desc, other);
+    assert!(compatible(&two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, Vec::new(), true)));
+    assert!(compatible(&two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::DontCare, Vec::new(), true)));
+    assert!(!compatible(&two_subpass_desc(Format::R16G16B16A16Sfloat, LoadOp::Clear, Vec::new(), true)));
+    // Differences outside the subpass a pipeline is used in still matter
+    assert!(!compatible(&two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, vec![2], true)));
+    assert!(!compatible(&two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, Vec::new(), false)));
+}
+
+#[test]
+fn pipeline_cache_hits_for_compatible_render_pass() {
+    let mut cache = PipelineCache::new();
+    let desc = two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, Vec::new(), true);
+    assert_eq!(cache.get_or_create("lighting", &desc, 1, || Ok(1)), Ok(1));
+
+    let rebuilt = two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::DontCare, Vec::new(), true);
+    let pipeline = cache.get_or_create("lighting", &rebuilt, 1, || panic!("compatible render pass missed the cache"));
+    assert_eq!(pipeline, Ok(1));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn pipeline_cache_misses_for_incompatible_render_pass() {
+    let mut cache = PipelineCache::new();
+    let desc = two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, Vec::new(), true);
+    assert_eq!(cache.get_or_create("lighting", &desc, 1, || Ok(1)), Ok(1));
+
+    let other_format = two_subpass_desc(Format::R16G16B16A16Sfloat, LoadOp::Clear, Vec::new(), true);
+    assert_eq!(cache.get_or_create("lighting", &other_format, 1, || Ok(2)), Ok(2));
+    let other_dependency = two_subpass_desc(Format::R8G8B8A8Unorm, LoadOp::Clear, Vec::new(), false);
+    assert_eq!(cache.get_or_create("lighting", &other_dependency, 1, || Ok(3)), Ok(3));
+    assert_eq!(cache.get_or_create("lighting", &desc, 0, || Ok(4)), Ok(4));
+    assert_eq!(cache.get_or_create("composite", &desc, 1, || Ok(5)), Ok(5));
+    assert_eq!(cache.len(), 5);
+
+    // Each is cached separately
+    assert_eq!(cache.get_or_create("lighting", &other_dependency, 1, || Ok(6)), Ok(3));
+    assert_eq!(cache.get_or_create("lighting", &desc, 1, || Ok(6)), Ok(1));
+}
+
+crate::render_config!(
+    name: shared_shaders_config,
+    attachments: {
+        tinted: {
+            format: Format::R8G8B8A8Unorm
+        },
+        plain: {
+            format: Format::R8G8B8A8Unorm
+        }
+    },
+    default_vertex_bindings: [
+        {
+            vertex_type_name: StateVertex,
+            input_rate: 0,
+            attributes: {
+                position: [f32; 2]
+            }
+        }
+    ],
+    graphics_passes: {
+        tint_pass: {
+            color_outputs: [tinted],
+            depth_stencil_output: {},
+            input_attachments: [],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                defines: [("GRAYSCALE", "1")]
+            }
+        },
+        plain_pass: {
+            color_outputs: [plain],
+            depth_stencil_output: {},
+            input_attachments: [tinted],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: tint_pass
+            }
+        }
+    }
+);
+
+#[test]
+fn render_config_only_shares_shaders_with_same_sources() {
+    assert!(state_config::builder().is_ok());
+    assert_eq!(
+        shared_shaders_config::builder().err(),
+        Some("A pass can only share the shaders of a pass with the same shader paths and defines.")
+    );
+}
+
+crate::render_config!(
+    name: targets_config,
+    output_targets: {
+        minimap: {format: Format::B8G8R8A8Unorm}
+    },
+    attachments: {
+        minimap_color: {
+            format: Format::R8G8B8A8Unorm,
+            sized_like: minimap
+        },
+        color: {
+            format: Format::R8G8B8A8Unorm
+        }
+    },
+    default_vertex_bindings: [
+        {
+            vertex_type_name: StateVertex,
+            input_rate: 0,
+            attributes: {
+                position: [f32; 2]
+            }
+        }
+    ],
+    graphics_passes: {
+        minimap_scene: {
+            color_outputs: [minimap_color],
+            depth_stencil_output: {},
+            input_attachments: [],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                }
+            }
+        },
+        minimap_composite: {
+            color_outputs: [minimap],
+            depth_stencil_output: {},
+            input_attachments: [minimap_color],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: minimap_scene
+            }
+        },
+        scene: {
+            color_outputs: [color],
+            depth_stencil_output: {},
+            input_attachments: [],
+            sampled_inputs: [minimap_color, color @ prev],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: minimap_scene
+            }
+        }
+    }
+);
+
+#[test]
+fn render_config_declares_targets_and_sampled_inputs() {
+    let builder = targets_config::builder().unwrap();
+    let attachment = |name: &str| builder.attachments.iter().find(|x| x.name == name).unwrap();
+    let minimap = attachment("minimap");
+    assert!(minimap.is_target);
+    assert_eq!(minimap.format, Format::B8G8R8A8Unorm);
+    assert_eq!(attachment("minimap_color").sized_like, minimap.id);
+    assert_eq!(attachment("color").sized_like, builder.get_backbuffer_attachment());
+    assert_eq!(attachment("color@prev").history_of, Some(attachment("color").id));
+
+    let scene = builder.passes.iter().find(|x| x.name == "scene").unwrap();
+    assert_eq!(scene.sampled_inputs, vec![attachment("minimap_color").id, attachment("color@prev").id]);
+    assert!(scene.input_attachments.is_empty());
+    assert!(attachment("minimap_color").usage.sampled);
+}
+
+#[test]
+fn half_to_f32_converts_special_values() {
+    assert_eq!(readback::half_to_f32(0x0000), 0.0);
+    assert_eq!(readback::half_to_f32(0x3c00), 1.0);
+    assert_eq!(readback::half_to_f32(0xc000), -2.0);
+    assert_eq!(readback::half_to_f32(0x3800), 0.5);
+    assert_eq!(readback::half_to_f32(0x7bff), 65504.0);
+    // Smallest subnormal
+    assert_eq!(readback::half_to_f32(0x0001), 2f32.powi(-24));
+    assert_eq!(readback::half_to_f32(0x7c00), f32::INFINITY);
+    assert_eq!(readback::half_to_f32(0xfc00), f32::NEG_INFINITY);
+    assert!(readback::half_to_f32(0x7e00).is_nan());
+}
+
+#[test]
+fn to_rgba8_converts_formats() {
+    let pixel = |format, data: &[u8]| readback::to_rgba8(format, [1, 1], data).unwrap().get_pixel(0, 0).0;
+    assert_eq!(pixel(Format::R8G8B8A8Unorm, &[1, 2, 3, 4]), [1, 2, 3, 4]);
+    assert_eq!(pixel(Format::B8G8R8A8Srgb, &[1, 2, 3, 4]), [3, 2, 1, 4]);
+    assert_eq!(pixel(Format::R8Unorm, &[7]), [7, 7, 7, 255]);
+    assert_eq!(pixel(Format::R8G8Unorm, &[7, 9]), [7, 9, 0, 255]);
+    assert_eq!(pixel(Format::D16Unorm, &0xffffu16.to_le_bytes()), [255, 255, 255, 255]);
+    assert_eq!(pixel(Format::D32Sfloat, &0.5f32.to_le_bytes()), [128, 128, 128, 255]);
+    // Floats are clamped to [0, 1]
+    let mut rgba32 = Vec::new();
+    for value in [2.0f32, -1.0, 0.0, 1.0].iter() {
+        rgba32.extend_from_slice(&value.to_le_bytes());
+    }
+    assert_eq!(pixel(Format::R32G32B32A32Sfloat, &rgba32), [255, 0, 0, 255]);
+    // 1.0, 0.5, 0.0 and 1.0 as halfs
+    assert_eq!(pixel(Format::R16G16B16A16Sfloat, &[0x00, 0x3c, 0x00, 0x38, 0x00, 0x00, 0x00, 0x3c]), [255, 128, 0, 255]);
+    // The top byte of depth aspect reads is undefined
+    assert_eq!(pixel(Format::X8_D24UnormPack32, &0xab_ff_ff_ffu32.to_le_bytes()), [255, 255, 255, 255]);
+    assert_eq!(pixel(Format::X8_D24UnormPack32, &0xab_00_00_00u32.to_le_bytes()), [0, 0, 0, 255]);
+    // Red 1.0, green 0.5, blue 0.0, alpha 1.0
+    let packed: u32 = 0x3ff | (0x200 << 10) | (0x3 << 30);
+    assert_eq!(pixel(Format::A2B10G10R10UnormPack32, &packed.to_le_bytes()), [255, 128, 0, 255]);
+
+    assert!(readback::to_rgba8(Format::BC1_RGBUnormBlock, [1, 1], &[0; 8]).is_err());
+    assert!(readback::to_rgba8(Format::R8G8B8A8Unorm, [2, 2], &[0; 4]).is_err());
+}
+
+#[test]
+fn material_uniforms_change_only_between_frames() {
+    // Up to date buffers are left alone, even within a frame
+    assert_eq!(material::needs_uniform_write((1, Some(5)), 1, 5), Ok(false));
+    assert_eq!(material::needs_uniform_write((0, None), 1, 0), Ok(true));
+    // The frame slot comes around again a few frames later
+    assert_eq!(material::needs_uniform_write((1, Some(2)), 2, 5), Ok(true));
+    assert_eq!(
+        material::needs_uniform_write((1, Some(5)), 2, 5),
+        Err("Material uniforms changed after it was drawn this frame.")
+    );
+}
+
+#[test]
+fn chunk_threads_spread_chunks_over_threads() {
+    assert_eq!(pass_executor::chunk_threads(7, 3), vec![vec![0, 3, 6], vec![1, 4], vec![2, 5]]);
+    // No more threads than chunks, and at least one
+    assert_eq!(pass_executor::chunk_threads(2, 8), vec![vec![0], vec![1]]);
+    assert_eq!(pass_executor::chunk_threads(3, 0), vec![vec![0, 1, 2]]);
+    assert!(pass_executor::chunk_threads(0, 4).is_empty());
+}
+
+#[test]
+fn parallel_recording_matches_serial_order() {
+    let serial_pool = pass_executor::RecordingPool::new(1);
+    let parallel_pool = pass_executor::RecordingPool::new(4);
+    let record = |pool: &pass_executor::RecordingPool, chunk_count, with_draws: bool| {
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let chunk_threads = threads.clone();
+        let recorded = pool.record_in_order(
+            chunk_count,
+            Arc::new(move |chunk| {
+                chunk_threads.lock().unwrap().insert(std::thread::current().id());
+                return Ok(format!("chunk {}", chunk));
+            }),
+            if with_draws { Some(|| Ok("draws".to_string())) } else { None }
+        ).unwrap();
+        let thread_count = threads.lock().unwrap().len();
+        return (recorded, thread_count);
+    };
+
+    for chunk_count in [0, 1, 5, 16].iter().cloned() {
+        for with_draws in [false, true].iter().cloned() {
+            let (serial, serial_threads) = record(&serial_pool, chunk_count, with_draws);
+            let mut expected: Vec<String> = (0..chunk_count).map(|x| format!("chunk {}", x)).collect();
+            if with_draws {
+                // Queued draws go in a trailing secondary
+                expected.push("draws".to_string());
+            }
+            assert_eq!(serial, expected);
+            assert!(serial_threads <= 1);
+
+            let (parallel, parallel_threads) = record(&parallel_pool, chunk_count, with_draws);
+            assert_eq!(parallel, serial);
+            assert_eq!(parallel_threads, chunk_count.min(4));
+        }
+    }
+}
+
+#[test]
+fn parallel_recording_stops_on_error() {
+    let pool = pass_executor::RecordingPool::new(4);
+    let result = pool.record_in_order(
+        8,
+        Arc::new(|chunk| if chunk == 5 { Err("chunk failed") } else { Ok(chunk) }),
+        Some(|| -> Result<usize, &'static str> { panic!("draws recorded after a failed chunk") })
+    );
+    assert_eq!(result, Err("chunk failed"));
+}
+
+#[test]
+fn recording_pool_reuses_its_threads() {
+    let pool = pass_executor::RecordingPool::new(2);
+    let record_threads = |pool: &pass_executor::RecordingPool| {
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let chunk_threads = threads.clone();
+        pool.record_in_order(
+            4,
+            Arc::new(move |chunk| {
+                chunk_threads.lock().unwrap().insert(std::thread::current().id());
+                return Ok(chunk);
+            }),
+            None::<fn() -> Result<usize, &'static str>>
+        ).unwrap();
+        let threads = threads.lock().unwrap().clone();
+        return threads;
+    };
+
+    let first = record_threads(&pool);
+    assert_eq!(first.len(), 2);
+    assert!(!first.contains(&std::thread::current().id()));
+    assert_eq!(record_threads(&pool), first);
+
+    // A panicking chunk fails the frame but leaves the threads for the next one
+    let result = pool.record_in_order(
+        4,
+        Arc::new(|chunk| -> Result<usize, &'static str> { if chunk == 1 { panic!("chunk panicked") } else { Ok(chunk) } }),
+        None::<fn() -> Result<usize, &'static str>>
+    );
+    assert_eq!(result, Err("Recording thread panicked."));
+    assert_eq!(record_threads(&pool), first);
+}
+
+#[test]
+fn swapchain_usage_covers_backbuffer_reads_the_surface_allows() {
+    let supported = ImageUsage {
+        color_attachment: true,
+        transfer_source: true,
+        input_attachment: true,
+        storage: true,
+        ..ImageUsage::none()
+    };
+    let usage = swapchain::swapchain_usage(supported);
+    assert!(usage.color_attachment && usage.transfer_source && usage.input_attachment);
+    assert!(!usage.storage);
+
+    let color_only = ImageUsage {
+        color_attachment: true,
+        ..ImageUsage::none()
+    };
+    assert_eq!(swapchain::swapchain_usage(color_only), color_only);
+}
+
+#[test]
+fn swapchain_state_recreates_on_resize() {
+    let mut state = SwapchainState::default();
+    assert_eq!(state.next_step([800, 600]), FrameStep::Render);
+    // Minimized windows have no area to present to
+    assert_eq!(state.next_step([0, 600]), FrameStep::Skip);
+
+    state.invalidate();
+    assert_eq!(state.next_step([1024, 768]), FrameStep::Recreate);
+    assert_eq!(state.next_step([1024, 0]), FrameStep::Skip);
+
+    // The window changed size again while recreating, so it's retried next frame
+    assert_eq!(state.recreated(Err(SwapchainCreationError::UnsupportedDimensions)), Ok(false));
+    assert_eq!(state.next_step([1024, 768]), FrameStep::Recreate);
+    assert_eq!(state.recreated(Err(SwapchainCreationError::SurfaceLost)), Err("Failed to recreate swapchain"));
+
+    assert_eq!(state.recreated(Ok(())), Ok(true));
+    assert_eq!(state.next_step([1024, 768]), FrameStep::Render);
+}
+
+#[test]
+fn swapchain_state_recreates_when_out_of_date() {
+    let mut state = SwapchainState::default();
+    assert_eq!(state.acquired(Ok(false)), Ok(true));
+    assert!(!state.needs_recreate());
+
+    // Suboptimal images are still rendered, the swapchain is recreated for the next frame
+    assert_eq!(state.acquired(Ok(true)), Ok(true));
+    assert!(state.needs_recreate());
+    assert_eq!(state.recreated(Ok(())), Ok(true));
+
+    // Out of date on acquire skips the frame
+    assert_eq!(state.acquired(Err(AcquireError::OutOfDate)), Ok(false));
+    assert_eq!(state.next_step([800, 600]), FrameStep::Recreate);
+    assert_eq!(state.recreated(Ok(())), Ok(true));
+    assert_eq!(state.acquired(Err(AcquireError::DeviceLost)), Err("Failed to acquire swapchain image"));
+    assert!(!state.needs_recreate());
+
+    // Out of date on present isn't an error either
+    assert_eq!(state.presented(Ok(())), Ok(()));
+    assert!(!state.needs_recreate());
+    assert_eq!(state.presented(Err(swapchain::SWAPCHAIN_OUT_OF_DATE)), Ok(()));
+    assert_eq!(state.next_step([800, 600]), FrameStep::Recreate);
+    assert_eq!(state.presented(Err("Failed to submit frame")), Err("Failed to submit frame"));
+}
+
+#[test]
+fn readback_of_failed_frame_errors() {
+    let readback = Readback::new();
+    assert!(!readback.is_ready());
+    assert_eq!(readback.wait().err(), Some("Readback frame has not been submitted."));
+
+    // What submit_frame does when the flush fails, e.g. on an out of date swapchain
+    readback.clone().fail(swapchain::SWAPCHAIN_OUT_OF_DATE);
+    assert!(readback.is_ready());
+    assert_eq!(readback.wait().err(), Some(swapchain::SWAPCHAIN_OUT_OF_DATE));
+}
+
+#[test]
+fn vertex_buffer_count_must_match_bindings() {
+    let mut vertex_input = VertexInputDesc::new();
+    vertex_input.add_binding(VertexBinding::new::<StateVertex>(InputRate::Vertex));
+    vertex_input.add_binding(VertexBinding::new::<StateVertex>(InputRate::Instance));
+
+    assert_eq!(vertex_input.check_buffer_count(2), Ok(()));
+    assert!(vertex_input.check_buffer_count(1).is_err());
+    assert!(vertex_input.check_buffer_count(3).is_err());
+}
+
+#[test]
+fn clear_values_are_checked_against_attachments() {
+    let desc = two_subpass_desc(Format::R32Uint, LoadOp::Clear, Vec::new(), true);
+    let clear_values = desc.clear_values();
+    assert_eq!(desc.check_clear_values(&clear_values), Ok(()));
+
+    assert!(desc.check_clear_values(&clear_values[..2]).is_err());
+    let mut wrong_kind = clear_values.clone();
+    wrong_kind[0] = ClearValue::Float([0.0, 0.0, 0.0, 0.0]);
+    assert!(desc.check_clear_values(&wrong_kind).is_err());
+
+    // Attachments that aren't cleared take no value
+    let loaded = two_subpass_desc(Format::R32Uint, LoadOp::Load, Vec::new(), true);
+    assert!(loaded.check_clear_values(&clear_values).is_err());
+    assert_eq!(loaded.check_clear_values(&loaded.clear_values()), Ok(()));
+}
+
+#[test]
+fn validate_passes_rejects_attachment_name_collisions() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    let other = builder.add_attachment("other", Format::R8G8B8A8Unorm, 1);
+    let pass = builder.add_pass("pass");
+    builder.add_color_output(pass, color);
+    builder.add_color_output(pass, other);
+
+    // The same history is shared
+    let history = builder.add_history_attachment("color@prev", color);
+    assert_eq!(builder.add_history_attachment("color@prev", color), history);
+    let reader = builder.add_pass("reader");
+    builder.add_input_attachment(reader, history);
+    builder.add_color_output(reader, builder.get_backbuffer_attachment());
+    assert_eq!(validate(&builder), Ok(()));
+
+    // A history of another attachment doesn't take over the name
+    let other_history = builder.add_history_attachment("color@prev", other);
+    assert_ne!(other_history, history);
+    assert!(builder.attachment(other).has_history);
+    assert_eq!(validate(&builder), Err("Attachment name collision"));
+
+    let mut builder = RendererBuilder::new();
+    builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    builder.add_attachment("color", Format::R8G8B8A8Unorm, 1);
+    assert_eq!(validate(&builder), Err("Attachment name collision"));
+}
+
+#[test]
+fn estimate_memory_sizes_attachments_by_target_and_selected_format() {
+    let mut builder = RendererBuilder::new();
+    let color = builder.add_attachment("color", Format::B8G8R8A8Unorm, 1);
+    let scene = builder.add_pass("scene");
+    builder.add_color_output(scene, color);
+    let composite = builder.add_pass("composite");
+    builder.add_input_attachment(composite, color);
+    builder.add_color_output(composite, builder.get_backbuffer_attachment());
+
+    let minimap = builder.add_output_target("minimap", Format::B8G8R8A8Unorm);
+    builder.set_target_dimensions(minimap, [4, 4]);
+    let minimap_color = builder.add_attachment("minimap_color", Format::R8G8B8A8Unorm, 1);
+    builder.add_fallback_format(minimap_color, Format::R16G16B16A16Sfloat);
+    builder.set_sized_like(minimap_color, minimap);
+    let minimap_scene = builder.add_pass("minimap_scene");
+    builder.add_color_output(minimap_scene, minimap_color);
+    let minimap_composite = builder.add_pass("minimap_composite");
+    builder.add_input_attachment(minimap_composite, minimap_color);
+    builder.add_color_output(minimap_composite, minimap);
+
+    let sizes = |estimate: &MemoryEstimate| {
+        return estimate.attachments.iter()
+            .filter(|x| x.allocated)
+            .map(|x| (x.name, x.dimensions, x.bytes))
+            .collect::<Vec<_>>();
+    };
+    let estimate = builder.estimate_memory([16, 16]).unwrap();
+    assert_eq!(sizes(&estimate), vec![("color", [16, 16], 1024), ("minimap_color", [4, 4], 64)]);
+
+    // Build selects the fallback format before checking the budget. Both chains are alive at once,
+    // so aliasing doesn't help.
+    builder.select_formats(|format, _, _| format != Format::R8G8B8A8Unorm).unwrap();
+    builder.set_memory_budget(1100, [16, 16]);
+    let records = capture_build_log(|| {
+        let estimate = builder.check_memory_budget().unwrap().unwrap();
+        assert_eq!(sizes(&estimate), vec![("color", [16, 16], 1024), ("minimap_color", [4, 4], 128)]);
+        assert_eq!(estimate.total_bytes, 1152);
+        assert!(estimate.exceeds_budget_with_aliasing());
+    });
+    assert!(records.iter().any(|x| {
+        return x.0 == log::Level::Warn && x.1.starts_with("attachment memory exceeds budget even with aliasing");
+    }));
+
+    builder.set_memory_budget(1200, [16, 16]);
+    let records = capture_build_log(|| {
+        assert!(!builder.check_memory_budget().unwrap().unwrap().exceeds_budget());
+    });
+    assert!(records.iter().all(|x| x.0 != log::Level::Warn));
+}
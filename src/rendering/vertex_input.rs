@@ -0,0 +1,141 @@
+use std::mem;
+use std::sync::Arc;
+use std::vec::IntoIter as VecIntoIter;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::pipeline::shader::ShaderInterfaceDef;
+use vulkano::pipeline::vertex::AttributeInfo;
+use vulkano::pipeline::vertex::IncompatibleVertexDefinitionError;
+use vulkano::pipeline::vertex::InputRate;
+use vulkano::pipeline::vertex::Vertex;
+use vulkano::pipeline::vertex::VertexDefinition;
+use vulkano::pipeline::vertex::VertexMemberInfo;
+use vulkano::pipeline::vertex::VertexMemberTy;
+use vulkano::pipeline::vertex::VertexSource;
+
+// One vertex buffer binding, backed by a type implementing vulkano's Vertex trait
+#[derive(Copy, Clone, Debug)]
+pub struct VertexBinding {
+    stride: usize,
+    input_rate: InputRate,
+    member: fn(&str) -> Option<VertexMemberInfo>,
+}
+
+impl VertexBinding {
+    pub fn new<V: Vertex>(input_rate: InputRate) -> VertexBinding {
+        return VertexBinding {
+            stride: mem::size_of::<V>(),
+            input_rate,
+            member: <V as Vertex>::member,
+        };
+    }
+
+    // Check that a declared attribute exists on the vertex type with the declared member type
+    pub fn check_attribute(&self, name: &str, ty: (VertexMemberTy, usize)) -> Result<(), &'static str> {
+        match (self.member)(name) {
+            Some(info) if info.ty == ty.0 && info.array_size == ty.1 => Ok(()),
+            Some(_) => Err("Vertex attribute type does not match the vertex type's member."),
+            None => Err("Vertex attribute is not a member of the vertex type.")
+        }
+    }
+}
+
+// Vertex definition assembled at runtime from the bindings declared in a render config.
+// Buffers are bound in declaration order.
+#[derive(Clone, Debug, Default)]
+pub struct VertexInputDesc {
+    bindings: Vec<VertexBinding>,
+}
+
+impl VertexInputDesc {
+    pub fn new() -> VertexInputDesc {
+        return VertexInputDesc {
+            bindings: Vec::new()
+        };
+    }
+
+    pub fn add_binding(&mut self, binding: VertexBinding) {
+        self.bindings.push(binding);
+    }
+
+    // Check that a draw binds one buffer per binding, since decode can't report an error
+    pub fn check_buffer_count(&self, count: usize) -> Result<(), &'static str> {
+        if count != self.bindings.len() {
+            return Err("Number of vertex and instance buffers does not match the vertex bindings.");
+        }
+        return Ok(());
+    }
+}
+
+unsafe impl<I> VertexDefinition<I> for VertexInputDesc
+    where I: ShaderInterfaceDef
+{
+    type BuffersIter = VecIntoIter<(u32, usize, InputRate)>;
+    type AttribsIter = VecIntoIter<(u32, u32, AttributeInfo)>;
+
+    fn definition(&self, interface: &I) -> Result<(Self::BuffersIter, Self::AttribsIter), IncompatibleVertexDefinitionError> {
+        let mut attribs = Vec::with_capacity(interface.elements().len());
+        for element in interface.elements() {
+            let name = element.name.as_ref().unwrap();
+
+            let (info, binding_index) = self.bindings.iter()
+                .enumerate()
+                .find_map(|(i, binding)| (binding.member)(name).map(|info| (info, i as u32)))
+                .ok_or_else(|| IncompatibleVertexDefinitionError::MissingAttribute {
+                    attribute: name.clone().into_owned()
+                })?;
+
+            let num_locations = element.location.end - element.location.start;
+            if !info.ty.matches(info.array_size, element.format, num_locations) {
+                return Err(IncompatibleVertexDefinitionError::FormatMismatch {
+                    attribute: name.clone().into_owned(),
+                    shader: (element.format, num_locations as usize),
+                    definition: (info.ty, info.array_size),
+                });
+            }
+
+            let mut offset = info.offset;
+            for location in element.location.clone() {
+                attribs.push((
+                    location,
+                    binding_index,
+                    AttributeInfo {
+                        offset,
+                        format: element.format
+                    }
+                ));
+                offset += element.format.size().unwrap();
+            }
+        }
+
+        let buffers: Vec<(u32, usize, InputRate)> = self.bindings.iter()
+            .enumerate()
+            .map(|(i, binding)| (i as u32, binding.stride, binding.input_rate))
+            .collect();
+
+        Ok((buffers.into_iter(), attribs.into_iter()))
+    }
+}
+
+unsafe impl VertexSource<Vec<Arc<dyn BufferAccess + Send + Sync>>> for VertexInputDesc {
+    fn decode(&self, source: Vec<Arc<dyn BufferAccess + Send + Sync>>) -> (Vec<Box<dyn BufferAccess + Send + Sync>>, usize, usize) {
+        assert_eq!(source.len(), self.bindings.len());
+
+        // Counts come from the first buffer of each input rate
+        let mut vertex_count = None;
+        let mut instance_count = None;
+        for (buffer, binding) in source.iter().zip(self.bindings.iter()) {
+            let count = buffer.size() / binding.stride;
+            match binding.input_rate {
+                InputRate::Vertex => { vertex_count.get_or_insert(count); },
+                InputRate::Instance => { instance_count.get_or_insert(count); }
+            }
+        }
+
+        return (
+            source.into_iter().map(|x| Box::new(x) as Box<_>).collect(),
+            vertex_count.unwrap_or(1),
+            instance_count.unwrap_or(1)
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use std::sync::Arc;
+
+use image::ImageBuffer;
+use image::RgbaImage;
+
+use vulkano::buffer::BufferAccess;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::Device;
+use vulkano::device::DeviceExtensions;
+use vulkano::device::Queue;
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::image::Dimensions;
+use vulkano::image::ImageUsage;
+use vulkano::image::StorageImage;
+use vulkano::pipeline::ComputePipeline;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use crate::render_config;
+use crate::rendering::Renderer;
+use crate::rendering::device_selection::DeviceRequirements;
+use crate::rendering::device_selection::FormatRequirement;
+use crate::rendering::headless;
+use crate::rendering::pass_executor::PassContext;
+use crate::rendering::pipeline_cache::PipelineCache;
+
+// Render graph of the triangle scene. Scenes are drawn by the binaries and checked against
+// goldens by tests/golden.rs.
+
+render_config!(
+    name: test_renderer,
+    attachments: {
+        depth: {
+            format: Format::D24Unorm_S8Uint
+        },
+        albedo: {
+            format: Format::R8G8B8A8Unorm
+        },
+        normal: {
+            format: Format::R8G8Unorm
+        },
+        color: {
+            format: Format::R8G8B8A8Unorm
+        },
+        blur: {
+            format: Format::R8G8B8A8Unorm
+        },
+        blur2: {
+            format: Format::R8G8B8A8Unorm
+        },
+        velocity: {
+            format: Format::R8G8B8A8Unorm
+        },
+        motion_blur: {
+            format: Format::R8G8B8A8Unorm
+        }
+    },
+    default_vertex_bindings: [
+        {
+            vertex_type_name: Vertex,
+            input_rate: 0,
+            attributes: {
+                position: [f32; 2],
+                color: [f32; 3]
+            }
+        },
+        {
+            vertex_type_name: InstanceData,
+            input_rate: 1,
+            attributes: {
+                position_offset: [f32; 2],
+                scale: f32
+            }
+        }
+    ],
+    graphics_passes: {
+        gbuffer: {
+            color_outputs: [albedo, normal],
+            depth_stencil_output: {depth},
+            input_attachments: [],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                state: {
+                    cull_mode: CullMode::Back,
+                    depth_stencil: DepthStencil::simple_depth_test()
+                }
+            }
+        },
+        lighting: {
+            color_outputs: [color],
+            depth_stencil_output: {},
+            input_attachments: [albedo, normal],
+            depth_stencil_input: {depth},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: gbuffer,
+                state: {
+                    depth_stencil: DepthStencil {
+                        depth_compare: Compare::LessOrEqual,
+                        depth_write: false,
+                        ..DepthStencil::disabled()
+                    }
+                }
+            }
+        },
+        blur_pass: {
+            color_outputs: [blur],
+            depth_stencil_output: {},
+            input_attachments: [color],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: gbuffer
+            }
+        },
+        blur_pass2: {
+            color_outputs: [blur2],
+            depth_stencil_output: {},
+            input_attachments: [blur],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag" { intensity: 0.5 }
+                },
+                defines: [("GRAYSCALE", "1")]
+            }
+        },
+        composite_pass: {
+            color_outputs: [backbuffer],
+            depth_stencil_output: {},
+            input_attachments: [color, blur, blur2, motion_blur],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: gbuffer,
+                state: {
+                    blend: vec![AttachmentBlend::alpha_blending()]
+                }
+            }
+        },
+        velocity_pass: {
+            color_outputs: [velocity],
+            depth_stencil_output: {},
+            input_attachments: [],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: gbuffer
+            }
+        },
+        motion_blur_pass: {
+            color_outputs: [motion_blur],
+            depth_stencil_output: {},
+            input_attachments: [velocity, color, motion_blur@prev],
+            depth_stencil_input: {},
+            pipeline: {
+                shader_paths: {
+                    vertex: "src/shaders/passthrough_2d.vert",
+                    fragment: "src/shaders/passthrough.frag"
+                },
+                shaders_from: gbuffer
+            }
+        }
+    }
+);
+
+#[derive(Default, Copy, Clone)]
+struct Vertex {
+    position: [f32; 2],
+    color: [f32; 3]
+}
+
+vulkano::impl_vertex!(Vertex, position, color);
+
+#[derive(Default, Copy, Clone)]
+struct InstanceData {
+    position_offset: [f32; 2],
+    scale: f32
+}
+
+vulkano::impl_vertex!(InstanceData, position_offset, scale);
+
+mod mandelbrot_cs {
+    vulkano_shaders::shader!{
+        ty: "compute",
+        path: "compute_testing/shaders/mandelbrot.comp"
+    }
+}
+
+// Format of the image the mandelbrot compute shader writes
+pub const MANDELBROT_FORMAT: Format = Format::R8G8B8A8Unorm;
+
+// What a device needs to draw every scene: the test renderer's graph, and the storage image and
+// buffer extension of the mandelbrot
+pub fn device_requirements() -> DeviceRequirements {
+    let mut requirements = test_renderer::builder().unwrap().device_requirements();
+    requirements.extensions = DeviceExtensions {
+        khr_storage_buffer_storage_class: true,
+        ..DeviceExtensions::none()
+    };
+    requirements.formats.push(FormatRequirement {
+        name: "mandelbrot",
+        candidates: vec![MANDELBROT_FORMAT],
+        usage: ImageUsage {
+            storage: true,
+            transfer_source: true,
+            ..ImageUsage::none()
+        },
+        samples: 1
+    });
+    return requirements;
+}
+
+// The test renderer drawing instanced triangles into an offscreen backbuffer
+pub fn triangle(device: Arc<Device>) -> Renderer {
+    return triangle_with_format(device, headless::OFFSCREEN_FORMAT);
+}
+
+// The triangle scene for a backbuffer of `backbuffer_format`, e.g. a swapchain's
+pub fn triangle_with_format(device: Arc<Device>, backbuffer_format: Format) -> Renderer {
+    let mut builder = test_renderer::builder().unwrap();
+    builder.set_backbuffer_format(backbuffer_format);
+    let mut renderer = builder.build(device.clone(), &mut PipelineCache::new()).unwrap();
+
+    let vertex_buffer = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::all(),
+        false,
+        vec![
+            Vertex {
+                position: [-0.5, -0.5],
+                color: [1.0, 1.0, 0.0]
+            },
+            Vertex {
+                position: [0.0, 0.5],
+                color: [1.0, 0.0, 1.0]
+            },
+            Vertex {
+                position: [0.5, -0.25],
+                color: [0.0, 1.0, 1.0]
+            }
+        ].into_iter()
+    ).unwrap();
+
+    let instance_buffer= CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::all(),
+        false,
+        vec![
+            InstanceData {
+                position_offset: [-0.5, -0.5],
+                scale: 1.0 
+            },
+            InstanceData {
+                position_offset: [0.0, 0.5],
+                scale: 0.5
+            },
+            InstanceData {
+                position_offset: [0.5, -0.25],
+                scale: 0.75
+            }
+        ].into_iter()
+    ).unwrap();
+
+    // The composite pass draws the triangles into the backbuffer
+    renderer.set_pass_executor("composite_pass", move |context: &mut PassContext| {
+        let pipeline = context.pipeline().clone();
+        let dynamic_state = context.dynamic_state().clone();
+        context.cmd_buf_builder()
+            .draw(
+                pipeline,
+                &dynamic_state,
+                vec![
+                    vertex_buffer.clone() as Arc<dyn BufferAccess + Send + Sync>,
+                    instance_buffer.clone() as Arc<dyn BufferAccess + Send + Sync>
+                ],
+                (),
+                ()
+            )
+            .map_err(|_| "Failed to draw triangles")?;
+        return Ok(());
+    }).unwrap();
+
+    return renderer;
+}
+
+
+// Dispatch the mandelbrot compute shader over a storage image of `dimensions`, cleared to blue
+// first, and read the image back
+pub fn mandelbrot(device: Arc<Device>, queue: Arc<Queue>, dimensions: [u32; 2]) -> Result<RgbaImage, &'static str> {
+    let image = StorageImage::new(
+        device.clone(),
+        Dimensions::Dim2d {
+            width: dimensions[0],
+            height: dimensions[1]
+        },
+        MANDELBROT_FORMAT,
+        Some(queue.family())
+    ).map_err(|_| "Failed to create mandelbrot image")?;
+
+    let image_buffer = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::all(),
+        false,
+        (0 .. dimensions[0] * dimensions[1] * 4).map(|_| 0u8)
+    ).map_err(|_| "Failed to create image buffer")?;
+
+    let shader = mandelbrot_cs::Shader::load(device.clone()).map_err(|_| "Failed to load mandelbrot shader")?;
+    let pipeline = Arc::new(
+        ComputePipeline::new(
+            device.clone(),
+            &shader.main_entry_point(),
+            &()
+        ).map_err(|_| "Failed to create mandelbrot compute pipeline")?
+    );
+
+    let layout = pipeline.layout().descriptor_set_layout(0).unwrap();
+    let set = Arc::new(
+        PersistentDescriptorSet::start(layout.clone())
+            .add_image(image.clone()).map_err(|_| "Failed to bind mandelbrot image")?
+            .build().map_err(|_| "Failed to create mandelbrot descriptor set")?
+    );
+
+    // The shader runs in 8x8 work groups
+    let mut cmd_buf_builder = AutoCommandBufferBuilder::new(
+        device.clone(),
+        queue.family()
+    ).map_err(|_| "Failed to create command buffer")?;
+    cmd_buf_builder
+        .clear_color_image(
+            image.clone(),
+            ClearValue::Float(
+                [0.0, 0.0, 1.0, 1.0]
+            )
+        ).map_err(|_| "Failed to clear mandelbrot image")?
+        .dispatch(
+            [(dimensions[0] + 7) / 8, (dimensions[1] + 7) / 8, 1],
+            pipeline.clone(),
+            set.clone(),
+            ()
+        ).map_err(|_| "Failed to dispatch mandelbrot")?
+        .copy_image_to_buffer(
+            image.clone(),
+            image_buffer.clone()
+        ).map_err(|_| "Failed to copy mandelbrot image")?;
+    let command_buffer = cmd_buf_builder.build().map_err(|_| "Failed to build command buffer")?;
+
+    sync::now(device.clone())
+        .then_execute(queue.clone(), command_buffer)
+        .map_err(|_| "Failed to execute mandelbrot")?
+        .then_signal_fence_and_flush()
+        .map_err(|_| "Failed to flush mandelbrot")?
+        .wait(None)
+        .map_err(|_| "Failed to wait for mandelbrot")?;
+
+    let content = image_buffer.read().map_err(|_| "Failed to read image buffer")?;
+    return ImageBuffer::from_raw(dimensions[0], dimensions[1], content.to_vec())
+        .ok_or("Image buffer doesn't match the mandelbrot's dimensions");
+}
@@ -1,9 +1,16 @@
 #version 450
 
+layout(constant_id = 0) const float intensity = 1.0;
+
 layout(location = 0) out vec4 f_color;
 
 layout(location = 0) in vec3 in_color;
 
 void main() {
-    f_color = vec4(in_color, 1.0);
+#ifdef GRAYSCALE
+    float luminance = dot(in_color, vec3(0.2126, 0.7152, 0.0722));
+    f_color = vec4(vec3(luminance) * intensity, 1.0);
+#else
+    f_color = vec4(in_color * intensity, 1.0);
+#endif
 }
@@ -0,0 +1,120 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use image::RgbaImage;
+
+use vulkano::device::Device;
+use vulkano::device::Queue;
+use vulkano::instance::Instance;
+use vulkano::instance::InstanceExtensions;
+use vulkano::sync;
+use vulkano::sync::GpuFuture;
+
+use sekirbo::golden;
+use sekirbo::rendering;
+use sekirbo::rendering::Renderer;
+use sekirbo::rendering::device_selection;
+use sekirbo::rendering::headless;
+use sekirbo::scenes;
+
+// Set to 1 to replace the goldens with what renders now instead of checking against them
+const BLESS_VAR: &str = "SEKIRBO_BLESS";
+
+// Frames rendered before the golden frame, so history attachments hold earlier frames
+const FRAME_COUNT: usize = 3;
+
+const DIMENSIONS: [u32; 2] = [1024, 1024];
+
+fn golden_dir() -> PathBuf {
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+}
+
+fn output_dir() -> PathBuf {
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden_output");
+}
+
+// A device for the scenes, picked like the binaries do. None when there is no vulkan
+// implementation to render with, in which case the golden tests are skipped.
+fn device() -> Option<(Arc<Device>, Arc<Queue>)> {
+    let instance = match Instance::new(None, &InstanceExtensions::none(), None) {
+        Ok(instance) => instance,
+        Err(error) => {
+            eprintln!("skipping golden test, no vulkan instance: {:?}", error);
+            return None;
+        }
+    };
+
+    let requirements = scenes::device_requirements();
+    let (physical, queue_family) = match device_selection::select_device(
+        &instance,
+        &requirements,
+        device_selection::device_choice().as_ref(),
+        |q| q.supports_graphics() && q.supports_compute()
+    ) {
+        Ok(x) => x,
+        Err(error) => {
+            eprintln!("skipping golden test: {}", error);
+            return None;
+        }
+    };
+
+    let (device, mut queues) = Device::new(
+        physical,
+        &requirements.features,
+        &requirements.extensions,
+        [(queue_family, 0.5)].iter().cloned()
+    ).expect("Failed to create device");
+    return Some((device, queues.next().unwrap()));
+}
+
+// Render FRAME_COUNT frames offscreen and read back the last one
+fn last_frame(renderer: &mut Renderer, device: Arc<Device>, queue: Arc<Queue>) -> RgbaImage {
+    let backbuffer = headless::offscreen_backbuffer(device.clone(), DIMENSIONS).unwrap();
+
+    let mut readback = None;
+    for i in 0..FRAME_COUNT {
+        if i == FRAME_COUNT - 1 {
+            readback = Some(renderer.read_attachment(rendering::BACKBUFFER_NAME).unwrap());
+        }
+        renderer.execute_frame(queue.clone(), backbuffer.clone(), sync::now(device.clone()).boxed()).unwrap();
+    }
+    return readback.unwrap().wait().unwrap();
+}
+
+fn check(name: &str, image: &RgbaImage) {
+    let bless = env::var(BLESS_VAR).map_or(false, |x| x == "1");
+    let result = golden::check(name, image, golden_dir(), output_dir(), &golden::Tolerance::default(), bless);
+    match result {
+        Ok(golden::GoldenResult::Passed(_)) => (),
+        Ok(golden::GoldenResult::Blessed) => eprintln!("{}: blessed", name),
+        Ok(golden::GoldenResult::Missing) => {
+            panic!("{}: {} is missing, run with {}=1 to bless it", name, golden_dir().join(format!("{}.png", name)).display(), BLESS_VAR);
+        },
+        Ok(golden::GoldenResult::Failed(stats)) => {
+            panic!("{}: differs from its golden {:?}, see {}", name, stats, output_dir().display());
+        },
+        Err(error) => panic!("{}: {}, run with {}=1 to bless", name, error, BLESS_VAR)
+    }
+}
+
+#[test]
+fn triangle_matches_golden() {
+    let (device, queue) = match device() {
+        Some(x) => x,
+        None => return
+    };
+    let mut renderer = scenes::triangle(device.clone());
+    let image = last_frame(&mut renderer, device, queue);
+    check("triangle", &image);
+}
+
+#[test]
+fn mandelbrot_matches_golden() {
+    let (device, queue) = match device() {
+        Some(x) => x,
+        None => return
+    };
+    let image = scenes::mandelbrot(device, queue, DIMENSIONS).unwrap();
+    check("mandelbrot", &image);
+}